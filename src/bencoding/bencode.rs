@@ -3,7 +3,7 @@ use std::{str, fmt};
 use crate::bencoding::byte_string::ByteString;
 use crate::bencoding::error::Error;
 
-#[derive(PartialEq, Eq, Debug)]
+#[derive(PartialEq, Eq, Clone, Debug)]
 pub enum Bencode {
     Empty,
     Number(i64),
@@ -24,7 +24,7 @@ impl Bencode {
 
         match dict.remove(&ByteString::from_str(key)) {
             Some(value) => Ok(value),
-            None => return Err(Error::new(format!("\"{}\" key is not present in torrent file.", key))),
+            None => Err(Error::new(format!("\"{}\" key is not present in torrent file.", key))),
         }
     }
 
@@ -67,7 +67,7 @@ impl Bencode {
             _ => return Err(Error::new(format!("\"{}\" value is not a ByteString.", key))),
         };
 
-        let bytes: &[u8] = &byte_string;
+        let bytes: &[u8] = byte_string;
 
         match str::from_utf8(bytes) {
             Ok(utf8) => Ok(utf8.to_string()),
@@ -75,6 +75,67 @@ impl Bencode {
         }
     }
 
+    pub fn get_bytes(&self, key: &str) -> Result<&[u8], Error> {
+        let dict = match self {
+            Bencode::Dict(d) => d,
+            _ => return Err(Error::new("Bencode is not a dict.".to_string())),
+        };
+
+        match dict.get(&ByteString::from_str(key)) {
+            Some(Bencode::ByteString(s)) => Ok(s),
+            Some(_) => Err(Error::new(format!("\"{}\" value is not a ByteString.", key))),
+            None => Err(Error::new(format!("\"{}\" key is not present in torrent file.", key))),
+        }
+    }
+
+    pub fn contains_key(&self, key: &str) -> bool {
+        match self {
+            Bencode::Dict(d) => d.contains_key(&ByteString::from_str(key)),
+            _ => false,
+        }
+    }
+
+    pub fn get_list(&self, key: &str) -> Result<&ListVec, Error> {
+        let dict = match self {
+            Bencode::Dict(d) => d,
+            _ => return Err(Error::new("Bencode is not a dict.".to_string())),
+        };
+
+        match dict.get(&ByteString::from_str(key)) {
+            Some(Bencode::List(l)) => Ok(l),
+            Some(_) => Err(Error::new(format!("\"{}\" value is not a List.", key))),
+            None => Err(Error::new(format!("\"{}\" key is not present in torrent file.", key))),
+        }
+    }
+
+    pub fn get_optional_string(&self, key: &str) -> Result<Option<String>, Error> {
+        if !self.contains_key(key) {
+            return Ok(None);
+        }
+        self.get_string(key).map(Some)
+    }
+
+    pub fn get_optional_number(&self, key: &str) -> Result<Option<i64>, Error> {
+        if !self.contains_key(key) {
+            return Ok(None);
+        }
+        self.get_number(key).map(Some)
+    }
+
+    /// Clones every entry of the dict whose key is not one of `known`, so that
+    /// extensions we don't model survive a decode/encode round trip.
+    pub fn unknown_entries(&self, known: &[&str]) -> DictMap {
+        let dict = match self {
+            Bencode::Dict(d) => d,
+            _ => return DictMap::new(),
+        };
+
+        dict.iter()
+            .filter(|(key, _)| !known.iter().any(|k| **key == ByteString::from_str(k)))
+            .map(|(key, value)| (key.clone(), value.clone()))
+            .collect()
+    }
+
     pub fn get_number(&self, key: &str) -> Result<i64, Error> {
         let dict = match self {
             Bencode::Dict(d) => d,
@@ -0,0 +1,148 @@
+use std::collections::BTreeMap;
+use std::{str, fmt};
+use crate::bencoding::bencode::{Bencode, ListVec, DictMap};
+use crate::bencoding::byte_string::ByteString;
+
+/// A `Bencode` value whose byte strings and dict keys borrow from the buffer it
+/// was decoded from.
+#[derive(PartialEq, Eq, Clone, Debug)]
+pub enum BencodeRef<'a> {
+    Empty,
+    Number(i64),
+    ByteString(&'a [u8]),
+    List(ListRefVec<'a>),
+    Dict(DictRefMap<'a>),
+}
+
+pub type ListRefVec<'a> = Vec<BencodeRef<'a>>;
+pub type DictRefMap<'a> = BTreeMap<&'a [u8], BencodeRef<'a>>;
+
+impl<'a> BencodeRef<'a> {
+    pub fn get(&self, key: &str) -> Option<&BencodeRef<'a>> {
+        match self {
+            BencodeRef::Dict(d) => d.get(key.as_bytes()),
+            _ => None,
+        }
+    }
+
+    pub fn as_bytes(&self) -> Option<&'a [u8]> {
+        match *self {
+            BencodeRef::ByteString(s) => Some(s),
+            _ => None,
+        }
+    }
+
+    pub fn as_number(&self) -> Option<i64> {
+        match *self {
+            BencodeRef::Number(n) => Some(n),
+            _ => None,
+        }
+    }
+
+    pub fn to_bencode(&self) -> Bencode {
+        match self {
+            BencodeRef::Empty => Bencode::Empty,
+            BencodeRef::Number(n) => Bencode::Number(*n),
+            BencodeRef::ByteString(s) => Bencode::ByteString(s.to_vec()),
+            BencodeRef::List(l) => Bencode::List(l.iter().map(|v| v.to_bencode()).collect::<ListVec>()),
+            BencodeRef::Dict(d) => Bencode::Dict(
+                d.iter()
+                    .map(|(k, v)| (ByteString::from_vec(k.to_vec()), v.to_bencode()))
+                    .collect::<DictMap>()
+            ),
+        }
+    }
+}
+
+impl<'a> From<BencodeRef<'a>> for Bencode {
+    fn from(value: BencodeRef<'a>) -> Self {
+        value.to_bencode()
+    }
+}
+
+impl<'a> fmt::Display for BencodeRef<'a> {
+    fn fmt(&self, fmt: &mut fmt::Formatter) -> fmt::Result {
+        format(fmt, self)
+    }
+}
+
+fn format(fmt: &mut fmt::Formatter, v: &BencodeRef) -> fmt::Result {
+    match *v {
+        BencodeRef::Empty => { Ok(()) }
+        BencodeRef::Number(v) => write!(fmt, "{}", v),
+        BencodeRef::ByteString(v) => fmt_bytestring(v, fmt),
+        BencodeRef::List(ref v) => {
+            write!(fmt, "[")?;
+            let mut first = true;
+            for value in v.iter() {
+                if first {
+                    first = false;
+                } else {
+                    write!(fmt, ", ")?;
+                }
+                write!(fmt, "{}", *value)?;
+            }
+            write!(fmt, "]")
+        }
+        BencodeRef::Dict(ref v) => {
+            write!(fmt, "{{")?;
+            let mut first = true;
+            for (key, value) in v.iter() {
+                if first {
+                    first = false;
+                } else {
+                    write!(fmt, ", ")?;
+                }
+                fmt_bytestring(key, fmt)?;
+                write!(fmt, ": {}", *value)?;
+            }
+            write!(fmt, "}}")
+        }
+    }
+}
+
+#[inline]
+fn fmt_bytestring(s: &[u8], fmt: &mut fmt::Formatter) -> fmt::Result {
+  match str::from_utf8(s) {
+    Ok(utf8_str) => write!(fmt, "s\"{}\"", utf8_str),
+    Err(..) => write!(fmt, "s{:?}", s),
+  }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_to_bencode() {
+        let mut d = DictRefMap::new();
+        d.insert(b"spam", BencodeRef::List(vec![BencodeRef::Number(3), BencodeRef::ByteString(b"eggs")]));
+
+        let mut expected = DictMap::new();
+        expected.insert(
+            ByteString::from_str("spam"),
+            Bencode::List(vec![Bencode::Number(3), Bencode::ByteString(b"eggs".to_vec())]),
+        );
+
+        assert_eq!(Bencode::Dict(expected), BencodeRef::Dict(d).to_bencode());
+    }
+
+    #[test]
+    fn test_get() {
+        let mut d = DictRefMap::new();
+        d.insert(b"spam", BencodeRef::Number(3));
+        let dict = BencodeRef::Dict(d);
+
+        assert_eq!(Some(3), dict.get("spam").and_then(|v| v.as_number()));
+        assert_eq!(None, dict.get("eggs"));
+        assert_eq!(None, BencodeRef::Number(3).get("spam"));
+    }
+
+    #[test]
+    fn test_display() {
+        let mut d = DictRefMap::new();
+        d.insert(b"spam", BencodeRef::List(vec![BencodeRef::Number(3), BencodeRef::ByteString(b"\xc3\x28")]));
+
+        assert_eq!("{s\"spam\": [3, s[195, 40]]}", format!("{}", BencodeRef::Dict(d)));
+    }
+}
@@ -4,6 +4,7 @@ use std::{str, fmt};
 pub struct ByteString(Vec<u8>);
 
 impl ByteString {
+    #[allow(clippy::should_implement_trait)]
     pub fn from_str(s: &str) -> ByteString {
         ByteString(s.as_bytes().to_vec())
     }
@@ -20,9 +21,8 @@ impl ByteString {
 
 impl fmt::Display for ByteString {
     fn fmt(&self, fmt: &mut fmt::Formatter) -> fmt::Result {
-        match self {
-            &ByteString(ref v) => fmt_bytestring(&v[..], fmt),
-        }
+        let ByteString(v) = self;
+        fmt_bytestring(&v[..], fmt)
     }
 }
 
@@ -1,136 +1,211 @@
-use std::{str, cmp};
+use std::ops::Range;
 
-use crate::bencoding::bencode::{Bencode, ListVec, DictMap};
-use crate::bencoding::byte_string;
+use crate::bencoding::bencode::Bencode;
+use crate::bencoding::bencode_ref::{BencodeRef, ListRefVec, DictRefMap};
+use crate::bencoding::error::{Error, ErrorKind};
+use crate::bencoding::mode::Mode;
 
-pub fn decode(data: Vec<u8>) -> Bencode {
-    decode_internal(data, 0).0
+type Decoded<'a> = Result<(BencodeRef<'a>, usize), Error>;
+
+/// How many lists and dictionaries may be nested inside each other. Input is
+/// untrusted and each level recurses, so without a limit it could overflow
+/// the stack.
+pub const MAX_DEPTH: usize = 256;
+
+pub fn decode(data: Vec<u8>) -> Result<Bencode, Error> {
+    decode_with_mode(data, Mode::Lenient)
 }
 
-fn decode_internal(data: Vec<u8>, index: usize) -> (Bencode, usize) {
-    let numbers = [b'0', b'1', b'2', b'3', b'4', b'5', b'6', b'7', b'8', b'9'];
-    let code = match data.get(index) {
-        Some(&r) => r,
-        None => return (Bencode::Empty, 0),
-    };
+pub fn decode_with_mode(data: Vec<u8>, mode: Mode) -> Result<Bencode, Error> {
+    Ok(decode_ref_with_mode(&data, mode)?.to_bencode())
+}
+
+/// Decodes without copying: every byte string and dict key in the result is a
+/// slice of `data`.
+pub fn decode_ref(data: &[u8]) -> Result<BencodeRef<'_>, Error> {
+    decode_ref_with_mode(data, Mode::Lenient)
+}
 
-    if numbers.contains(&code) {
-        decode_str(data, index)
-    } else if code == b'i' {
-        decode_int(data, index)
-    } else if code == b'l' {
-        decode_list(data, index)
-    } else if code == b'd' {
-        decode_dictionary(data, index)
-    } else {
-        (Bencode::Empty, 0)
+pub fn decode_ref_with_mode(data: &[u8], mode: Mode) -> Result<BencodeRef<'_>, Error> {
+    let (value, end) = decode_internal(data, 0, mode, 0)?;
+
+    if end != data.len() {
+        return Err(Error::at(ErrorKind::TrailingData, end));
     }
+
+    Ok(value)
 }
 
-fn decode_str(data: Vec<u8>, index: usize) -> (Bencode, usize) {
-    let slice = data.get(index..).unwrap();
-    let i = match slice.iter().position(|&r| r == b':') {
-        Some(val) => val,
-        None => return (Bencode::Empty, 0),
-    };
+/// Whether `data` is a single value in canonical form, i.e. re-encoding it
+/// would produce the exact same bytes.
+pub fn is_canonical(data: &[u8]) -> bool {
+    decode_ref_with_mode(data, Mode::Strict).is_ok()
+}
 
-    let length_bytes = slice.get(..i).unwrap();
-    let length_str = str::from_utf8(&length_bytes).unwrap();
-    let length = match length_str.parse::<usize>() {
-        Ok(val) => val,
-        Err(_) => return (Bencode::Empty, 0),
-    };
-    let length = cmp::min(length, slice.len() - i - 1);
+/// Finds the exact bytes of the value stored under `key` in the top level dict of
+/// `data`, e.g. the `info` dict of a torrent file, whose hash must be computed
+/// over the original encoding. If `key` repeats, the last one wins, as it does
+/// when the dict is decoded.
+pub fn value_span(data: &[u8], key: &str) -> Result<Option<Range<usize>>, Error> {
+    match data.first() {
+        Some(b'd') => {},
+        Some(_) => return Err(Error::at(ErrorKind::InvalidType, 0)),
+        None => return Err(Error::at(ErrorKind::UnexpectedEof, 0)),
+    }
 
-    if length == 0 {
-        return (Bencode::ByteString(Vec::<u8>::new()), index+i+1)
-    };
+    let mut i = 1;
+    let mut span = None;
 
-    let s = match slice.get((i+1)..(i+1+length)) {
-        Some(val) => val,
-        None => return (Bencode::Empty, 0),
-    };
+    loop {
+        match data.get(i) {
+            Some(b'e') => return Ok(span),
+            Some(b'0'..=b'9') => {},
+            Some(_) => return Err(Error::at(ErrorKind::InvalidKey, i)),
+            None => return Err(Error::at(ErrorKind::UnexpectedEof, i)),
+        }
 
-    (Bencode::ByteString(s.to_vec()), index+i+1+length)
-}
+        let (current_key, start) = read_bytes(data, i, Mode::Lenient)?;
+        let (_, end) = decode_internal(data, start, Mode::Lenient, 1)?;
 
-fn decode_int(data: Vec<u8>, index: usize) -> (Bencode, usize) {
-    let slice = data.get(index..).unwrap();
-    let i = match slice.iter().position(|&r| r == b'e') {
-        Some(val) => val,
-        None => return (Bencode::Empty, 0),
-    };
+        if current_key == key.as_bytes() {
+            span = Some(start..end);
+        }
+
+        i = end;
+    }
+}
 
-    let number_bytes = slice.get(1..i).unwrap();
-    let number_str = str::from_utf8(&number_bytes).unwrap();
-    let number = match number_str.parse::<i64>() {
-        Ok(val) => val,
-        Err(_) => return (Bencode::Empty, 0),
+/// `depth` is how many lists and dictionaries enclose the value at `index`.
+fn decode_internal(data: &[u8], index: usize, mode: Mode, depth: usize) -> Decoded<'_> {
+    let code = match data.get(index) {
+        Some(&r) => r,
+        None => return Err(Error::at(ErrorKind::UnexpectedEof, index)),
     };
 
-    (Bencode::Number(number), index + i + 1)
+    match code {
+        b'0'..=b'9' => decode_str(data, index, mode),
+        b'i' => decode_int(data, index, mode),
+        b'l' | b'd' if depth >= MAX_DEPTH => Err(Error::at(ErrorKind::TooDeep, index)),
+        b'l' => decode_list(data, index, mode, depth),
+        b'd' => decode_dictionary(data, index, mode, depth),
+        _ => Err(Error::at(ErrorKind::InvalidType, index)),
+    }
 }
 
-fn decode_list(data: Vec<u8>, index: usize) -> (Bencode, usize) {
-    let slice = data.get(index..).unwrap();
-    let mut list = ListVec::new();
-    let mut i = 1;
-    let mut item : Bencode;
+/// Finds the index of `delimiter` at or after `index`, the bytes before it must
+/// all be ascii digits (optionally preceded by a `-` when `signed`).
+fn find_number_end(data: &[u8], index: usize, delimiter: u8, signed: bool, kind: ErrorKind) -> Result<usize, Error> {
+    let mut i = index;
+    if signed && data.get(i) == Some(&b'-') {
+        i += 1;
+    }
+    let digits = i;
 
     loop {
-        match slice.get(i) {
-            Some(b'e') => break,
-            Some(_) => {},
-            None => return (Bencode::List(list), index + i),
+        match data.get(i) {
+            Some(&b) if b == delimiter && i > digits => return Ok(i),
+            Some(b'0'..=b'9') => i += 1,
+            Some(_) => return Err(Error::at(kind, i)),
+            None => return Err(Error::at(ErrorKind::UnexpectedEof, i)),
         }
+    }
+}
 
-        let result = decode_internal(slice.to_vec(), i);
+fn parse_number<T: std::str::FromStr>(data: &[u8], index: usize, kind: ErrorKind) -> Result<T, Error> {
+    // Only ascii digits and `-` make it this far, so this is always valid utf-8.
+    let number_str = std::str::from_utf8(data).map_err(|_| Error::at(kind, index))?;
+    number_str.parse::<T>().map_err(|_| Error::at(kind, index))
+}
 
-        item = result.0;
-        i = result.1;
-        list.push(item);
+fn decode_str(data: &[u8], index: usize, mode: Mode) -> Decoded<'_> {
+    let (bytes, end) = read_bytes(data, index, mode)?;
+    Ok((BencodeRef::ByteString(bytes), end))
+}
+
+fn read_bytes(data: &[u8], index: usize, mode: Mode) -> Result<(&[u8], usize), Error> {
+    let colon = find_number_end(data, index, b':', false, ErrorKind::InvalidLength)?;
+    mode.check_number(&data[index..colon], index)?;
+    let length = parse_number::<usize>(&data[index..colon], index, ErrorKind::InvalidLength)?;
+    let start = colon + 1;
+
+    if length > data.len() - start {
+        return Err(Error::at(ErrorKind::UnexpectedEof, data.len()));
     }
 
-    (Bencode::List(list), index + i)
+    Ok((&data[start..start + length], start + length))
 }
 
-fn decode_dictionary(data: Vec<u8>, index: usize) -> (Bencode, usize) {
-    let slice = data.get(index..).unwrap();
-    let mut dict = DictMap::new();
-    let mut i = 1;
-    let mut key : byte_string::ByteString;
-    let mut value : Bencode;
+fn decode_int(data: &[u8], index: usize, mode: Mode) -> Decoded<'_> {
+    let end = find_number_end(data, index + 1, b'e', true, ErrorKind::InvalidInteger)?;
+    mode.check_number(&data[index + 1..end], index + 1)?;
+    let number = parse_number::<i64>(&data[index + 1..end], index + 1, ErrorKind::InvalidInteger)?;
+
+    Ok((BencodeRef::Number(number), end + 1))
+}
+
+fn decode_list(data: &[u8], index: usize, mode: Mode, depth: usize) -> Decoded<'_> {
+    let mut list = ListRefVec::new();
+    let mut i = index + 1;
 
     loop {
-        match slice.get(i) {
+        match data.get(i) {
             Some(b'e') => break,
             Some(_) => {},
-            None => return (Bencode::Dict(dict), index + i),
+            None => return Err(Error::at(ErrorKind::UnexpectedEof, i)),
         }
 
-        let result = decode_internal(slice.to_vec(), i);
+        let (item, next) = decode_internal(data, i, mode, depth + 1)?;
+        i = next;
+        list.push(item);
+    }
+
+    Ok((BencodeRef::List(list), i + 1))
+}
 
-        i = result.1;
+fn decode_dictionary(data: &[u8], index: usize, mode: Mode, depth: usize) -> Decoded<'_> {
+    let mut dict = DictRefMap::new();
+    let mut i = index + 1;
+    let mut previous_key = None;
 
-        key = match result.0 {
-            Bencode::ByteString(v) => byte_string::ByteString::from_vec(v),
-            _ => return (Bencode::Dict(dict), index + i),
-        };
+    loop {
+        match data.get(i) {
+            Some(b'e') => break,
+            Some(b'0'..=b'9') => {},
+            Some(_) => return Err(Error::at(ErrorKind::InvalidKey, i)),
+            None => return Err(Error::at(ErrorKind::UnexpectedEof, i)),
+        }
 
-        let result = decode_internal(slice.to_vec(), i); 
+        let (key, next) = read_bytes(data, i, mode)?;
+        mode.check_key_order(previous_key, key, i)?;
+        previous_key = Some(key);
+        i = next;
 
-        value = result.0;
-        i = result.1;
+        let (value, next) = decode_internal(data, i, mode, depth + 1)?;
+        i = next;
 
         dict.insert(key, value);
     }
 
-    (Bencode::Dict(dict), index + i)
+    Ok((BencodeRef::Dict(dict), i + 1))
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::bencoding::bencode::{ListVec, DictMap};
+    use crate::bencoding::byte_string;
+
+    fn decode(data: Vec<u8>) -> Bencode {
+        super::decode(data).unwrap()
+    }
+
+    fn assert_decode_error(kind: ErrorKind, offset: usize, data: &[u8]) {
+        let error = match super::decode(data.to_vec()) {
+            Ok(v) => panic!("Unexpected Ok value `{}`.", v),
+            Err(e) => e,
+        };
+        assert_eq!((kind, Some(offset)), (error.kind(), error.offset()));
+    }
 
     #[test]
     fn test_can_decode_an_empty_dictionary() {
@@ -141,25 +216,13 @@ mod tests {
     }
 
     #[test]
-    fn test_can_decode_a_malformed_dictionary() {
-        let s = b"d".to_vec();
-        let result = decode(s);
-
-        assert_eq!(Bencode::Dict(DictMap::new()), result);
+    fn test_err_when_dictionary_is_not_terminated() {
+        assert_decode_error(ErrorKind::UnexpectedEof, 1, b"d");
     }
 
     #[test]
-    fn test_can_decode_a_dictionary_with_invalid_key() {
-        let s = b"d3:fooi-4ei3e4:spam".to_vec();
-        let result = decode(s);
-
-        let mut d = DictMap::new();
-        d.insert(
-            byte_string::ByteString::from_vec(b"foo".to_vec()),
-            Bencode::Number(-4)
-        );
-
-        assert_eq!(Bencode::Dict(d), result);
+    fn test_err_when_dictionary_has_an_invalid_key() {
+        assert_decode_error(ErrorKind::InvalidKey, 10, b"d3:fooi-4ei3e4:spam");
     }
 
     #[test]
@@ -208,11 +271,8 @@ mod tests {
     }
 
     #[test]
-    fn test_can_decode_a_malformed_list() {
-        let s = b"l".to_vec();
-        let result = decode(s);
-
-        assert_eq!(Bencode::List(ListVec::new()), result)
+    fn test_err_when_list_is_not_terminated() {
+        assert_decode_error(ErrorKind::UnexpectedEof, 1, b"l");
     }
 
     #[test]
@@ -279,19 +339,13 @@ mod tests {
     }
 
     #[test]
-    fn test_can_decode_a_string_shorter_than_the_size() {
-        let s = b"10:1234567".to_vec();
-        let result = decode(s);
-
-        assert_eq!(Bencode::ByteString(b"1234567".to_vec()), result);
+    fn test_err_when_string_is_shorter_than_the_size() {
+        assert_decode_error(ErrorKind::UnexpectedEof, 10, b"10:1234567");
     }
 
     #[test]
-    fn test_can_decode_a_malformed_empty_string() {
-        let s = b"4:".to_vec();
-        let result = decode(s);
-
-        assert_eq!(Bencode::ByteString(b"".to_vec()), result);
+    fn test_err_when_string_is_empty_but_the_size_is_not() {
+        assert_decode_error(ErrorKind::UnexpectedEof, 2, b"4:");
     }
 
     #[test]
@@ -303,11 +357,49 @@ mod tests {
     }
 
     #[test]
-    fn test_empty_input() {
-        let s = b"".to_vec();
-        let result = decode(s);
+    fn test_err_on_empty_input() {
+        assert_decode_error(ErrorKind::UnexpectedEof, 0, b"");
+    }
 
-        assert_eq!(Bencode::Empty, result);
+    #[test]
+    fn test_err_on_unknown_type_byte() {
+        assert_decode_error(ErrorKind::InvalidType, 4, b"li3ex3:fooe");
+    }
+
+    #[test]
+    fn test_err_on_invalid_string_length() {
+        assert_decode_error(ErrorKind::InvalidLength, 2, b"l3x:fooe");
+    }
+
+    #[test]
+    fn test_err_on_invalid_integers() {
+        assert_decode_error(ErrorKind::InvalidInteger, 1, b"ie");
+        assert_decode_error(ErrorKind::InvalidInteger, 2, b"i-e");
+        assert_decode_error(ErrorKind::InvalidInteger, 1, b"i+3e");
+        assert_decode_error(ErrorKind::InvalidInteger, 2, b"i3.5e");
+        assert_decode_error(ErrorKind::InvalidInteger, 1, b"i9223372036854775808e");
+    }
+
+    #[test]
+    fn test_err_when_integer_is_not_terminated() {
+        assert_decode_error(ErrorKind::UnexpectedEof, 3, b"i42");
+    }
+
+    #[test]
+    fn test_err_when_nested_too_deeply() {
+        let mut s = vec![b'l'; MAX_DEPTH];
+        s.extend(vec![b'e'; MAX_DEPTH]);
+        assert!(super::decode(s).is_ok());
+
+        assert_decode_error(ErrorKind::TooDeep, MAX_DEPTH, &vec![b'l'; 200_000]);
+        let mut s = b"d1:a".repeat(MAX_DEPTH);
+        s.extend(b"de");
+        assert_decode_error(ErrorKind::TooDeep, 4 * MAX_DEPTH, &s);
+    }
+
+    #[test]
+    fn test_err_on_trailing_data() {
+        assert_decode_error(ErrorKind::TrailingData, 4, b"i42ei43e");
     }
 
     #[test]
@@ -347,4 +439,127 @@ mod tests {
 
         assert_eq!(Bencode::Dict(d), result);
     }
+
+    #[test]
+    fn test_can_decode_a_key_after_a_nested_dictionary() {
+        let s = b"d3:food4:spami3ee3:quxlee".to_vec();
+        let result = decode(s);
+
+        let mut foo = DictMap::new();
+        foo.insert(
+            byte_string::ByteString::from_vec(b"spam".to_vec()),
+            Bencode::Number(3),
+        );
+
+        let mut d = DictMap::new();
+        d.insert(
+            byte_string::ByteString::from_vec(b"foo".to_vec()),
+            Bencode::Dict(foo),
+        );
+        d.insert(
+            byte_string::ByteString::from_vec(b"qux".to_vec()),
+            Bencode::List(ListVec::new()),
+        );
+
+        assert_eq!(Bencode::Dict(d), result);
+    }
+
+    #[test]
+    fn test_decode_ref_borrows_from_input() {
+        let s = b"d4:spaml4:eggsi3eee";
+        let result = decode_ref(s).unwrap();
+
+        let mut d = DictRefMap::new();
+        d.insert(&s[3..7], BencodeRef::List(vec![BencodeRef::ByteString(&s[10..14]), BencodeRef::Number(3)]));
+
+        assert_eq!(BencodeRef::Dict(d), result);
+        assert_eq!(s[10..14].as_ptr(), result.get("spam").map(|l| match l {
+            BencodeRef::List(v) => v[0].as_bytes().unwrap().as_ptr(),
+            _ => panic!("spam should be a list"),
+        }).unwrap());
+    }
+
+    #[test]
+    fn test_decode_ref_of_a_large_string() {
+        let mut s = b"1048576:".to_vec();
+        s.extend(vec![7; 1048576]);
+        let result = decode_ref(&s).unwrap();
+
+        assert_eq!(Some(&s[8..]), result.as_bytes());
+    }
+
+    fn assert_strict_error(kind: ErrorKind, offset: usize, data: &[u8]) {
+        let error = match decode_ref_with_mode(data, Mode::Strict) {
+            Ok(v) => panic!("Unexpected Ok value `{}`.", v),
+            Err(e) => e,
+        };
+        assert_eq!((kind, Some(offset)), (error.kind(), error.offset()));
+        assert!(decode_ref(data).is_ok());
+        assert!(!is_canonical(data));
+    }
+
+    #[test]
+    fn test_strict_rejects_non_canonical_integers() {
+        assert_strict_error(ErrorKind::NegativeZero, 1, b"i-0e");
+        assert_strict_error(ErrorKind::LeadingZero, 1, b"i03e");
+        assert_strict_error(ErrorKind::LeadingZero, 2, b"li00ee");
+    }
+
+    #[test]
+    fn test_strict_rejects_leading_zero_string_lengths() {
+        assert_strict_error(ErrorKind::LeadingZero, 0, b"04:spam");
+        assert_strict_error(ErrorKind::LeadingZero, 1, b"d03:fooi1ee");
+    }
+
+    #[test]
+    fn test_strict_rejects_unsorted_keys() {
+        assert_strict_error(ErrorKind::UnsortedKeys, 10, b"d4:spami3e3:fooi4ee");
+    }
+
+    #[test]
+    fn test_strict_rejects_duplicate_keys() {
+        assert_strict_error(ErrorKind::DuplicateKey, 9, b"d3:fooi1e3:fooi2ee");
+    }
+
+    #[test]
+    fn test_lenient_keeps_the_last_duplicate_key() {
+        let mut d = DictMap::new();
+        d.insert(
+            byte_string::ByteString::from_vec(b"foo".to_vec()),
+            Bencode::Number(2),
+        );
+
+        assert_eq!(Bencode::Dict(d), decode(b"d3:fooi1e3:fooi2ee".to_vec()));
+    }
+
+    #[test]
+    fn test_is_canonical() {
+        assert!(is_canonical(b"d0:0:3:fool4:spami0ee4:spami-3e5:spamsdee"));
+        assert!(!is_canonical(b"i3"));
+    }
+
+    #[test]
+    fn test_value_span() {
+        let s = b"d8:announce3:yes4:infod6:lengthi4e4:name5:dereke3:zzzi1ee";
+
+        assert_eq!(Some(22..48), value_span(s, "info").unwrap());
+        assert_eq!(b"d6:lengthi4e4:name5:dereke", &s[22..48]);
+        assert_eq!(Some(11..16), value_span(s, "announce").unwrap());
+        assert_eq!(None, value_span(s, "comment").unwrap());
+    }
+
+    #[test]
+    fn test_value_span_takes_the_last_duplicate() {
+        let s = b"d4:infoi1e4:infoi2ee";
+
+        assert_eq!(Some(16..19), value_span(s, "info").unwrap());
+        assert_eq!(2, decode(s.to_vec()).get_number("info").unwrap());
+    }
+
+    #[test]
+    fn test_err_when_value_span_input_is_not_a_dictionary() {
+        let error = value_span(b"le", "info").unwrap_err();
+
+        assert_eq!((ErrorKind::InvalidType, Some(0)), (error.kind(), error.offset()));
+    }
 }
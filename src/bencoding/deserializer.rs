@@ -0,0 +1,339 @@
+use std::vec;
+use std::collections::btree_map;
+use serde::de::{self, Visitor, DeserializeOwned, DeserializeSeed, IntoDeserializer};
+use serde::forward_to_deserialize_any;
+
+use crate::bencoding::bencode::Bencode;
+use crate::bencoding::byte_string::ByteString;
+use crate::bencoding::decoder;
+use crate::bencoding::error::Error;
+
+pub fn from_bytes<T: DeserializeOwned>(data: &[u8]) -> Result<T, Error> {
+    from_bencode(decoder::decode_ref(data)?.to_bencode())
+}
+
+pub fn from_bencode<T: DeserializeOwned>(value: Bencode) -> Result<T, Error> {
+    T::deserialize(Deserializer::new(value))
+}
+
+/// Deserializes out of a decoded `Bencode` tree. A `Bencode::Empty` value, or a
+/// missing dict key, deserializes as `None`.
+pub struct Deserializer {
+    input: Bencode,
+}
+
+impl Deserializer {
+    pub fn new(input: Bencode) -> Self {
+        Self { input }
+    }
+
+    fn into_string(self) -> Result<String, Error> {
+        match self.input {
+            Bencode::ByteString(v) => match String::from_utf8(v) {
+                Ok(s) => Ok(s),
+                Err(_) => Err(Error::new("ByteString is not valid utf-8.".to_string())),
+            },
+            other => Err(Error::new(format!("Value was `{}`, expected a ByteString.", other))),
+        }
+    }
+}
+
+impl<'de> de::Deserializer<'de> for Deserializer {
+    type Error = Error;
+
+    fn deserialize_any<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, Error> {
+        match self.input {
+            Bencode::Empty => visitor.visit_unit(),
+            Bencode::Number(n) => visitor.visit_i64(n),
+            Bencode::ByteString(v) => visitor.visit_byte_buf(v),
+            Bencode::List(l) => visitor.visit_seq(ListAccess { iter: l.into_iter() }),
+            Bencode::Dict(d) => visitor.visit_map(DictAccess { iter: d.into_iter(), value: None }),
+        }
+    }
+
+    fn deserialize_bool<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, Error> {
+        match self.input {
+            Bencode::Number(0) => visitor.visit_bool(false),
+            Bencode::Number(1) => visitor.visit_bool(true),
+            other => Err(Error::new(format!("Value was `{}`, expected 0 or 1.", other))),
+        }
+    }
+
+    fn deserialize_char<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, Error> {
+        self.deserialize_string(visitor)
+    }
+
+    fn deserialize_str<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, Error> {
+        self.deserialize_string(visitor)
+    }
+
+    fn deserialize_string<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, Error> {
+        visitor.visit_string(self.into_string()?)
+    }
+
+    fn deserialize_identifier<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, Error> {
+        match self.input {
+            Bencode::ByteString(v) => match String::from_utf8(v) {
+                Ok(s) => visitor.visit_string(s),
+                Err(e) => visitor.visit_byte_buf(e.into_bytes()),
+            },
+            other => Deserializer::new(other).deserialize_any(visitor),
+        }
+    }
+
+    fn deserialize_option<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, Error> {
+        match self.input {
+            Bencode::Empty => visitor.visit_none(),
+            _ => visitor.visit_some(self),
+        }
+    }
+
+    fn deserialize_unit<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, Error> {
+        visitor.visit_unit()
+    }
+
+    fn deserialize_unit_struct<V: Visitor<'de>>(self, _name: &'static str, visitor: V) -> Result<V::Value, Error> {
+        visitor.visit_unit()
+    }
+
+    fn deserialize_newtype_struct<V: Visitor<'de>>(self, _name: &'static str, visitor: V) -> Result<V::Value, Error> {
+        visitor.visit_newtype_struct(self)
+    }
+
+    fn deserialize_enum<V: Visitor<'de>>(
+        self,
+        _name: &'static str,
+        _variants: &'static [&'static str],
+        visitor: V,
+    ) -> Result<V::Value, Error> {
+        match self.input {
+            Bencode::ByteString(_) => visitor.visit_enum(self.into_string()?.into_deserializer()),
+            Bencode::Dict(d) => {
+                if d.len() != 1 {
+                    return Err(Error::new(format!("Enum dict has {} keys, expected 1.", d.len())));
+                }
+                let (variant, value) = d.into_iter().next().unwrap();
+                visitor.visit_enum(VariantAccess { variant, value })
+            },
+            other => Err(Error::new(format!("Value was `{}`, expected a ByteString or Dict enum.", other))),
+        }
+    }
+
+    fn deserialize_ignored_any<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, Error> {
+        visitor.visit_unit()
+    }
+
+    fn is_human_readable(&self) -> bool {
+        false
+    }
+
+    forward_to_deserialize_any! {
+        i8 i16 i32 i64 i128 u8 u16 u32 u64 u128 f32 f64
+        bytes byte_buf seq tuple tuple_struct map struct
+    }
+}
+
+struct ListAccess {
+    iter: vec::IntoIter<Bencode>,
+}
+
+impl<'de> de::SeqAccess<'de> for ListAccess {
+    type Error = Error;
+
+    fn next_element_seed<T: DeserializeSeed<'de>>(&mut self, seed: T) -> Result<Option<T::Value>, Error> {
+        match self.iter.next() {
+            Some(value) => seed.deserialize(Deserializer::new(value)).map(Some),
+            None => Ok(None),
+        }
+    }
+
+    fn size_hint(&self) -> Option<usize> {
+        Some(self.iter.len())
+    }
+}
+
+struct DictAccess {
+    iter: btree_map::IntoIter<ByteString, Bencode>,
+    value: Option<Bencode>,
+}
+
+impl<'de> de::MapAccess<'de> for DictAccess {
+    type Error = Error;
+
+    fn next_key_seed<K: DeserializeSeed<'de>>(&mut self, seed: K) -> Result<Option<K::Value>, Error> {
+        match self.iter.next() {
+            Some((key, value)) => {
+                self.value = Some(value);
+                seed.deserialize(Deserializer::new(Bencode::ByteString(key.unwrap()))).map(Some)
+            },
+            None => Ok(None),
+        }
+    }
+
+    fn next_value_seed<V: DeserializeSeed<'de>>(&mut self, seed: V) -> Result<V::Value, Error> {
+        match self.value.take() {
+            Some(value) => seed.deserialize(Deserializer::new(value)),
+            None => Err(Error::new("Dictionary value requested before its key.".to_string())),
+        }
+    }
+
+    fn size_hint(&self) -> Option<usize> {
+        Some(self.iter.len())
+    }
+}
+
+struct VariantAccess {
+    variant: ByteString,
+    value: Bencode,
+}
+
+impl<'de> de::EnumAccess<'de> for VariantAccess {
+    type Error = Error;
+    type Variant = Deserializer;
+
+    fn variant_seed<V: DeserializeSeed<'de>>(self, seed: V) -> Result<(V::Value, Deserializer), Error> {
+        let variant = seed.deserialize(Deserializer::new(Bencode::ByteString(self.variant.unwrap())))?;
+        Ok((variant, Deserializer::new(self.value)))
+    }
+}
+
+impl<'de> de::VariantAccess<'de> for Deserializer {
+    type Error = Error;
+
+    fn unit_variant(self) -> Result<(), Error> {
+        Ok(())
+    }
+
+    fn newtype_variant_seed<T: DeserializeSeed<'de>>(self, seed: T) -> Result<T::Value, Error> {
+        seed.deserialize(self)
+    }
+
+    fn tuple_variant<V: Visitor<'de>>(self, _len: usize, visitor: V) -> Result<V::Value, Error> {
+        de::Deserializer::deserialize_seq(self, visitor)
+    }
+
+    fn struct_variant<V: Visitor<'de>>(self, _fields: &'static [&'static str], visitor: V) -> Result<V::Value, Error> {
+        de::Deserializer::deserialize_map(self, visitor)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::collections::BTreeMap;
+    use serde::{Serialize, Deserialize};
+    use crate::bencoding::serializer::to_bytes;
+
+    #[derive(Serialize, Deserialize, PartialEq, Debug)]
+    struct Info {
+        name: String,
+        #[serde(rename = "piece length")]
+        piece_length: i64,
+        #[serde(with = "serde_bytes")]
+        pieces: Vec<u8>,
+        private: Option<bool>,
+    }
+
+    #[derive(Serialize, Deserialize, PartialEq, Debug)]
+    struct Metainfo {
+        announce: String,
+        #[serde(rename = "creation date")]
+        creation_date: Option<i64>,
+        info: Info,
+    }
+
+    #[derive(Serialize, Deserialize, PartialEq, Debug)]
+    enum Event {
+        Started,
+        Progress(u32),
+        Peer { port: u16 },
+    }
+
+    #[test]
+    fn test_can_deserialize_a_struct() {
+        let data = b"d8:announce3:yes4:infod4:name5:derek12:piece lengthi100e6:pieces3:z\xc3\x287:privatei1eee";
+        let expected = Metainfo {
+            announce: "yes".to_string(),
+            creation_date: None,
+            info: Info {
+                name: "derek".to_string(),
+                piece_length: 100,
+                pieces: vec![b'z', 195, 40],
+                private: Some(true),
+            },
+        };
+
+        assert_eq!(expected, from_bytes::<Metainfo>(data).unwrap());
+    }
+
+    #[test]
+    fn test_unknown_keys_are_ignored() {
+        let data = b"d8:announce3:yes7:comment2:hi4:infod4:name5:derek12:piece lengthi100e6:pieces0:ee";
+        let result = from_bytes::<Metainfo>(data).unwrap();
+
+        assert_eq!("derek", result.info.name);
+        assert_eq!(None, result.info.private);
+    }
+
+    #[test]
+    fn test_round_trip() {
+        let metainfo = Metainfo {
+            announce: "http://tracker".to_string(),
+            creation_date: Some(170),
+            info: Info {
+                name: "derek.jar".to_string(),
+                piece_length: 16384,
+                pieces: vec![0, 255, 13],
+                private: None,
+            },
+        };
+
+        let bytes = to_bytes(&metainfo).unwrap();
+        assert_eq!(metainfo, from_bytes::<Metainfo>(&bytes).unwrap());
+    }
+
+    #[test]
+    fn test_can_deserialize_nested_collections() {
+        let data = b"d3:food4:spamli1ei2eee3:quxdee";
+        let result = from_bytes::<BTreeMap<String, BTreeMap<String, Vec<u8>>>>(data).unwrap();
+
+        let mut foo = BTreeMap::new();
+        foo.insert("spam".to_string(), vec![1, 2]);
+        let mut expected = BTreeMap::new();
+        expected.insert("foo".to_string(), foo);
+        expected.insert("qux".to_string(), BTreeMap::new());
+
+        assert_eq!(expected, result);
+    }
+
+    #[test]
+    fn test_can_deserialize_enum_variants() {
+        assert_eq!(Event::Started, from_bytes::<Event>(b"7:Started").unwrap());
+        assert_eq!(Event::Progress(3), from_bytes::<Event>(b"d8:Progressi3ee").unwrap());
+        assert_eq!(Event::Peer { port: 6881 }, from_bytes::<Event>(b"d4:Peerd4:porti6881eee").unwrap());
+    }
+
+    #[test]
+    fn test_err_when_missing_a_required_field() {
+        assert_eq!(
+            Error::new("missing field `name`".to_string()),
+            from_bytes::<Info>(b"d12:piece lengthi100e6:pieces0:e").unwrap_err(),
+        );
+    }
+
+    #[test]
+    fn test_err_when_string_is_not_utf8() {
+        assert_eq!(
+            Error::new("ByteString is not valid utf-8.".to_string()),
+            from_bytes::<String>(b"2:\xc3\x28").unwrap_err(),
+        );
+    }
+
+    #[test]
+    fn test_err_when_bool_is_out_of_range() {
+        assert_eq!(
+            Error::new("Value was `2`, expected 0 or 1.".to_string()),
+            from_bytes::<bool>(b"i2e").unwrap_err(),
+        );
+    }
+}
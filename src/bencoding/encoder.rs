@@ -6,7 +6,7 @@ use crate::bencoding::byte_string::ByteString;
 pub fn encode(data: Bencode) -> Vec<u8> {
     let mut buffer = Vec::<u8>::new();
     encode_internal(&mut buffer, data);
-    return buffer;
+    buffer
 }
 
 fn encode_internal(buffer: &mut Vec<u8>, data: Bencode) {
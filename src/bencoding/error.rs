@@ -2,20 +2,76 @@ use std::{fmt, io};
 use std::str::Utf8Error;
 use std::num::ParseIntError;
 
+#[derive(PartialEq, Eq, Clone, Copy, Debug)]
+pub enum ErrorKind {
+    UnexpectedEof,
+    InvalidType,
+    InvalidInteger,
+    InvalidLength,
+    InvalidKey,
+    LeadingZero,
+    NegativeZero,
+    UnsortedKeys,
+    DuplicateKey,
+    TrailingData,
+    TooDeep,
+    Io,
+    Other,
+}
+
+impl ErrorKind {
+    fn message(self) -> &'static str {
+        match self {
+            ErrorKind::UnexpectedEof => "Unexpected end of input",
+            ErrorKind::InvalidType => "Unknown bencode type byte",
+            ErrorKind::InvalidInteger => "Invalid integer",
+            ErrorKind::InvalidLength => "Invalid byte string length",
+            ErrorKind::InvalidKey => "Dictionary key is not a ByteString",
+            ErrorKind::LeadingZero => "Number has a leading zero",
+            ErrorKind::NegativeZero => "Negative zero is not a valid integer",
+            ErrorKind::UnsortedKeys => "Dictionary keys are not sorted",
+            ErrorKind::DuplicateKey => "Dictionary key is duplicated",
+            ErrorKind::TrailingData => "Trailing data after bencode value",
+            ErrorKind::TooDeep => "Lists and dictionaries are nested too deeply",
+            ErrorKind::Io => "I/O error",
+            ErrorKind::Other => "Bencode error",
+        }
+    }
+}
+
 #[derive(PartialEq, Debug)]
 pub struct Error {
+    kind: ErrorKind,
+    offset: Option<usize>,
     details: String
 }
 
 impl Error {
     pub fn new(msg: String) -> Error {
-        Self{details: msg}
+        Self{kind: ErrorKind::Other, offset: None, details: msg}
+    }
+
+    /// An error raised while decoding, `offset` is the index of the byte that
+    /// could not be parsed.
+    pub fn at(kind: ErrorKind, offset: usize) -> Error {
+        Self{kind, offset: Some(offset), details: kind.message().to_string()}
+    }
+
+    pub fn kind(&self) -> ErrorKind {
+        self.kind
+    }
+
+    pub fn offset(&self) -> Option<usize> {
+        self.offset
     }
 }
 
 impl fmt::Display for Error {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
-        write!(f, "{}", self.details)
+        match self.offset {
+            Some(offset) => write!(f, "{} at byte {}", self.details, offset),
+            None => write!(f, "{}", self.details),
+        }
     }
 }
 
@@ -27,7 +83,7 @@ impl std::error::Error for Error {
 
 impl From<io::Error> for Error {
     fn from(err: io::Error) -> Self {
-        Error::new(format!("{}", err))
+        Self{kind: ErrorKind::Io, offset: None, details: format!("{}", err)}
     }
 }
 
@@ -43,3 +99,33 @@ impl From<ParseIntError> for Error {
     }
 }
 
+impl serde::ser::Error for Error {
+    fn custom<T: fmt::Display>(msg: T) -> Self {
+        Error::new(format!("{}", msg))
+    }
+}
+
+impl serde::de::Error for Error {
+    fn custom<T: fmt::Display>(msg: T) -> Self {
+        Error::new(format!("{}", msg))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_display_with_offset() {
+        let error = Error::at(ErrorKind::UnexpectedEof, 12);
+        assert_eq!("Unexpected end of input at byte 12", format!("{}", error));
+    }
+
+    #[test]
+    fn test_display_without_offset() {
+        let error = Error::new("Bencode is not a dict.".to_string());
+        assert_eq!("Bencode is not a dict.", format!("{}", error));
+        assert_eq!(ErrorKind::Other, error.kind());
+        assert_eq!(None, error.offset());
+    }
+}
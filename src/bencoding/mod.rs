@@ -2,6 +2,10 @@ pub mod encoder;
 pub mod stream_decoder;
 pub mod decoder;
 pub mod bencode;
+pub mod bencode_ref;
 pub mod error;
+pub mod mode;
 pub mod result;
 pub mod byte_string;
+pub mod serializer;
+pub mod deserializer;
@@ -0,0 +1,74 @@
+use std::cmp::Ordering;
+
+use crate::bencoding::error::{Error, ErrorKind};
+
+/// How strictly the decoders enforce canonical bencode.
+///
+/// `Strict` rejects every form that would not survive a decode/encode round trip
+/// unchanged: `i-0e`, leading zeros in integers and string lengths, and dict
+/// keys that are duplicated or out of order. `Lenient` accepts them, which is
+/// what most clients do in the wild.
+#[derive(PartialEq, Eq, Clone, Copy, Debug, Default)]
+pub enum Mode {
+    #[default]
+    Lenient,
+    Strict,
+}
+
+impl Mode {
+    /// Checks the ascii digits of an integer or string length, `offset` is the
+    /// index of the first byte of `number`.
+    pub(crate) fn check_number(self, number: &[u8], offset: usize) -> Result<(), Error> {
+        if self == Mode::Lenient {
+            return Ok(());
+        }
+
+        match number {
+            [b'-', b'0', ..] => Err(Error::at(ErrorKind::NegativeZero, offset)),
+            [b'0', _, ..] => Err(Error::at(ErrorKind::LeadingZero, offset)),
+            _ => Ok(()),
+        }
+    }
+
+    /// Checks that `key` sorts strictly after the `previous` key of the same dict.
+    pub(crate) fn check_key_order(self, previous: Option<&[u8]>, key: &[u8], offset: usize) -> Result<(), Error> {
+        if self == Mode::Lenient {
+            return Ok(());
+        }
+
+        match previous.map(|p| p.cmp(key)) {
+            Some(Ordering::Equal) => Err(Error::at(ErrorKind::DuplicateKey, offset)),
+            Some(Ordering::Greater) => Err(Error::at(ErrorKind::UnsortedKeys, offset)),
+            _ => Ok(()),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_lenient_accepts_non_canonical_numbers() {
+        assert_eq!(Ok(()), Mode::Lenient.check_number(b"03", 0));
+        assert_eq!(Ok(()), Mode::Lenient.check_number(b"-0", 0));
+    }
+
+    #[test]
+    fn test_strict_check_number() {
+        assert_eq!(Ok(()), Mode::Strict.check_number(b"0", 0));
+        assert_eq!(Ok(()), Mode::Strict.check_number(b"-10", 0));
+        assert_eq!(Err(Error::at(ErrorKind::LeadingZero, 4)), Mode::Strict.check_number(b"03", 4));
+        assert_eq!(Err(Error::at(ErrorKind::NegativeZero, 4)), Mode::Strict.check_number(b"-0", 4));
+        assert_eq!(Err(Error::at(ErrorKind::NegativeZero, 4)), Mode::Strict.check_number(b"-03", 4));
+    }
+
+    #[test]
+    fn test_strict_check_key_order() {
+        assert_eq!(Ok(()), Mode::Strict.check_key_order(None, b"b", 1));
+        assert_eq!(Ok(()), Mode::Strict.check_key_order(Some(b"a"), b"b", 1));
+        assert_eq!(Err(Error::at(ErrorKind::DuplicateKey, 1)), Mode::Strict.check_key_order(Some(b"b"), b"b", 1));
+        assert_eq!(Err(Error::at(ErrorKind::UnsortedKeys, 1)), Mode::Strict.check_key_order(Some(b"c"), b"b", 1));
+        assert_eq!(Ok(()), Mode::Lenient.check_key_order(Some(b"c"), b"b", 1));
+    }
+}
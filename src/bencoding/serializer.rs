@@ -0,0 +1,435 @@
+use std::convert::TryFrom;
+use serde::ser::{self, Serialize};
+
+use crate::bencoding::bencode::{Bencode, ListVec, DictMap};
+use crate::bencoding::byte_string::ByteString;
+use crate::bencoding::encoder;
+use crate::bencoding::error::Error;
+
+pub fn to_bytes<T: Serialize + ?Sized>(value: &T) -> Result<Vec<u8>, Error> {
+    Ok(encoder::encode(to_bencode(value)?))
+}
+
+pub fn to_bencode<T: Serialize + ?Sized>(value: &T) -> Result<Bencode, Error> {
+    value.serialize(Serializer)
+}
+
+/// Serializes into a `Bencode` tree. Bencode has no null, so `None` and unit
+/// values become `Bencode::Empty` and are left out of the enclosing list or dict.
+pub struct Serializer;
+
+impl ser::Serializer for Serializer {
+    type Ok = Bencode;
+    type Error = Error;
+
+    type SerializeSeq = SerializeList;
+    type SerializeTuple = SerializeList;
+    type SerializeTupleStruct = SerializeList;
+    type SerializeTupleVariant = SerializeTupleVariant;
+    type SerializeMap = SerializeDict;
+    type SerializeStruct = SerializeDict;
+    type SerializeStructVariant = SerializeStructVariant;
+
+    fn serialize_bool(self, v: bool) -> Result<Bencode, Error> {
+        Ok(Bencode::Number(if v { 1 } else { 0 }))
+    }
+
+    fn serialize_i8(self, v: i8) -> Result<Bencode, Error> {
+        self.serialize_i64(i64::from(v))
+    }
+
+    fn serialize_i16(self, v: i16) -> Result<Bencode, Error> {
+        self.serialize_i64(i64::from(v))
+    }
+
+    fn serialize_i32(self, v: i32) -> Result<Bencode, Error> {
+        self.serialize_i64(i64::from(v))
+    }
+
+    fn serialize_i64(self, v: i64) -> Result<Bencode, Error> {
+        Ok(Bencode::Number(v))
+    }
+
+    fn serialize_u8(self, v: u8) -> Result<Bencode, Error> {
+        self.serialize_i64(i64::from(v))
+    }
+
+    fn serialize_u16(self, v: u16) -> Result<Bencode, Error> {
+        self.serialize_i64(i64::from(v))
+    }
+
+    fn serialize_u32(self, v: u32) -> Result<Bencode, Error> {
+        self.serialize_i64(i64::from(v))
+    }
+
+    fn serialize_u64(self, v: u64) -> Result<Bencode, Error> {
+        match i64::try_from(v) {
+            Ok(n) => self.serialize_i64(n),
+            Err(_) => Err(Error::new(format!("{} does not fit in an i64.", v))),
+        }
+    }
+
+    fn serialize_f32(self, _v: f32) -> Result<Bencode, Error> {
+        Err(Error::new("Bencode does not support floating point numbers.".to_string()))
+    }
+
+    fn serialize_f64(self, _v: f64) -> Result<Bencode, Error> {
+        Err(Error::new("Bencode does not support floating point numbers.".to_string()))
+    }
+
+    fn serialize_char(self, v: char) -> Result<Bencode, Error> {
+        let mut buffer = [0; 4];
+        self.serialize_str(v.encode_utf8(&mut buffer))
+    }
+
+    fn serialize_str(self, v: &str) -> Result<Bencode, Error> {
+        Ok(Bencode::ByteString(v.as_bytes().to_vec()))
+    }
+
+    fn serialize_bytes(self, v: &[u8]) -> Result<Bencode, Error> {
+        Ok(Bencode::ByteString(v.to_vec()))
+    }
+
+    fn serialize_none(self) -> Result<Bencode, Error> {
+        Ok(Bencode::Empty)
+    }
+
+    fn serialize_some<T: Serialize + ?Sized>(self, value: &T) -> Result<Bencode, Error> {
+        value.serialize(self)
+    }
+
+    fn serialize_unit(self) -> Result<Bencode, Error> {
+        Ok(Bencode::Empty)
+    }
+
+    fn serialize_unit_struct(self, _name: &'static str) -> Result<Bencode, Error> {
+        Ok(Bencode::Empty)
+    }
+
+    fn serialize_unit_variant(self, _name: &'static str, _index: u32, variant: &'static str) -> Result<Bencode, Error> {
+        self.serialize_str(variant)
+    }
+
+    fn serialize_newtype_struct<T: Serialize + ?Sized>(self, _name: &'static str, value: &T) -> Result<Bencode, Error> {
+        value.serialize(self)
+    }
+
+    fn serialize_newtype_variant<T: Serialize + ?Sized>(
+        self,
+        _name: &'static str,
+        _index: u32,
+        variant: &'static str,
+        value: &T,
+    ) -> Result<Bencode, Error> {
+        let mut dict = DictMap::new();
+        insert(&mut dict, ByteString::from_str(variant), to_bencode(value)?);
+        Ok(Bencode::Dict(dict))
+    }
+
+    fn serialize_seq(self, len: Option<usize>) -> Result<SerializeList, Error> {
+        Ok(SerializeList { list: ListVec::with_capacity(len.unwrap_or(0)) })
+    }
+
+    fn serialize_tuple(self, len: usize) -> Result<SerializeList, Error> {
+        self.serialize_seq(Some(len))
+    }
+
+    fn serialize_tuple_struct(self, _name: &'static str, len: usize) -> Result<SerializeList, Error> {
+        self.serialize_seq(Some(len))
+    }
+
+    fn serialize_tuple_variant(
+        self,
+        _name: &'static str,
+        _index: u32,
+        variant: &'static str,
+        len: usize,
+    ) -> Result<SerializeTupleVariant, Error> {
+        Ok(SerializeTupleVariant { variant, list: ListVec::with_capacity(len) })
+    }
+
+    fn serialize_map(self, _len: Option<usize>) -> Result<SerializeDict, Error> {
+        Ok(SerializeDict { dict: DictMap::new(), key: None })
+    }
+
+    fn serialize_struct(self, _name: &'static str, _len: usize) -> Result<SerializeDict, Error> {
+        self.serialize_map(None)
+    }
+
+    fn serialize_struct_variant(
+        self,
+        _name: &'static str,
+        _index: u32,
+        variant: &'static str,
+        _len: usize,
+    ) -> Result<SerializeStructVariant, Error> {
+        Ok(SerializeStructVariant { variant, dict: DictMap::new() })
+    }
+
+    fn is_human_readable(&self) -> bool {
+        false
+    }
+}
+
+pub struct SerializeList {
+    list: ListVec,
+}
+
+pub struct SerializeTupleVariant {
+    variant: &'static str,
+    list: ListVec,
+}
+
+pub struct SerializeDict {
+    dict: DictMap,
+    key: Option<ByteString>,
+}
+
+pub struct SerializeStructVariant {
+    variant: &'static str,
+    dict: DictMap,
+}
+
+/// Bencode has no null, a `None` can only be left out of a dict. In a list it
+/// would shift every later element.
+fn push(list: &mut ListVec, value: Bencode) -> Result<(), Error> {
+    if value == Bencode::Empty {
+        return Err(Error::new("None can't be serialized inside a list.".to_string()));
+    }
+    list.push(value);
+    Ok(())
+}
+
+fn insert(dict: &mut DictMap, key: ByteString, value: Bencode) {
+    if value != Bencode::Empty {
+        dict.insert(key, value);
+    }
+}
+
+fn wrap_variant(variant: &'static str, value: Bencode) -> Bencode {
+    let mut dict = DictMap::new();
+    dict.insert(ByteString::from_str(variant), value);
+    Bencode::Dict(dict)
+}
+
+impl ser::SerializeSeq for SerializeList {
+    type Ok = Bencode;
+    type Error = Error;
+
+    fn serialize_element<T: Serialize + ?Sized>(&mut self, value: &T) -> Result<(), Error> {
+        push(&mut self.list, to_bencode(value)?)
+    }
+
+    fn end(self) -> Result<Bencode, Error> {
+        Ok(Bencode::List(self.list))
+    }
+}
+
+impl ser::SerializeTuple for SerializeList {
+    type Ok = Bencode;
+    type Error = Error;
+
+    fn serialize_element<T: Serialize + ?Sized>(&mut self, value: &T) -> Result<(), Error> {
+        ser::SerializeSeq::serialize_element(self, value)
+    }
+
+    fn end(self) -> Result<Bencode, Error> {
+        ser::SerializeSeq::end(self)
+    }
+}
+
+impl ser::SerializeTupleStruct for SerializeList {
+    type Ok = Bencode;
+    type Error = Error;
+
+    fn serialize_field<T: Serialize + ?Sized>(&mut self, value: &T) -> Result<(), Error> {
+        ser::SerializeSeq::serialize_element(self, value)
+    }
+
+    fn end(self) -> Result<Bencode, Error> {
+        ser::SerializeSeq::end(self)
+    }
+}
+
+impl ser::SerializeTupleVariant for SerializeTupleVariant {
+    type Ok = Bencode;
+    type Error = Error;
+
+    fn serialize_field<T: Serialize + ?Sized>(&mut self, value: &T) -> Result<(), Error> {
+        push(&mut self.list, to_bencode(value)?)
+    }
+
+    fn end(self) -> Result<Bencode, Error> {
+        Ok(wrap_variant(self.variant, Bencode::List(self.list)))
+    }
+}
+
+impl ser::SerializeMap for SerializeDict {
+    type Ok = Bencode;
+    type Error = Error;
+
+    fn serialize_key<T: Serialize + ?Sized>(&mut self, key: &T) -> Result<(), Error> {
+        match to_bencode(key)? {
+            Bencode::ByteString(v) => {
+                self.key = Some(ByteString::from_vec(v));
+                Ok(())
+            },
+            other => Err(Error::new(format!("Dictionary key was `{}`, expected a ByteString", other))),
+        }
+    }
+
+    fn serialize_value<T: Serialize + ?Sized>(&mut self, value: &T) -> Result<(), Error> {
+        let key = match self.key.take() {
+            Some(key) => key,
+            None => return Err(Error::new("Dictionary value serialized before its key.".to_string())),
+        };
+        insert(&mut self.dict, key, to_bencode(value)?);
+        Ok(())
+    }
+
+    fn end(self) -> Result<Bencode, Error> {
+        Ok(Bencode::Dict(self.dict))
+    }
+}
+
+impl ser::SerializeStruct for SerializeDict {
+    type Ok = Bencode;
+    type Error = Error;
+
+    fn serialize_field<T: Serialize + ?Sized>(&mut self, key: &'static str, value: &T) -> Result<(), Error> {
+        insert(&mut self.dict, ByteString::from_str(key), to_bencode(value)?);
+        Ok(())
+    }
+
+    fn end(self) -> Result<Bencode, Error> {
+        Ok(Bencode::Dict(self.dict))
+    }
+}
+
+impl ser::SerializeStructVariant for SerializeStructVariant {
+    type Ok = Bencode;
+    type Error = Error;
+
+    fn serialize_field<T: Serialize + ?Sized>(&mut self, key: &'static str, value: &T) -> Result<(), Error> {
+        insert(&mut self.dict, ByteString::from_str(key), to_bencode(value)?);
+        Ok(())
+    }
+
+    fn end(self) -> Result<Bencode, Error> {
+        Ok(wrap_variant(self.variant, Bencode::Dict(self.dict)))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::collections::BTreeMap;
+    use serde::Serialize;
+
+    #[derive(Serialize)]
+    struct Info {
+        name: String,
+        #[serde(rename = "piece length")]
+        piece_length: i64,
+        #[serde(with = "serde_bytes")]
+        pieces: Vec<u8>,
+        private: Option<bool>,
+    }
+
+    #[derive(Serialize)]
+    struct Metainfo {
+        announce: String,
+        info: Info,
+        #[serde(rename = "url-list")]
+        url_list: Vec<String>,
+    }
+
+    #[derive(Serialize)]
+    enum Event {
+        Started,
+        Progress(u32),
+        Peer { port: u16 },
+    }
+
+    #[test]
+    fn test_can_serialize_a_struct() {
+        let metainfo = Metainfo {
+            announce: "yes".to_string(),
+            info: Info {
+                name: "derek".to_string(),
+                piece_length: 100,
+                pieces: vec![b'z', 195, 40],
+                private: Some(true),
+            },
+            url_list: vec!["a".to_string(), "bc".to_string()],
+        };
+
+        assert_eq!(
+            b"d8:announce3:yes4:infod4:name5:derek12:piece lengthi100e6:pieces3:z\xc3\x287:privatei1ee8:url-listl1:a2:bcee".to_vec(),
+            to_bytes(&metainfo).unwrap(),
+        );
+    }
+
+    #[test]
+    fn test_none_fields_are_omitted() {
+        let info = Info {
+            name: "derek".to_string(),
+            piece_length: 100,
+            pieces: vec![],
+            private: None,
+        };
+
+        assert_eq!(b"d4:name5:derek12:piece lengthi100e6:pieces0:e".to_vec(), to_bytes(&info).unwrap());
+    }
+
+    #[test]
+    fn test_err_when_a_list_holds_none() {
+        assert_eq!(
+            Error::new("None can't be serialized inside a list.".to_string()),
+            to_bytes(&vec![None, Some(1)]).unwrap_err(),
+        );
+    }
+
+    #[test]
+    fn test_can_serialize_a_map() {
+        let mut map = BTreeMap::new();
+        map.insert("spam", vec![1, 2]);
+        map.insert("foo", vec![]);
+
+        assert_eq!(b"d3:foole4:spamli1ei2eee".to_vec(), to_bytes(&map).unwrap());
+    }
+
+    #[test]
+    fn test_can_serialize_enum_variants() {
+        assert_eq!(b"7:Started".to_vec(), to_bytes(&Event::Started).unwrap());
+        assert_eq!(b"d8:Progressi3ee".to_vec(), to_bytes(&Event::Progress(3)).unwrap());
+        assert_eq!(b"d4:Peerd4:porti6881eee".to_vec(), to_bytes(&Event::Peer { port: 6881 }).unwrap());
+    }
+
+    #[test]
+    fn test_err_when_serializing_a_float() {
+        assert_eq!(
+            Error::new("Bencode does not support floating point numbers.".to_string()),
+            to_bytes(&1.5).unwrap_err(),
+        );
+    }
+
+    #[test]
+    fn test_err_when_u64_overflows() {
+        assert_eq!(
+            Error::new("18446744073709551615 does not fit in an i64.".to_string()),
+            to_bytes(&u64::MAX).unwrap_err(),
+        );
+    }
+
+    #[test]
+    fn test_err_when_map_key_is_not_a_string() {
+        let mut map = BTreeMap::new();
+        map.insert(3, 4);
+
+        assert_eq!(
+            Error::new("Dictionary key was `3`, expected a ByteString".to_string()),
+            to_bytes(&map).unwrap_err(),
+        );
+    }
+}
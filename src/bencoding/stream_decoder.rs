@@ -1,131 +1,169 @@
 use std::{str, io};
-// use std::{str, io, cmp};
 use std::iter::{Iterator, Peekable};
-// use std::io::Read;
 
 use crate::bencoding::bencode::{Bencode, ListVec, DictMap};
-use crate::bencoding::error::Error;
+use crate::bencoding::decoder::MAX_DEPTH;
+use crate::bencoding::error::{Error, ErrorKind};
+use crate::bencoding::mode::Mode;
 use crate::bencoding::result::Result;
 use crate::bencoding::byte_string;
 
 
 type FileByte = std::result::Result<u8, io::Error>;
-const BENCODE_EOF_ERROR: &str = "Unexpected end of stream.";
 
 pub fn decode(data: &mut dyn Iterator<Item=FileByte>) -> Result {
-    decode_internal(&mut data.peekable())
+    decode_with_mode(data, Mode::Lenient)
 }
 
-fn decode_internal(data: &mut Peekable<&mut dyn Iterator<Item=FileByte>>) -> Result {
-    let numbers = [b'0', b'1', b'2', b'3', b'4', b'5', b'6', b'7', b'8', b'9'];
-    match data.peek() {
-        Some(Ok(b'i')) => decode_int(data),
-        Some(Ok(b'l')) => decode_list(data),
-        Some(Ok(b'd')) => decode_dictionary(data),
-        Some(Ok(byte)) => {
-            if numbers.contains(&byte) {
-                decode_str(data)
-            } else {
-                Ok(Bencode::Empty)
-            }
-        },
-        Some(Err(e)) => Err(Error::new(format!("{}", e))),
-        None => Ok(Bencode::Empty),
-    }
-}
+pub fn decode_with_mode(data: &mut dyn Iterator<Item=FileByte>, mode: Mode) -> Result {
+    let mut stream = Stream { data: data.peekable(), offset: 0, mode, depth: 0 };
 
-fn next(data: &mut dyn Iterator<Item=FileByte>) -> std::result::Result<u8, Error> {
-    match data.next() {
-        Some(Ok(v)) => Ok(v),
-        Some(Err(e)) => Err(e.into()),
-        None => Err(Error::new(BENCODE_EOF_ERROR.to_string())),
+    let value = decode_internal(&mut stream)?;
+
+    match stream.data.peek() {
+        Some(_) => Err(Error::at(ErrorKind::TrailingData, stream.offset)),
+        None => Ok(value),
     }
 }
 
-fn decode_str(data: &mut Peekable<&mut dyn Iterator<Item=FileByte>>) -> Result {
-    let mut length_as_bytes = Vec::new();
-    let mut value = next(data)?;
+/// Wraps the byte iterator to keep track of how far into the stream we are.
+struct Stream<'a> {
+    data: Peekable<&'a mut dyn Iterator<Item=FileByte>>,
+    offset: usize,
+    mode: Mode,
+    /// How many lists and dictionaries enclose the next value.
+    depth: usize,
+}
 
-    while value != b':' {
-        length_as_bytes.push(value);
-        value = next(data)?;
+impl<'a> Stream<'a> {
+    fn next(&mut self) -> std::result::Result<u8, Error> {
+        match self.data.next() {
+            Some(Ok(v)) => {
+                self.offset += 1;
+                Ok(v)
+            },
+            Some(Err(e)) => Err(e.into()),
+            None => Err(Error::at(ErrorKind::UnexpectedEof, self.offset)),
+        }
     }
 
-    let length_str = str::from_utf8(&length_as_bytes)?;
-    let length = length_str.parse::<usize>()?;
+    fn peek(&mut self) -> std::result::Result<u8, Error> {
+        match self.data.peek() {
+            Some(Ok(v)) => Ok(*v),
+            Some(Err(_)) => self.next(),
+            None => Err(Error::at(ErrorKind::UnexpectedEof, self.offset)),
+        }
+    }
+}
 
-    if length == 0 {
-        return Ok(Bencode::ByteString(Vec::<u8>::new()))
-    };
+fn decode_internal(data: &mut Stream) -> Result {
+    match data.peek()? {
+        b'i' => decode_int(data),
+        b'l' | b'd' if data.depth >= MAX_DEPTH => Err(Error::at(ErrorKind::TooDeep, data.offset)),
+        b'l' => decode_list(data),
+        b'd' => decode_dictionary(data),
+        b'0'..=b'9' => decode_str(data),
+        _ => Err(Error::at(ErrorKind::InvalidType, data.offset)),
+    }
+}
 
-    let mut counter = 0;
-    let mut byte_string = Vec::new();
+/// Reads ascii digits (optionally preceded by a `-` when `signed`) up to and
+/// including `delimiter`, returning the digits.
+fn read_number(data: &mut Stream, delimiter: u8, signed: bool, kind: ErrorKind) -> std::result::Result<(Vec<u8>, usize), Error> {
+    let start = data.offset;
+    let mut number_as_bytes = Vec::new();
 
-    while counter < length {
-        value = next(data)?;
-        byte_string.push(value);
-        counter += 1;
+    if signed && data.peek()? == b'-' {
+        number_as_bytes.push(data.next()?);
+    }
+    let sign_length = number_as_bytes.len();
+
+    loop {
+        match data.peek()? {
+            b if b == delimiter && number_as_bytes.len() > sign_length => {
+                data.mode.check_number(&number_as_bytes, start)?;
+                data.next()?;
+                return Ok((number_as_bytes, start));
+            },
+            b'0'..=b'9' => number_as_bytes.push(data.next()?),
+            _ => return Err(Error::at(kind, data.offset)),
+        }
     }
+}
+
+fn parse_number<T: str::FromStr>(number_as_bytes: &[u8], offset: usize, kind: ErrorKind) -> std::result::Result<T, Error> {
+    let number_str = str::from_utf8(number_as_bytes).map_err(|_| Error::at(kind, offset))?;
+    number_str.parse::<T>().map_err(|_| Error::at(kind, offset))
+}
 
-    return Ok(Bencode::ByteString(byte_string))
+fn decode_str(data: &mut Stream) -> Result {
+    Ok(Bencode::ByteString(read_bytes(data)?))
 }
 
-fn decode_int(data: &mut Peekable<&mut dyn Iterator<Item=FileByte>>) -> Result {
-    next(data)?;
-    let mut bencode_number_as_bytes = Vec::new();
-    let mut value = next(data)?;
+fn read_bytes(data: &mut Stream) -> std::result::Result<Vec<u8>, Error> {
+    let (length_as_bytes, start) = read_number(data, b':', false, ErrorKind::InvalidLength)?;
+    let length = parse_number::<usize>(&length_as_bytes, start, ErrorKind::InvalidLength)?;
 
-    while value != b'e' {
-        bencode_number_as_bytes.push(value);
-        value = next(data)?;
-    }
+    let mut byte_string = Vec::new();
 
-    let bencode_number_as_str = str::from_utf8(&bencode_number_as_bytes)?;
-    let number = bencode_number_as_str.parse::<i64>()?;
+    while byte_string.len() < length {
+        byte_string.push(data.next()?);
+    }
 
-    return Ok(Bencode::Number(number))
+    Ok(byte_string)
 }
 
-fn peek(data: &mut Peekable<&mut dyn Iterator<Item= FileByte>>) ->  std::result::Result<u8, Error> {
-    match data.peek() {
-        Some(Ok(v)) => Ok(*v),
-        Some(Err(e)) => Err(Error::new(format!("{}", e))),
-        None => Err(Error::new("Unexpected end of stream.".to_string())),
-    }
+fn decode_int(data: &mut Stream) -> Result {
+    data.next()?;
+    let (number_as_bytes, start) = read_number(data, b'e', true, ErrorKind::InvalidInteger)?;
+    let number = parse_number::<i64>(&number_as_bytes, start, ErrorKind::InvalidInteger)?;
+
+    Ok(Bencode::Number(number))
 }
 
-fn decode_list(data: &mut Peekable<&mut dyn Iterator<Item=FileByte>>) -> Result {
-    next(data)?;
-    let mut value = peek(data)?;
+fn decode_list(data: &mut Stream) -> Result {
+    data.next()?;
     let mut list = ListVec::new();
+    data.depth += 1;
 
-    while value != b'e' {
+    while data.peek()? != b'e' {
         let result = decode_internal(data)?;
         list.push(result);
-        value = peek(data)?;
     }
+    data.next()?;
+    data.depth -= 1;
 
-    return Ok(Bencode::List(list))
+    Ok(Bencode::List(list))
 }
 
-fn decode_dictionary(data: &mut Peekable<&mut dyn Iterator<Item=FileByte>>) -> Result {
-    next(data)?;
-    let mut value = peek(data)?;
+fn decode_dictionary(data: &mut Stream) -> Result {
+    data.next()?;
     let mut dict = DictMap::new();
-
-    while value != b'e' {
-        let result = decode_internal(data)?;
-        let key = match result {
-            Bencode::ByteString(v) => byte_string::ByteString::from_vec(v),
-            _ => return Err(Error::new(format!("Dictionary key was `{}`, expected a ByteString", result))),
-        };
+    let mut previous_key: Option<Vec<u8>> = None;
+    data.depth += 1;
+
+    loop {
+        match data.peek()? {
+            b'e' => break,
+            b'0'..=b'9' => {},
+            _ => return Err(Error::at(ErrorKind::InvalidKey, data.offset)),
+        }
+
+        let offset = data.offset;
+        let key = read_bytes(data)?;
+        data.mode.check_key_order(previous_key.as_deref(), &key, offset)?;
+        if data.mode == Mode::Strict {
+            previous_key = Some(key.clone());
+        }
+        let key = byte_string::ByteString::from_vec(key);
 
         let bencode_value = decode_internal(data)?;
         dict.insert(key, bencode_value);
-        value = peek(data)?;
     }
+    data.next()?;
+    data.depth -= 1;
 
-    return Ok(Bencode::Dict(dict))
+    Ok(Bencode::Dict(dict))
 }
 
 #[cfg(test)]
@@ -155,7 +193,7 @@ mod tests {
             Err(e) => e,
         };
 
-        assert_eq!(Error::new(BENCODE_EOF_ERROR.to_string()), result);
+        assert_eq!((ErrorKind::UnexpectedEof, Some(1)), (result.kind(), result.offset()));
     }
 
     #[test]
@@ -166,7 +204,7 @@ mod tests {
             Err(e) => e,
         };
 
-        assert_eq!(Error::new("Dictionary key was `3`, expected a ByteString".to_string()), result);
+        assert_eq!((ErrorKind::InvalidKey, Some(10)), (result.kind(), result.offset()));
     }
 
     #[test]
@@ -222,7 +260,7 @@ mod tests {
             Err(e) => e,
         };
 
-        assert_eq!(Error::new(BENCODE_EOF_ERROR.to_string()), result);
+        assert_eq!((ErrorKind::UnexpectedEof, Some(1)), (result.kind(), result.offset()));
     }
 
     #[test]
@@ -296,7 +334,7 @@ mod tests {
             Err(e) => e,
         };
 
-        assert_eq!(Error::new(BENCODE_EOF_ERROR.to_string()), result);
+        assert_eq!((ErrorKind::UnexpectedEof, Some(10)), (result.kind(), result.offset()));
     }
 
     #[test]
@@ -307,7 +345,7 @@ mod tests {
             Err(e) => e,
         };
 
-        assert_eq!(Error::new(BENCODE_EOF_ERROR.to_string()), result);
+        assert_eq!((ErrorKind::UnexpectedEof, Some(2)), (result.kind(), result.offset()));
     }
 
     #[test]
@@ -320,10 +358,9 @@ mod tests {
 
     #[test]
     fn test_empty_input() {
-        let s = b"".to_vec();
-        let result = decode(s);
+        let result = decode(b"".to_vec()).unwrap_err();
 
-        assert_eq!(Bencode::Empty, result.unwrap());
+        assert_eq!((ErrorKind::UnexpectedEof, Some(0)), (result.kind(), result.offset()));
     }
 
     #[test]
@@ -363,4 +400,86 @@ mod tests {
 
         assert_eq!(Bencode::Dict(d), result.unwrap());
     }
+
+    #[test]
+    fn test_can_decode_a_key_after_a_nested_list() {
+        let s = b"d3:fool4:spame3:quxi3ee".to_vec();
+        let result = decode(s);
+
+        let mut d = DictMap::new();
+        d.insert(
+            byte_string::ByteString::from_vec(b"foo".to_vec()),
+            Bencode::List(vec![Bencode::ByteString(b"spam".to_vec())]),
+        );
+        d.insert(
+            byte_string::ByteString::from_vec(b"qux".to_vec()),
+            Bencode::Number(3),
+        );
+
+        assert_eq!(Bencode::Dict(d), result.unwrap());
+    }
+
+    #[test]
+    fn test_err_on_unknown_type_byte() {
+        let result = decode(b"li3ex3:fooe".to_vec()).unwrap_err();
+
+        assert_eq!((ErrorKind::InvalidType, Some(4)), (result.kind(), result.offset()));
+    }
+
+    #[test]
+    fn test_err_on_invalid_integer() {
+        let result = decode(b"i3.5e".to_vec()).unwrap_err();
+
+        assert_eq!((ErrorKind::InvalidInteger, Some(2)), (result.kind(), result.offset()));
+    }
+
+    #[test]
+    fn test_err_when_nested_too_deeply() {
+        let mut s = vec![b'l'; MAX_DEPTH];
+        s.extend(vec![b'e'; MAX_DEPTH]);
+        assert!(decode(s).is_ok());
+
+        let result = decode(vec![b'l'; 200_000]).unwrap_err();
+        assert_eq!((ErrorKind::TooDeep, Some(MAX_DEPTH)), (result.kind(), result.offset()));
+
+        let mut s = b"d1:a".repeat(MAX_DEPTH);
+        s.extend(b"de");
+        let result = decode(s).unwrap_err();
+        assert_eq!((ErrorKind::TooDeep, Some(4 * MAX_DEPTH)), (result.kind(), result.offset()));
+    }
+
+    #[test]
+    fn test_err_on_trailing_data() {
+        let result = decode(b"i42ei43e".to_vec()).unwrap_err();
+
+        assert_eq!((ErrorKind::TrailingData, Some(4)), (result.kind(), result.offset()));
+    }
+
+    fn decode_strict(data: &[u8]) -> Result {
+        decode_with_mode(&mut data.bytes(), Mode::Strict)
+    }
+
+    #[test]
+    fn test_strict_rejects_non_canonical_input() {
+        let cases: Vec<(&[u8], ErrorKind, usize)> = vec![
+            (b"i-0e", ErrorKind::NegativeZero, 1),
+            (b"i03e", ErrorKind::LeadingZero, 1),
+            (b"04:spam", ErrorKind::LeadingZero, 0),
+            (b"d4:spami3e3:fooi4ee", ErrorKind::UnsortedKeys, 10),
+            (b"d3:fooi1e3:fooi2ee", ErrorKind::DuplicateKey, 9),
+        ];
+
+        for (data, kind, offset) in cases {
+            let result = decode_strict(data).unwrap_err();
+            assert_eq!((kind, Some(offset)), (result.kind(), result.offset()));
+            assert!(decode(data.to_vec()).is_ok());
+        }
+    }
+
+    #[test]
+    fn test_strict_accepts_canonical_input() {
+        let result = decode_strict(b"d0:0:3:fool4:spami0ee4:spami-3ee");
+
+        assert!(result.is_ok());
+    }
 }
@@ -0,0 +1,212 @@
+use std::fmt;
+use std::net::Ipv6Addr;
+use percent_encoding::{percent_encode, NON_ALPHANUMERIC};
+
+use crate::torrent::info_hash::InfoHash;
+
+#[derive(Eq, PartialEq, Clone, Copy, Debug)]
+pub enum Event {
+    Started,
+    Stopped,
+    Completed,
+}
+
+impl Event {
+    /// The `event` field of a UDP announce (BEP 15), 0 means none.
+    pub fn udp_id(self) -> u32 {
+        match self {
+            Event::Completed => 1,
+            Event::Started => 2,
+            Event::Stopped => 3,
+        }
+    }
+
+    pub fn as_str(self) -> &'static str {
+        match self {
+            Event::Started => "started",
+            Event::Stopped => "stopped",
+            Event::Completed => "completed",
+        }
+    }
+}
+
+impl fmt::Display for Event {
+    fn fmt(&self, fmt: &mut fmt::Formatter) -> fmt::Result {
+        write!(fmt, "{}", self.as_str())
+    }
+}
+
+/// The parameters of a tracker announce, `compact` defaults to `true` and the
+/// optional parameters are left out of the url when unset. The UDP tracker
+/// packs the same fields into its binary announce.
+#[derive(Eq, PartialEq, Clone, Debug)]
+pub struct AnnounceRequest {
+    pub(crate) info_hash: InfoHash,
+    pub(crate) peer_id: [u8; 20],
+    pub(crate) port: u16,
+    pub(crate) uploaded: u64,
+    pub(crate) downloaded: u64,
+    pub(crate) left: u64,
+    pub(crate) compact: bool,
+    pub(crate) event: Option<Event>,
+    pub(crate) numwant: Option<u32>,
+    pub(crate) key: Option<u32>,
+    pub(crate) tracker_id: Option<String>,
+    pub(crate) ipv6: Option<Ipv6Addr>,
+}
+
+impl AnnounceRequest {
+    pub fn new(info_hash: InfoHash, peer_id: [u8; 20], port: u16) -> Self {
+        Self {
+            info_hash,
+            peer_id,
+            port,
+            uploaded: 0,
+            downloaded: 0,
+            left: 0,
+            compact: true,
+            event: None,
+            numwant: None,
+            key: None,
+            tracker_id: None,
+            ipv6: None,
+        }
+    }
+
+    pub fn uploaded(mut self, uploaded: u64) -> Self {
+        self.uploaded = uploaded;
+        self
+    }
+
+    pub fn downloaded(mut self, downloaded: u64) -> Self {
+        self.downloaded = downloaded;
+        self
+    }
+
+    pub fn left(mut self, left: u64) -> Self {
+        self.left = left;
+        self
+    }
+
+    pub fn compact(mut self, compact: bool) -> Self {
+        self.compact = compact;
+        self
+    }
+
+    pub fn event(mut self, event: Option<Event>) -> Self {
+        self.event = event;
+        self
+    }
+
+    pub fn numwant(mut self, numwant: Option<u32>) -> Self {
+        self.numwant = numwant;
+        self
+    }
+
+    /// Lets a tracker recognise us when our ip changes, HTTP sends it as hex
+    /// and UDP as is so both carry the same key.
+    pub fn key(mut self, key: Option<u32>) -> Self {
+        self.key = key;
+        self
+    }
+
+    pub fn tracker_id(mut self, tracker_id: Option<String>) -> Self {
+        self.tracker_id = tracker_id;
+        self
+    }
+
+    /// Our IPv6 address, sent so that a tracker reached over IPv4 can still
+    /// hand us out to IPv6 peers (BEP 7).
+    pub fn ipv6(mut self, ipv6: Option<Ipv6Addr>) -> Self {
+        self.ipv6 = ipv6;
+        self
+    }
+
+    /// Appends the parameters to `announce`, keeping any query string the
+    /// announce url already has.
+    pub fn url(&self, announce: &str) -> String {
+        let separator = match announce.find('?') {
+            None => "?",
+            Some(_) if announce.ends_with('?') || announce.ends_with('&') => "",
+            Some(_) => "&",
+        };
+
+        let mut params = vec![
+            format!("info_hash={}", encode(self.info_hash.as_bytes())),
+            format!("peer_id={}", encode(&self.peer_id)),
+            format!("port={}", self.port),
+            format!("uploaded={}", self.uploaded),
+            format!("downloaded={}", self.downloaded),
+            format!("left={}", self.left),
+            format!("compact={}", if self.compact { 1 } else { 0 }),
+        ];
+
+        if let Some(event) = self.event {
+            params.push(format!("event={}", event));
+        }
+        if let Some(numwant) = self.numwant {
+            params.push(format!("numwant={}", numwant));
+        }
+        if let Some(key) = self.key {
+            params.push(format!("key={:08x}", key));
+        }
+        if let Some(tracker_id) = &self.tracker_id {
+            params.push(format!("trackerid={}", encode(tracker_id.as_bytes())));
+        }
+        if let Some(ipv6) = self.ipv6 {
+            params.push(format!("ipv6={}", encode(ipv6.to_string().as_bytes())));
+        }
+
+        format!("{}{}{}", announce, separator, params.join("&"))
+    }
+}
+
+fn encode(bytes: &[u8]) -> String {
+    percent_encode(bytes, NON_ALPHANUMERIC).to_string()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn request() -> AnnounceRequest {
+        let info_hash = InfoHash::digest(b"d6:lengthi4e4:name5:derek12:piece lengthi100e6:pieces3:z\xc3\x287:privatei1ee");
+        AnnounceRequest::new(info_hash, *b"-RS0001-abcdefghij.k", 6881)
+    }
+
+    const INFO_HASH: &str = "info_hash=%3AJ%9A%B3%D7%3E%D0t%BDD%DDz%A5%EE%9D%DE%8C%AD%28%AE";
+
+    #[test]
+    fn test_url_with_required_parameters() {
+        assert_eq!(
+            format!("http://t/announce?{}&peer_id=%2DRS0001%2Dabcdefghij%2Ek&port=6881&uploaded=0&downloaded=0&left=0&compact=1", INFO_HASH),
+            request().url("http://t/announce"),
+        );
+    }
+
+    #[test]
+    fn test_url_with_optional_parameters() {
+        let request = request()
+            .uploaded(10)
+            .downloaded(20)
+            .left(30)
+            .compact(false)
+            .event(Some(Event::Started))
+            .numwant(Some(50))
+            .key(Some(0xab12))
+            .tracker_id(Some("t&1".to_string()))
+            .ipv6(Some("2001:db8::1".parse().unwrap()));
+
+        assert_eq!(
+            format!("yes?{}&peer_id=%2DRS0001%2Dabcdefghij%2Ek&port=6881&uploaded=10&downloaded=20&left=30&compact=0&event=started&numwant=50&key=0000ab12&trackerid=t%261&ipv6=2001%3Adb8%3A%3A1", INFO_HASH),
+            request.url("yes"),
+        );
+    }
+
+    #[test]
+    fn test_url_keeps_an_existing_query_string() {
+        assert!(request().url("http://t/announce?passkey=abc").starts_with(&format!("http://t/announce?passkey=abc&{}&", INFO_HASH)));
+        assert!(request().url("http://t/announce?passkey=abc&").starts_with(&format!("http://t/announce?passkey=abc&{}&", INFO_HASH)));
+        assert!(request().url("http://t/announce?").starts_with(&format!("http://t/announce?{}&", INFO_HASH)));
+    }
+}
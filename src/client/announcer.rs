@@ -0,0 +1,410 @@
+use std::cmp;
+use std::collections::VecDeque;
+use std::time::Duration;
+use tokio::sync::mpsc;
+use tokio::task::JoinHandle;
+use tokio::time::{self, Instant};
+
+use crate::client::announce_request::Event;
+use crate::client::client::Client;
+use crate::client::error::Error;
+use crate::torrent::tracker_info::TrackerInfo;
+
+/// Trackers asking for a shorter interval than this are not taken literally.
+const MIN_INTERVAL: Duration = Duration::from_secs(30);
+const FAILURE_BACKOFF: Duration = Duration::from_secs(15);
+const MAX_BACKOFF: Duration = Duration::from_secs(30 * 60);
+
+/// How long each of the final `completed` and `stopped` announces may take
+/// before shutdown goes ahead without it.
+pub const DEFAULT_SHUTDOWN_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// When the next announce is due. After a success the tracker's `interval` is
+/// honoured, after a failure we back off exponentially, and announces we send
+/// early, e.g. `completed`, wait for the tracker's `min interval`.
+#[derive(Eq, PartialEq, Clone, Debug)]
+pub struct Schedule {
+    failures: u32,
+    last_success: Option<Instant>,
+    min_interval: Duration,
+}
+
+impl Schedule {
+    pub fn new() -> Self {
+        Self { failures: 0, last_success: None, min_interval: Duration::from_secs(0) }
+    }
+
+    pub fn succeeded(&mut self, now: Instant, tracker_info: &TrackerInfo) -> Instant {
+        self.failures = 0;
+        self.last_success = Some(now);
+        self.min_interval = seconds(tracker_info.min_interval.unwrap_or(0));
+
+        let interval = cmp::max(seconds(tracker_info.interval), self.min_interval);
+        now + cmp::max(interval, MIN_INTERVAL)
+    }
+
+    pub fn failed(&mut self, now: Instant) -> Instant {
+        let backoff = FAILURE_BACKOFF * 2u32.saturating_pow(cmp::min(self.failures, 16));
+        self.failures += 1;
+
+        now + cmp::min(backoff, MAX_BACKOFF)
+    }
+
+    /// The earliest an announce outside of the regular interval may be sent.
+    pub fn earliest(&self, now: Instant) -> Instant {
+        match self.last_success {
+            Some(last) => cmp::max(now, last + self.min_interval),
+            None => now,
+        }
+    }
+}
+
+impl Default for Schedule {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+fn seconds(value: i64) -> Duration {
+    Duration::from_secs(cmp::max(value, 0) as u64)
+}
+
+#[derive(Debug)]
+enum Command {
+    Progress { uploaded: u64, downloaded: u64 },
+    Completed,
+    Stop,
+}
+
+/// Re-announces the client's torrent in the background. Dropping the
+/// `Announcer` stops the loop the same way `shutdown` does.
+pub struct Announcer {
+    commands: mpsc::Sender<Command>,
+    handle: JoinHandle<Client>,
+}
+
+impl Announcer {
+    /// Starts the loop, the first announce carries the `started` event.
+    pub fn spawn(client: Client) -> Self {
+        Self::spawn_with_shutdown_timeout(client, DEFAULT_SHUTDOWN_TIMEOUT)
+    }
+
+    /// Like `spawn`, with a different bound on each of the final announces.
+    pub fn spawn_with_shutdown_timeout(client: Client, shutdown_timeout: Duration) -> Self {
+        let (commands, receiver) = mpsc::channel(8);
+        let handle = tokio::spawn(run(client, receiver, shutdown_timeout));
+
+        Self { commands, handle }
+    }
+
+    /// Updates the transfer totals reported on the next announce.
+    pub async fn progress(&mut self, uploaded: u64, downloaded: u64) -> Result<(), Error> {
+        self.send(Command::Progress { uploaded, downloaded }).await
+    }
+
+    /// Sends the `completed` event as soon as `min interval` allows.
+    pub async fn completed(&mut self) -> Result<(), Error> {
+        self.send(Command::Completed).await
+    }
+
+    /// Sends the `stopped` event and hands the client back.
+    pub async fn shutdown(mut self) -> Result<Client, Error> {
+        self.send(Command::Stop).await?;
+        Ok(self.handle.await?)
+    }
+
+    async fn send(&mut self, command: Command) -> Result<(), Error> {
+        match self.commands.send(command).await {
+            Ok(()) => Ok(()),
+            Err(_) => Err(Error::new("Announcer has stopped.".to_string())),
+        }
+    }
+}
+
+async fn run(mut client: Client, mut commands: mpsc::Receiver<Command>, shutdown_timeout: Duration) -> Client {
+    let mut schedule = Schedule::new();
+    // Events still to be sent, in order, so that `completed` can't overtake
+    // a `started` that hasn't gone through yet.
+    let mut events = VecDeque::from(vec![Event::Started]);
+    let mut next_announce = Instant::now();
+
+    loop {
+        let received = tokio::select! {
+            _ = time::delay_until(next_announce) => {
+                let (result, deferred) = announce(&mut client, events.front().cloned(), &mut commands).await;
+                next_announce = match result {
+                    Some(Ok(tracker_info)) => {
+                        events.pop_front();
+                        let next = schedule.succeeded(Instant::now(), &tracker_info);
+                        match events.front() {
+                            Some(_) => schedule.earliest(Instant::now()),
+                            None => next,
+                        }
+                    },
+                    Some(Err(_)) => schedule.failed(Instant::now()),
+                    None => break,
+                };
+                deferred
+            },
+            command = commands.recv() => match command {
+                Some(Command::Stop) | None => break,
+                Some(command) => vec![command],
+            },
+        };
+
+        for command in received {
+            match command {
+                Command::Progress { uploaded, downloaded } => client.set_progress(uploaded, downloaded),
+                Command::Completed => {
+                    if !events.contains(&Event::Completed) {
+                        events.push_back(Event::Completed);
+                    }
+                    if events.front() == Some(&Event::Completed) {
+                        next_announce = schedule.earliest(Instant::now());
+                    }
+                },
+                Command::Stop => unreachable!("Stop ends the loop when it is received."),
+            }
+        }
+    }
+
+    // The tracker only needs to hear about these, the responses carry nothing
+    // we use. A pending `completed` still counts towards the swarm's totals.
+    // A tracker that doesn't answer mustn't hold up the shutdown.
+    if events.contains(&Event::Completed) {
+        let _ = time::timeout(shutdown_timeout, client.announce(Some(Event::Completed))).await;
+    }
+    let _ = time::timeout(shutdown_timeout, client.announce(Some(Event::Stopped))).await;
+    client
+}
+
+/// Announces `event` while still listening for commands, since trying every
+/// tracker can take minutes. `Stop` abandons the announce and gives `None`,
+/// other commands are handed back to be handled once the announce is done.
+async fn announce(client: &mut Client, event: Option<Event>, commands: &mut mpsc::Receiver<Command>) -> (Option<Result<TrackerInfo, Error>>, Vec<Command>) {
+    let mut deferred = Vec::new();
+    let announce = client.announce(event);
+    tokio::pin!(announce);
+
+    loop {
+        tokio::select! {
+            result = &mut announce => return (Some(result.cloned()), deferred),
+            command = commands.recv() => match command {
+                Some(Command::Stop) | None => return (None, deferred),
+                Some(command) => deferred.push(command),
+            },
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::net::SocketAddr;
+    use std::sync::Arc;
+    use mockito::{mock, Matcher};
+    use tokio::io::{AsyncReadExt, AsyncWriteExt};
+    use tokio::net::TcpListener;
+    use tokio::sync::Notify;
+
+    use crate::bencoding::bencode::DictMap;
+    use crate::torrent::torrent::Torrent;
+    use crate::torrent::torrent_info::TorrentInfo;
+    use crate::torrent::info_hash::InfoHash;
+    use crate::torrent::file_layout::Layout;
+
+    fn tracker_info(interval: i64, min_interval: Option<i64>) -> TrackerInfo {
+        TrackerInfo {
+            complete: None,
+            downloaded: None,
+            incomplete: None,
+            interval,
+            min_interval,
+            warning_message: None,
+            tracker_id: None,
+            peers: Vec::new(),
+        }
+    }
+
+    fn client(announce: String) -> Client {
+        let torrent = Torrent {
+            announce: Some(announce),
+            announce_list: None,
+            created_by: None,
+            encoding: None,
+            creation_date: None,
+            info: TorrentInfo {
+                layout: Layout::SingleFile { length: 4 },
+                name: "derek.jar".to_string(),
+                piece_length: 100,
+                private: None,
+                pieces: vec![b'z'; 20],
+                extra: DictMap::new(),
+            },
+            info_hash: InfoHash::new([0; 20]),
+            extra: DictMap::new(),
+        };
+        Client::new(torrent)
+    }
+
+    /// Answers announces of `event` at `path`, `announced` is notified when
+    /// one arrives. Expects one announce unless told otherwise.
+    fn event_mock(path: &str, event: &str, announced: &Arc<Notify>) -> mockito::Mock {
+        let announced = announced.clone();
+        mock("GET", path)
+            .match_query(Matcher::UrlEncoded("event".into(), event.into()))
+            .with_status(200)
+            .with_body_from_fn(move |body| {
+                announced.notify();
+                body.write_all(b"d8:intervali1800e5:peers0:e")
+            })
+    }
+
+    /// A tracker that never answers its first request and sends an empty peer
+    /// list for the later ones, `hanging` is notified once the first arrived
+    /// and the request lines of the others are passed on.
+    async fn hanging_tracker(hanging: Arc<Notify>, mut requests: mpsc::Sender<String>) -> SocketAddr {
+        let mut listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+
+        tokio::spawn(async move {
+            let (first, _) = listener.accept().await.unwrap();
+            hanging.notify();
+
+            while let Ok((mut stream, _)) = listener.accept().await {
+                let mut request = Vec::new();
+                let mut buf = [0; 1024];
+                while !request.windows(4).any(|end| end == b"\r\n\r\n") {
+                    let read = stream.read(&mut buf).await.unwrap();
+                    request.extend_from_slice(&buf[..read]);
+                }
+                let line = String::from_utf8_lossy(&request).lines().next().unwrap().to_string();
+                requests.send(line).await.unwrap();
+
+                let body = "d8:intervali1800e5:peers0:e";
+                let response = format!("HTTP/1.1 200 OK\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{}", body.len(), body);
+                stream.write_all(response.as_bytes()).await.unwrap();
+            }
+            drop(first);
+        });
+
+        addr
+    }
+
+    /// A tracker that accepts connections and never answers, `connected` is
+    /// notified for each one.
+    async fn silent_tracker(connected: Arc<Notify>) -> SocketAddr {
+        let mut listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+
+        tokio::spawn(async move {
+            let mut streams = Vec::new();
+            while let Ok((stream, _)) = listener.accept().await {
+                streams.push(stream);
+                connected.notify();
+            }
+        });
+
+        addr
+    }
+
+    #[test]
+    fn test_succeeded_honours_intervals() {
+        let now = Instant::now();
+        let mut schedule = Schedule::new();
+
+        assert_eq!(now + Duration::from_secs(1800), schedule.succeeded(now, &tracker_info(1800, Some(900))));
+        assert_eq!(now + Duration::from_secs(900), schedule.earliest(now));
+        assert_eq!(now + Duration::from_secs(1000), schedule.succeeded(now, &tracker_info(10, Some(1000))));
+        assert_eq!(now + MIN_INTERVAL, schedule.succeeded(now, &tracker_info(0, None)));
+        assert_eq!(now, schedule.earliest(now));
+    }
+
+    #[test]
+    fn test_failed_backs_off_exponentially() {
+        let now = Instant::now();
+        let mut schedule = Schedule::new();
+
+        assert_eq!(now + Duration::from_secs(15), schedule.failed(now));
+        assert_eq!(now + Duration::from_secs(30), schedule.failed(now));
+        assert_eq!(now + Duration::from_secs(60), schedule.failed(now));
+        for _ in 0..20 {
+            schedule.failed(now);
+        }
+        assert_eq!(now + MAX_BACKOFF, schedule.failed(now));
+
+        schedule.succeeded(now, &tracker_info(1800, None));
+        assert_eq!(now + Duration::from_secs(15), schedule.failed(now));
+    }
+
+    #[test]
+    fn test_earliest_before_any_announce() {
+        let now = Instant::now();
+        assert_eq!(now, Schedule::new().earliest(now));
+    }
+
+    #[tokio::test]
+    async fn test_sends_started_completed_and_stopped() {
+        let (announced, unused) = (Arc::new(Notify::new()), Arc::new(Notify::new()));
+        let started = event_mock("/announcer", "started", &announced).create();
+        let completed = event_mock("/announcer", "completed", &announced).expect_at_least(1).create();
+        let stopped = event_mock("/announcer", "stopped", &unused).create();
+
+        let mut announcer = Announcer::spawn(client(format!("{}/announcer", mockito::server_url())));
+        announced.notified().await;
+        started.assert();
+
+        announcer.progress(0, 4).await.unwrap();
+        announcer.completed().await.unwrap();
+        announced.notified().await;
+        announcer.shutdown().await.unwrap();
+
+        completed.assert();
+        stopped.assert();
+    }
+
+    #[tokio::test]
+    async fn test_completed_waits_for_started() {
+        let (unused, announced_completed) = (Arc::new(Notify::new()), Arc::new(Notify::new()));
+        let started = event_mock("/queued", "started", &unused).create();
+        // Shutting down may abandon the announce after the tracker saw it, it
+        // is then sent again.
+        let completed = event_mock("/queued", "completed", &announced_completed).expect_at_least(1).create();
+        let stopped = event_mock("/queued", "stopped", &unused).create();
+
+        let mut announcer = Announcer::spawn(client(format!("{}/queued", mockito::server_url())));
+        announcer.completed().await.unwrap();
+        announced_completed.notified().await;
+        announcer.shutdown().await.unwrap();
+
+        started.assert();
+        completed.assert();
+        stopped.assert();
+    }
+
+    #[tokio::test]
+    async fn test_shutdown_does_not_wait_for_a_hanging_announce() {
+        let hanging = Arc::new(Notify::new());
+        let (sender, mut requests) = mpsc::channel(4);
+        let addr = hanging_tracker(hanging.clone(), sender).await;
+
+        let announcer = Announcer::spawn(client(format!("http://{}/announce", addr)));
+        hanging.notified().await;
+        announcer.shutdown().await.unwrap();
+
+        assert!(requests.recv().await.unwrap().contains("event=stopped"));
+    }
+
+    #[tokio::test]
+    async fn test_shutdown_gives_up_on_a_silent_tracker() {
+        let connected = Arc::new(Notify::new());
+        let addr = silent_tracker(connected.clone()).await;
+
+        let client = client(format!("http://{}/announce", addr));
+        let mut announcer = Announcer::spawn_with_shutdown_timeout(client, Duration::from_millis(50));
+        connected.notified().await;
+        announcer.completed().await.unwrap();
+
+        assert!(time::timeout(Duration::from_secs(5), announcer.shutdown()).await.is_ok());
+    }
+}
@@ -1,38 +1,446 @@
 use std::fmt;
 use std::result::Result;
+use std::collections::BTreeMap;
+use std::net::{Ipv6Addr, SocketAddr, UdpSocket};
+use std::path::Path;
+use std::sync::Arc;
+use rand::Rng;
+use rand::distributions::Alphanumeric;
+use tokio::sync::{mpsc, Mutex, Notify};
 
 use crate::torrent::torrent::Torrent;
 use crate::torrent::tracker_info::TrackerInfo;
+use crate::torrent::tracker_response::TrackerResponse;
+use crate::torrent::scrape_info::ScrapeInfo;
 use crate::bencoding::decoder;
 use crate::client::error::Error;
+use crate::client::tracker_manager::TrackerManager;
+use crate::client::resume_data::ResumeData;
+use crate::client::announce_request::{AnnounceRequest, Event};
+use crate::client::udp_tracker::UdpTracker;
+use crate::client::scrape;
+use crate::download::block::Block;
+use crate::download::downloader::Downloader;
+use crate::download::recheck::{self, Progress};
+use crate::download::session::Session;
+use crate::peer::connection::{Connection, DEFAULT_HANDSHAKE_TIMEOUT};
+use crate::peer::handshake::Handshake;
+use crate::storage::storage::{FileStat, Storage};
+use crate::torrent::peer::Peer;
+
+const PEER_ID_PREFIX: &[u8; 8] = b"-RS0001-";
+const DEFAULT_PORT: u16 = 6881;
+/// Verified pieces waiting to be written to storage.
+const PIECE_QUEUE: usize = 16;
 
 #[derive(Eq, PartialEq, Clone, Debug)]
 pub struct Client {
     torrent: Torrent,
+    peer_id: [u8; 20],
+    port: u16,
+    ipv6: Option<Ipv6Addr>,
+    uploaded: u64,
+    downloaded: u64,
+    trackers: TrackerManager,
+    udp_trackers: BTreeMap<String, UdpTracker>,
     tracker_info: Option<TrackerInfo>,
+    /// The pieces in storage, known once it has been rechecked.
+    have: Option<Vec<bool>>,
+    /// Blocks of unfinished pieces, kept for the next download.
+    partial_blocks: Vec<(Block, Vec<u8>)>,
+    /// Peers from resume data, tried along with the ones trackers return.
+    known_peers: Vec<SocketAddr>,
+    /// Why peers were dropped during downloads.
+    peer_errors: BTreeMap<SocketAddr, String>,
 }
 
 impl Client {
     pub fn new(torrent: Torrent) -> Self {
         Self {
+            trackers: TrackerManager::new(torrent.trackers()),
             torrent,
+            peer_id: generate_peer_id(),
+            port: DEFAULT_PORT,
+            ipv6: None,
+            uploaded: 0,
+            downloaded: 0,
+            udp_trackers: BTreeMap::new(),
             tracker_info: None,
+            have: None,
+            partial_blocks: Vec::new(),
+            known_peers: Vec::new(),
+            peer_errors: BTreeMap::new(),
         }
     }
 
+    pub fn peer_id(&self) -> &[u8; 20] {
+        &self.peer_id
+    }
+
+    /// Sets the IPv6 address sent to trackers, none is sent by default.
+    pub fn set_ipv6(&mut self, ipv6: Option<Ipv6Addr>) {
+        self.ipv6 = ipv6;
+    }
+
+    /// Sends trackers the address of our route to the IPv6 internet, if there
+    /// is one, and returns it.
+    pub fn detect_ipv6(&mut self) -> Option<Ipv6Addr> {
+        self.ipv6 = local_ipv6();
+        self.ipv6
+    }
+
+    /// Which pieces we have, `None` until a recheck or a download.
+    pub fn have(&self) -> Option<&[bool]> {
+        self.have.as_deref()
+    }
+
+    /// The last error of each peer that was dropped during a download, e.g.
+    /// because its handshake failed or it stopped responding.
+    pub fn peer_errors(&self) -> &BTreeMap<SocketAddr, String> {
+        &self.peer_errors
+    }
+
+    pub fn trackers(&self) -> &TrackerManager {
+        &self.trackers
+    }
+
+    /// Records the transfer totals reported to trackers.
+    pub fn set_progress(&mut self, uploaded: u64, downloaded: u64) {
+        self.uploaded = uploaded;
+        self.downloaded = downloaded;
+    }
+
+    /// Announces once, with `started` the first time.
     pub async fn tracker_info(&mut self) -> Result<&TrackerInfo, Error> {
-        let announce_url = self.torrent.announce_url()?;
-        let uri: hyper::Uri = announce_url.parse()?;
+        let event = match self.tracker_info {
+            None => Some(Event::Started),
+            Some(_) => None,
+        };
+        self.announce(event).await
+    }
+
+    /// Announces `event` to each tracker in turn until one responds, a
+    /// `warning message` from the tracker is kept on the returned `TrackerInfo`.
+    pub async fn announce(&mut self, event: Option<Event>) -> Result<&TrackerInfo, Error> {
+        let mut last_error = Error::new("Torrent has no trackers.".to_string());
+        let request = self.announce_request().event(event);
+
+        for url in self.trackers.urls() {
+            let request = request.clone().tracker_id(self.trackers.tracker_id(&url));
+            match self.announce_to(&request, &url).await {
+                Ok(tracker_info) => {
+                    if let Some(tracker_id) = &tracker_info.tracker_id {
+                        self.trackers.set_tracker_id(&url, tracker_id.clone());
+                    }
+                    self.trackers.mark_success(&url);
+                    self.tracker_info = Some(tracker_info);
+                    return Ok(self.tracker_info.as_ref().unwrap());
+                },
+                Err(e) => {
+                    self.trackers.mark_failure(&url, format!("{}", e));
+                    last_error = e;
+                },
+            }
+        }
+
+        Err(last_error)
+    }
+
+    /// Asks each tracker in turn for the torrent's swarm statistics, stopping
+    /// at the first that answers.
+    pub async fn scrape(&mut self) -> Result<ScrapeInfo, Error> {
+        let mut last_error = Error::new("Torrent has no trackers.".to_string());
+        let info_hashes = [self.torrent.info_hash];
+
+        for url in self.trackers.urls() {
+            let result = if url.starts_with("udp://") {
+                match self.udp_tracker(&url) {
+                    Ok(tracker) => scrape::scrape_udp(tracker, &info_hashes).await,
+                    Err(e) => Err(e),
+                }
+            } else {
+                scrape::scrape_http(&url, &info_hashes).await
+            };
+
+            match result.map(|mut stats| stats.remove(&self.torrent.info_hash)) {
+                Ok(Some(stats)) => return Ok(stats),
+                Ok(None) => last_error = Error::new(format!("Tracker has no statistics for {}.", self.torrent.info_hash)),
+                Err(e) => last_error = e,
+            }
+        }
+
+        Err(last_error)
+    }
+
+    /// Hashes every piece in `storage` to find the ones we already have,
+    /// `progress` is called after each piece. Later downloads only fetch the
+    /// rest.
+    pub async fn recheck<F>(&mut self, storage: Arc<dyn Storage>, progress: F) -> Result<&[bool], Error>
+        where F: FnMut(Progress) + Send + 'static
+    {
+        let info = self.torrent.info.clone();
+        let have = tokio::task::spawn_blocking(move || recheck::recheck(&info, storage.as_ref(), progress)).await?;
+        self.have = Some(have);
+        Ok(self.have.as_ref().unwrap())
+    }
+
+    /// A snapshot of what we know about the download, to be saved and loaded
+    /// on the next start instead of rechecking `storage`.
+    pub fn resume_data(&self, storage: &dyn Storage) -> Result<ResumeData, Error> {
+        let have = match &self.have {
+            Some(have) => have.clone(),
+            None => return Err(Error::new("Pieces are not known before a recheck or a download.".to_string())),
+        };
+
+        let mut peers = self.known_peers.clone();
+        for peer in self.tracker_info.iter().flat_map(|tracker_info| &tracker_info.peers) {
+            if !peers.contains(&peer.addr) {
+                peers.push(peer.addr);
+            }
+        }
+
+        Ok(ResumeData {
+            info_hash: self.torrent.info_hash,
+            have,
+            partial_blocks: self.partial_blocks.clone(),
+            files: file_stats(storage)?,
+            uploaded: self.uploaded,
+            downloaded: self.downloaded,
+            peers,
+            trackers: self.trackers.tiers().to_vec(),
+        })
+    }
+
+    /// Writes `resume_data` bencoded to `path`.
+    pub async fn save_resume_data(&self, storage: &dyn Storage, path: &Path) -> Result<(), Error> {
+        let data = self.resume_data(storage)?.encode();
+        tokio::fs::write(path, data).await?;
+        Ok(())
+    }
+
+    /// Restores the resume data saved at `path` when it was saved for this
+    /// torrent and the files in `storage` haven't changed since. Otherwise the
+    /// error says why, nothing is restored and the next download rechecks.
+    pub async fn load_resume_data(&mut self, storage: &dyn Storage, path: &Path) -> Result<(), Error> {
+        let resume = ResumeData::from_bytes(&tokio::fs::read(path).await?)?;
+        resume.check(&self.torrent, &file_stats(storage)?)?;
+        Downloader::new(&self.torrent.info).with_have(&resume.have)?.with_partial_blocks(&resume.partial_blocks)?;
+
+        self.have = Some(resume.have);
+        self.partial_blocks = resume.partial_blocks;
+        self.set_progress(resume.uploaded, resume.downloaded);
+        self.known_peers = resume.peers;
+
+        // The saved order is only kept while the torrent lists the same trackers.
+        let mut saved: Vec<&str> = resume.trackers.iter().flatten().map(|tracker| tracker.url.as_str()).collect();
+        let mut current = self.trackers.urls();
+        saved.sort_unstable();
+        current.sort_unstable();
+        if saved == current {
+            self.trackers = TrackerManager::from_tiers(resume.trackers);
+        }
+        Ok(())
+    }
+
+    /// Downloads the torrent from the peers the tracker returns into
+    /// `storage`, which can be any backend. Storage that wasn't rechecked yet
+    /// is rechecked first so that only missing pieces are fetched. Peers known
+    /// from resume data are tried too, even when no tracker answers. Peers that
+    /// fail are dropped and their errors kept in `peer_errors`, the download
+    /// fails when none are left before every piece is in. A storage error
+    /// fails it right away and stops the other sessions. Announcing the
+    /// completion is best effort, a failure only shows in the tracker status.
+    pub async fn download(&mut self, storage: Arc<dyn Storage>) -> Result<(), Error> {
+        if self.have.is_none() {
+            self.recheck(storage.clone(), |_| ()).await?;
+        }
+        let downloader = Downloader::new(&self.torrent.info)
+            .with_have(self.have.as_ref().unwrap())?
+            .with_partial_blocks(&self.partial_blocks)?;
+        if downloader.is_complete() {
+            return Ok(());
+        }
+
+        let known_peers = !self.known_peers.is_empty();
+        let mut peers = match self.tracker_info().await {
+            Ok(tracker_info) => tracker_info.peers.clone(),
+            Err(_) if known_peers => Vec::new(),
+            Err(e) => return Err(e),
+        };
+        for addr in &self.known_peers {
+            if !peers.iter().any(|peer| peer.addr == *addr) {
+                peers.push(Peer::new(*addr));
+            }
+        }
+        let downloader = Arc::new(Mutex::new(downloader));
+        let ours = Handshake::new(self.torrent.info_hash, self.peer_id);
+        let (sender, mut receiver) = mpsc::channel(PIECE_QUEUE);
+
+        // Each session can be stopped through its own `Notify`, which keeps the
+        // request when the session isn't waiting on it yet.
+        let mut sessions = Vec::new();
+        for peer in peers {
+            let (downloader, sender) = (downloader.clone(), sender.clone());
+            let addr = peer.addr;
+            let stop = Arc::new(Notify::new());
+            let stopped = stop.clone();
+            sessions.push((addr, stop, tokio::spawn(async move {
+                let session = async {
+                    let connection = Connection::connect(&peer, &ours, DEFAULT_HANDSHAKE_TIMEOUT).await?;
+                    Session::new(connection.into_framed(), peer.addr, downloader, sender).run().await?;
+                    Ok::<(), Error>(())
+                };
+                tokio::select! {
+                    result = session => result,
+                    _ = stopped.notified() => Ok(()),
+                }
+            })));
+        }
+        drop(sender);
+
+        let mut downloaded = self.downloaded;
+        let mut stored = Ok(());
+        while let Some(piece) = receiver.recv().await {
+            let (index, length) = (piece.index, piece.data.len() as u64);
+            let storage = storage.clone();
+            let written = tokio::task::spawn_blocking(move || storage.write_block(piece.index, 0, &piece.data)).await?;
+            if let Err(e) = written {
+                stored = Err(e);
+                break;
+            }
+            downloaded += length;
+            self.have.as_mut().unwrap()[index as usize] = true;
+        }
+        self.set_progress(self.uploaded, downloaded);
+
+        // Pieces that can't be stored aren't worth downloading, so the sessions
+        // still running are stopped. Otherwise they have all dropped their
+        // senders by now and are done.
+        drop(receiver);
+        if stored.is_err() {
+            for (_, stop, _) in &sessions {
+                stop.notify();
+            }
+        }
+        for (addr, _, session) in sessions {
+            if let Err(e) = session.await? {
+                self.peer_errors.insert(addr, format!("{}", e));
+            }
+        }
+
+        let downloader = downloader.lock().await;
+        self.partial_blocks = downloader.partial_blocks();
+        stored?;
+        if !downloader.is_complete() {
+            let have = (0..downloader.piece_count()).filter(|index| downloader.have(*index)).count();
+            return Err(Error::new(format!("Ran out of peers with {} of {} pieces.", have, downloader.piece_count())));
+        }
+        drop(downloader);
+
+        let _ = self.announce(Some(Event::Completed)).await;
+        Ok(())
+    }
+
+    fn announce_request(&self) -> AnnounceRequest {
+        let left = self.left();
+
+        AnnounceRequest::new(self.torrent.info_hash, self.peer_id, self.port)
+            .uploaded(self.uploaded)
+            .downloaded(self.downloaded)
+            .left(left)
+            .ipv6(self.ipv6)
+    }
+
+    /// Bytes still to download, from the pieces we have once we know them.
+    fn left(&self) -> u64 {
+        let info = &self.torrent.info;
+        match &self.have {
+            Some(have) => (0..info.piece_count())
+                .filter(|index| !have[*index as usize])
+                .map(|index| u64::from(info.piece_len(index)))
+                .sum(),
+            None => (info.length() as u64).saturating_sub(self.downloaded),
+        }
+    }
+
+    async fn announce_to(&mut self, request: &AnnounceRequest, tracker: &str) -> Result<TrackerInfo, Error> {
+        let response = if tracker.starts_with("udp://") {
+            self.announce_udp(request, tracker).await?
+        } else {
+            self.announce_http(request, tracker).await?
+        };
+
+        match response {
+            TrackerResponse::Success(tracker_info) => Ok(tracker_info),
+            TrackerResponse::Failure { reason } => Err(Error::new(format!("Tracker failure: {}", reason))),
+        }
+    }
+
+    /// UDP trackers are kept around so their connection ids can be reused.
+    async fn announce_udp(&mut self, request: &AnnounceRequest, tracker: &str) -> Result<TrackerResponse, Error> {
+        self.udp_tracker(tracker)?.announce(request).await
+    }
+
+    fn udp_tracker(&mut self, url: &str) -> Result<&mut UdpTracker, Error> {
+        if !self.udp_trackers.contains_key(url) {
+            self.udp_trackers.insert(url.to_string(), UdpTracker::new(url)?);
+        }
+
+        Ok(self.udp_trackers.get_mut(url).unwrap())
+    }
+
+    async fn announce_http(&self, request: &AnnounceRequest, tracker: &str) -> Result<TrackerResponse, Error> {
+        let uri: hyper::Uri = request.url(tracker).parse()?;
         let client = hyper::Client::new();
 
         let resp = client.get(uri).await?;
         let buf = hyper::body::to_bytes(resp).await?;
-        let response_data = decoder::decode(buf.to_vec());
+        let response_data = decoder::decode(buf.to_vec())?;
+
+        // Dictionary peer lists may name hosts that need a blocking lookup.
+        Ok(tokio::task::spawn_blocking(move || TrackerResponse::from(response_data)).await??)
+    }
+}
+
+/// The stats resume data is validated against, without them it can't be
+/// trusted.
+fn file_stats(storage: &dyn Storage) -> Result<Vec<FileStat>, Error> {
+    match storage.file_stats()? {
+        Some(stats) => Ok(stats),
+        None => Err(Error::new("Storage can't tell whether its files changed, resume data can't be validated.".to_string())),
+    }
+}
 
-        self.tracker_info = Some(TrackerInfo::from(response_data)?);
+/// An Azureus-style peer id, the client prefix followed by random characters.
+fn generate_peer_id() -> [u8; 20] {
+    let mut peer_id = [0; 20];
+    peer_id[..PEER_ID_PREFIX.len()].copy_from_slice(PEER_ID_PREFIX);
 
-        Ok(&self.tracker_info.as_ref().unwrap())
+    let mut rng = rand::thread_rng();
+    for byte in peer_id[PEER_ID_PREFIX.len()..].iter_mut() {
+        *byte = rng.sample(Alphanumeric) as u8;
     }
+    peer_id
+}
+
+/// Finds the global IPv6 address we would send from. Connecting a UDP socket
+/// only selects a route, no packets are sent.
+fn local_ipv6() -> Option<Ipv6Addr> {
+    let socket = UdpSocket::bind("[::]:0").ok()?;
+    socket.connect("[2001:4860:4860::8888]:80").ok()?;
+
+    match socket.local_addr().ok()? {
+        SocketAddr::V6(addr) if is_global(addr.ip()) => Some(*addr.ip()),
+        _ => None,
+    }
+}
+
+fn is_global(ip: &Ipv6Addr) -> bool {
+    let first = ip.segments()[0];
+    !ip.is_loopback()
+        && !ip.is_unspecified()
+        && (first & 0xffc0) != 0xfe80
+        && (first & 0xfe00) != 0xfc00
 }
 
 impl fmt::Display for Client {
@@ -49,26 +457,51 @@ mod tests {
     use crate::bencoding::decoder::decode;
     use crate::torrent::torrent::Torrent;
     use crate::torrent::torrent_info::TorrentInfo;
+    use crate::torrent::info_hash::InfoHash;
+    use crate::torrent::file_layout::Layout;
+    use crate::bencoding::bencode::DictMap;
+    use crate::client::tracker_manager::TrackerStatus;
+    use crate::download::verifier;
+    use crate::peer::codec::MessageCodec;
+    use crate::peer::connection::handshake;
+    use crate::peer::message::Message;
+    use crate::storage::disk::{Allocation, DiskStorage};
+    use crate::storage::memory::MemoryStorage;
+    use crate::test_util::scratch_dir;
+    use futures_util::{SinkExt, StreamExt};
+    use tokio::net::TcpListener;
+    use tokio::sync::oneshot;
+    use tokio_util::codec::Framed;
+
+    const DEAD_TRACKER: &str = "http://127.0.0.1:1/announce";
 
     fn client() -> Client {
+        client_with_announce_list(None)
+    }
+
+    fn client_with_announce_list(announce_list: Option<Vec<Vec<String>>>) -> Client {
         let torrent = Torrent {
-            announce: mockito::server_url(),
-            created_by: "derekstride".to_string(),
-            encoding: "UTF-8".to_string(),
-            creation_date: 170,
+            announce: Some(mockito::server_url()),
+            announce_list,
+            created_by: Some("derekstride".to_string()),
+            encoding: Some("UTF-8".to_string()),
+            creation_date: Some(170),
             info: TorrentInfo {
-                length: 4,
+                layout: Layout::SingleFile { length: 4 },
                 name: "derek.jar".to_string(),
                 piece_length: 100,
-                private: true,
-                pieces: vec![b'z', 195, 40],
+                private: Some(1),
+                pieces: vec![b'z'; 20],
+                extra: DictMap::new(),
             },
+            info_hash: InfoHash::new([0; 20]),
+            extra: DictMap::new(),
         };
         Client::new(torrent)
     }
 
     fn tracker_info_struct() -> TrackerInfo {
-        let bencode = decode(tracker_info_str().as_bytes().to_vec());
+        let bencode = decode(tracker_info_str().as_bytes().to_vec()).unwrap();
         match TrackerInfo::from(bencode) {
             Ok(t) => t,
             Err(_) => panic!("Can not decode tracker_info_str()"),
@@ -83,7 +516,13 @@ mod tests {
     async fn test_mock() {
         let expected = tracker_info_struct();
         let mut client = client();
-        let m = mock("GET", Matcher::Regex(".*".into()))
+        let m = mock("GET", Matcher::Any)
+            .match_query(Matcher::AllOf(vec![
+                Matcher::UrlEncoded("port".into(), "6881".into()),
+                Matcher::UrlEncoded("left".into(), "4".into()),
+                Matcher::UrlEncoded("compact".into(), "1".into()),
+                Matcher::UrlEncoded("event".into(), "started".into()),
+            ]))
             .with_status(200)
             .with_header("content-type", "text/plain")
             .with_body(tracker_info_str())
@@ -92,10 +531,449 @@ mod tests {
 
         let tracker_info = match client.tracker_info().await {
             Ok(t) => t,
-            Err(e) => panic!(format!("{}", e)),
+            Err(e) => panic!("{}", e),
         };
 
         m.assert();
         assert_eq!(&expected, tracker_info);
     }
+
+    #[tokio::test]
+    async fn test_falls_back_to_next_tier() {
+        let expected = tracker_info_struct();
+        let mut client = client_with_announce_list(Some(vec![
+            vec![DEAD_TRACKER.to_string()],
+            vec![mockito::server_url()],
+        ]));
+        let _m = mock("GET", Matcher::Regex(".*".into()))
+            .with_status(200)
+            .with_body(tracker_info_str())
+            .create();
+
+        let tracker_info = client.tracker_info().await.unwrap().clone();
+
+        assert_eq!(expected, tracker_info);
+        assert_eq!(Some(&TrackerStatus::Working), client.trackers().status(&mockito::server_url()));
+        match client.trackers().status(DEAD_TRACKER) {
+            Some(TrackerStatus::Failed { failures: 1, .. }) => (),
+            status => panic!("Unexpected status {:?}", status),
+        }
+    }
+
+    #[tokio::test]
+    async fn test_err_when_every_tracker_fails() {
+        let mut client = client_with_announce_list(Some(vec![vec![DEAD_TRACKER.to_string()]]));
+
+        assert!(client.tracker_info().await.is_err());
+    }
+
+    #[tokio::test]
+    async fn test_err_when_tracker_reports_a_failure() {
+        let mut client = client();
+        let _m = mock("GET", Matcher::Any)
+            .with_status(200)
+            .with_body("d14:failure reason17:torrent not founde")
+            .create();
+
+        assert_eq!(
+            Err(Error::new("Tracker failure: torrent not found".to_string())),
+            client.tracker_info().await.cloned(),
+        );
+        match client.trackers().status(&mockito::server_url()) {
+            Some(TrackerStatus::Failed { error, .. }) => assert_eq!("Tracker failure: torrent not found", error),
+            status => panic!("Unexpected status {:?}", status),
+        }
+    }
+
+    #[tokio::test]
+    async fn test_sends_tracker_id_on_later_announces() {
+        let mut client = client();
+        let first = mock("GET", Matcher::Any)
+            .with_status(200)
+            .with_body("d8:intervali1800e5:peers0:10:tracker id3:abce")
+            .create();
+        client.tracker_info().await.unwrap();
+        first.assert();
+
+        let second = mock("GET", Matcher::Any)
+            .match_query(Matcher::UrlEncoded("trackerid".into(), "abc".into()))
+            .with_status(200)
+            .with_body("d8:intervali1800e5:peers0:e")
+            .create();
+        client.tracker_info().await.unwrap();
+        second.assert();
+    }
+
+    #[tokio::test]
+    async fn test_sends_ipv6_address() {
+        let mut client = client();
+        assert_eq!(None, client.ipv6);
+        client.set_ipv6(Some("2001:db8::1".parse().unwrap()));
+        let m = mock("GET", Matcher::Any)
+            .match_query(Matcher::UrlEncoded("ipv6".into(), "2001:db8::1".into()))
+            .with_status(200)
+            .with_body("d8:intervali1800e5:peers0:e")
+            .create();
+
+        client.tracker_info().await.unwrap();
+        m.assert();
+    }
+
+    #[tokio::test]
+    async fn test_scrape() {
+        let mut client = client_with_announce_list(Some(vec![vec![format!("{}/announce", mockito::server_url())]]));
+        let _m = mock("GET", "/scrape")
+            .match_query(Matcher::Any)
+            .with_status(200)
+            .with_body("d5:filesd20:\x00\x00\x00\x00\x00\x00\x00\x00\x00\x00\x00\x00\x00\x00\x00\x00\x00\x00\x00\x00d8:completei5e10:downloadedi50e10:incompletei10eeee")
+            .create();
+
+        assert_eq!(
+            Ok(ScrapeInfo { complete: 5, downloaded: 50, incomplete: 10 }),
+            client.scrape().await,
+        );
+    }
+
+    /// Accepts one peer connection and serves `data` in pieces of 32 KiB.
+    async fn seeder(info_hash: InfoHash, data: Vec<u8>) -> SocketAddr {
+        let ready = Arc::new(Notify::new());
+        ready.notify();
+        gated_seeder(info_hash, data, vec![0b1100_0000], ready).await
+    }
+
+    /// A seeder of the pieces in `bitfield` that only unchokes us once `ready`
+    /// is notified.
+    async fn gated_seeder(info_hash: InfoHash, data: Vec<u8>, bitfield: Vec<u8>, ready: Arc<Notify>) -> SocketAddr {
+        let mut listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+
+        tokio::spawn(async move {
+            let (mut stream, _) = listener.accept().await.unwrap();
+            handshake(&mut stream, &Handshake::new(info_hash, *b"-XX0001-seederseeder"), None).await.unwrap();
+            let mut framed = Framed::new(stream, MessageCodec::new());
+            framed.send(Message::Bitfield(bitfield)).await.unwrap();
+            ready.notified().await;
+            framed.send(Message::Unchoke).await.unwrap();
+
+            while let Some(Ok(message)) = framed.next().await {
+                if let Message::Request { index, begin, length } = message {
+                    let start = index as usize * 32768 + begin as usize;
+                    let block = data[start..start + length as usize].to_vec();
+                    framed.send(Message::Piece { index, begin, block }).await.unwrap();
+                }
+            }
+        });
+
+        addr
+    }
+
+    /// A peer with no pieces that never says anything. `connected` is notified
+    /// once the handshake is done, the receiver fires when we hang up.
+    async fn silent_peer(info_hash: InfoHash, connected: Arc<Notify>) -> (SocketAddr, oneshot::Receiver<()>) {
+        let mut listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        let (closed, hung_up) = oneshot::channel();
+
+        tokio::spawn(async move {
+            let (mut stream, _) = listener.accept().await.unwrap();
+            handshake(&mut stream, &Handshake::new(info_hash, *b"-XX0001-silentsilent"), None).await.unwrap();
+            connected.notify();
+            let mut framed = Framed::new(stream, MessageCodec::new());
+            while let Some(Ok(_)) = framed.next().await {}
+            let _ = closed.send(());
+        });
+
+        (addr, hung_up)
+    }
+
+    fn seeded_data() -> Vec<u8> {
+        (0..40000).map(|i| (i % 251) as u8).collect()
+    }
+
+    /// A full piece of 32 KiB and a short last piece.
+    fn seeded_client(path: &str) -> Client {
+        let data = seeded_data();
+        Client::new(Torrent {
+            announce: Some(format!("{}{}", mockito::server_url(), path)),
+            announce_list: None,
+            created_by: None,
+            encoding: None,
+            creation_date: None,
+            info: TorrentInfo {
+                layout: Layout::SingleFile { length: 40000 },
+                name: "derek.iso".to_string(),
+                piece_length: 32768,
+                private: None,
+                pieces: data.chunks(32768).flat_map(|piece| verifier::digest(piece).to_vec()).collect(),
+                extra: DictMap::new(),
+            },
+            info_hash: InfoHash::new([7; 20]),
+            extra: DictMap::new(),
+        })
+    }
+
+    /// A tracker response listing `addrs`, which must be on 127.0.0.1.
+    fn peers_response(addrs: &[SocketAddr]) -> Vec<u8> {
+        let mut peers = format!("d8:intervali1800e5:peers{}:", 6 * addrs.len()).into_bytes();
+        for addr in addrs {
+            peers.extend_from_slice(&[127, 0, 0, 1]);
+            peers.extend_from_slice(&addr.port().to_be_bytes());
+        }
+        peers.push(b'e');
+        peers
+    }
+
+    fn announce_mock(path: &str, event: &str, left: &str, body: &[u8]) -> mockito::Mock {
+        mock("GET", path)
+            .match_query(Matcher::AllOf(vec![
+                Matcher::UrlEncoded("event".into(), event.into()),
+                Matcher::UrlEncoded("left".into(), left.into()),
+            ]))
+            .with_body(body)
+            .create()
+    }
+
+    #[tokio::test]
+    async fn test_download_into_memory() {
+        let mut client = seeded_client("/download");
+        let body = peers_response(&[seeder(client.torrent.info_hash, seeded_data()).await]);
+        let started = announce_mock("/download", "started", "40000", &body);
+        let completed = announce_mock("/download", "completed", "0", &body);
+
+        let storage = Arc::new(MemoryStorage::new(&client.torrent.info));
+        client.download(storage.clone()).await.unwrap();
+
+        started.assert();
+        completed.assert();
+        assert_eq!(seeded_data(), storage.data());
+        assert_eq!(Some(&[true, true][..]), client.have());
+    }
+
+    #[tokio::test]
+    async fn test_download_resumes_after_recheck() {
+        let mut client = seeded_client("/resume");
+        let storage = Arc::new(MemoryStorage::new(&client.torrent.info));
+        storage.write_block(0, 0, &seeded_data()[..32768]).unwrap();
+
+        let reports = Arc::new(std::sync::Mutex::new(Vec::new()));
+        let recorded = reports.clone();
+        let have = client.recheck(storage.clone(), move |progress| recorded.lock().unwrap().push(progress)).await.unwrap();
+        assert_eq!(&[true, false], have);
+        assert_eq!(
+            vec![Progress { checked: 1, total: 2, have: 1 }, Progress { checked: 2, total: 2, have: 1 }],
+            *reports.lock().unwrap(),
+        );
+
+        let body = peers_response(&[seeder(client.torrent.info_hash, seeded_data()).await]);
+        let started = announce_mock("/resume", "started", "7232", &body);
+        let completed = announce_mock("/resume", "completed", "0", &body);
+        client.download(storage.clone()).await.unwrap();
+
+        started.assert();
+        completed.assert();
+        assert_eq!(seeded_data(), storage.data());
+    }
+
+    #[tokio::test]
+    async fn test_download_of_complete_data_skips_the_tracker() {
+        // Nothing is mocked at this path, an announce would fail the download.
+        let mut client = seeded_client("/complete");
+        let storage = Arc::new(MemoryStorage::new(&client.torrent.info));
+        storage.write_block(0, 0, &seeded_data()[..32768]).unwrap();
+        storage.write_block(1, 0, &seeded_data()[32768..]).unwrap();
+
+        assert_eq!(Ok(()), client.download(storage).await);
+        assert_eq!(Some(&[true, true][..]), client.have());
+    }
+
+    #[tokio::test]
+    async fn test_download_survives_failing_peers_and_tracker() {
+        let mut client = seeded_client("/unannounced");
+        let dead = TcpListener::bind("127.0.0.1:0").await.unwrap().local_addr().unwrap();
+        let body = peers_response(&[dead, seeder(client.torrent.info_hash, seeded_data()).await]);
+        // Only the started announce is mocked, so announcing the completion fails.
+        let started = announce_mock("/unannounced", "started", "40000", &body);
+
+        let storage = Arc::new(MemoryStorage::new(&client.torrent.info));
+        assert_eq!(Ok(()), client.download(storage.clone()).await);
+
+        started.assert();
+        assert_eq!(seeded_data(), storage.data());
+        assert_eq!(vec![&dead], client.peer_errors().keys().collect::<Vec<_>>());
+        let url = format!("{}/unannounced", mockito::server_url());
+        assert!(matches!(client.trackers().status(&url), Some(TrackerStatus::Failed { .. })));
+    }
+
+    /// Memory storage that fails every write after the first.
+    struct FailingStorage {
+        inner: MemoryStorage,
+        writes: std::sync::Mutex<u32>,
+    }
+
+    impl Storage for FailingStorage {
+        fn write_block(&self, index: u32, begin: u32, data: &[u8]) -> Result<(), crate::storage::error::Error> {
+            let mut writes = self.writes.lock().unwrap();
+            *writes += 1;
+            if *writes > 1 {
+                return Err(crate::storage::error::Error::new("Disk full.".to_string()));
+            }
+            self.inner.write_block(index, begin, data)
+        }
+
+        fn read_block(&self, index: u32, begin: u32, length: usize) -> Result<Vec<u8>, crate::storage::error::Error> {
+            self.inner.read_block(index, begin, length)
+        }
+    }
+
+    #[tokio::test]
+    async fn test_pieces_written_before_a_storage_error_are_kept() {
+        let mut client = seeded_client("/disk-full");
+        let body = peers_response(&[seeder(client.torrent.info_hash, seeded_data()).await]);
+        let _started = announce_mock("/disk-full", "started", "40000", &body);
+
+        let storage = Arc::new(FailingStorage { inner: MemoryStorage::new(&client.torrent.info), writes: std::sync::Mutex::new(0) });
+        client.recheck(storage.clone(), |_| ()).await.unwrap();
+        assert_eq!(Err(Error::new("Disk full.".to_string())), client.download(storage.clone()).await);
+
+        let have = client.have().unwrap();
+        assert_eq!(1, have.iter().filter(|have| **have).count());
+        let index = have.iter().position(|have| *have).unwrap() as u32;
+        let length = client.torrent.info.piece_len(index) as usize;
+        let start = index as usize * 32768;
+        assert_eq!(seeded_data()[start..start + length].to_vec(), storage.read_block(index, 0, length).unwrap());
+        assert_eq!(length as u64, client.downloaded);
+    }
+
+    #[tokio::test]
+    async fn test_storage_error_stops_the_sessions() {
+        let mut client = seeded_client("/disk-full-stop");
+        let connected = Arc::new(Notify::new());
+        let (silent, hung_up) = silent_peer(client.torrent.info_hash, connected.clone()).await;
+        let seeder = gated_seeder(client.torrent.info_hash, seeded_data(), vec![0b1000_0000], connected).await;
+        let body = peers_response(&[seeder, silent]);
+        let _started = announce_mock("/disk-full-stop", "started", "40000", &body);
+
+        // The first write fails, with the last piece still to download.
+        let storage = Arc::new(FailingStorage { inner: MemoryStorage::new(&client.torrent.info), writes: std::sync::Mutex::new(1) });
+        client.recheck(storage.clone(), |_| ()).await.unwrap();
+        assert_eq!(Err(Error::new("Disk full.".to_string())), client.download(storage).await);
+
+        // Left running, the silent peer's session would only end after the idle timeout.
+        assert_eq!(Ok(Ok(())), tokio::time::timeout(std::time::Duration::from_secs(5), hung_up).await);
+        assert!(client.peer_errors().is_empty());
+    }
+
+    #[tokio::test]
+    async fn test_resume_data_round_trips_through_a_file() {
+        let root = scratch_dir("client-resume");
+        let mut client = seeded_client("/resume-data");
+        let storage = Arc::new(DiskStorage::create(&client.torrent.info, &root, Allocation::Sparse).unwrap());
+        storage.write_block(0, 0, &seeded_data()[..32768]).unwrap();
+        assert!(client.resume_data(storage.as_ref()).is_err());
+
+        client.recheck(storage.clone(), |_| ()).await.unwrap();
+        client.set_progress(5, 32768);
+        let path = root.join("derek.iso.resume");
+        client.save_resume_data(storage.as_ref(), &path).await.unwrap();
+
+        let mut resumed = seeded_client("/resume-data");
+        resumed.load_resume_data(storage.as_ref(), &path).await.unwrap();
+        assert_eq!(Some(&[true, false][..]), resumed.have());
+        assert_eq!(client.resume_data(storage.as_ref()), resumed.resume_data(storage.as_ref()));
+        std::fs::remove_dir_all(root).unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_changed_files_invalidate_resume_data() {
+        let root = scratch_dir("client-stale");
+        let mut client = seeded_client("/stale");
+        let storage = Arc::new(DiskStorage::create(&client.torrent.info, &root, Allocation::Sparse).unwrap());
+        client.recheck(storage.clone(), |_| ()).await.unwrap();
+        let path = root.join("derek.iso.resume");
+        client.save_resume_data(storage.as_ref(), &path).await.unwrap();
+
+        std::fs::OpenOptions::new().write(true).open(root.join("derek.iso")).unwrap().set_len(40001).unwrap();
+        let mut resumed = seeded_client("/stale");
+        assert_eq!(
+            Err(Error::new("File 0 changed since the resume data was saved.".to_string())),
+            resumed.load_resume_data(storage.as_ref(), &path).await,
+        );
+        assert_eq!(None, resumed.have());
+
+        let mut other = seeded_client("/stale");
+        other.torrent.info_hash = InfoHash::new([8; 20]);
+        assert!(other.load_resume_data(storage.as_ref(), &path).await.is_err());
+        std::fs::remove_dir_all(root).unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_download_from_resumed_peers_and_blocks() {
+        let root = scratch_dir("client-resumed");
+        let mut client = seeded_client("/resumed");
+        let storage = Arc::new(DiskStorage::create(&client.torrent.info, &root, Allocation::Sparse).unwrap());
+        let seeder = seeder(client.torrent.info_hash, seeded_data()).await;
+        let resume = ResumeData {
+            info_hash: client.torrent.info_hash,
+            have: vec![false, false],
+            partial_blocks: vec![(Block { index: 0, begin: 0, length: 16384 }, seeded_data()[..16384].to_vec())],
+            files: storage.file_stats().unwrap().unwrap(),
+            uploaded: 0,
+            downloaded: 0,
+            peers: vec![seeder],
+            trackers: client.trackers().tiers().to_vec(),
+        };
+        let path = root.join("derek.iso.resume");
+        tokio::fs::write(&path, resume.encode()).await.unwrap();
+        client.load_resume_data(storage.as_ref(), &path).await.unwrap();
+
+        // Only the completed announce is mocked, the peer comes from the resume data.
+        let completed = announce_mock("/resumed", "completed", "0", &peers_response(&[seeder]));
+        client.download(storage.clone()).await.unwrap();
+
+        completed.assert();
+        assert_eq!(seeded_data(), std::fs::read(root.join("derek.iso")).unwrap());
+        std::fs::remove_dir_all(root).unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_resume_data_is_not_trusted_without_file_stats() {
+        let mut client = seeded_client("/memory-resume");
+        let resume = ResumeData {
+            info_hash: client.torrent.info_hash,
+            have: vec![true, true],
+            partial_blocks: vec![],
+            files: vec![],
+            uploaded: 0,
+            downloaded: 0,
+            peers: vec![],
+            trackers: vec![],
+        };
+        let path = std::env::temp_dir().join(format!("torrent-rs-memory-{}.resume", std::process::id()));
+        tokio::fs::write(&path, resume.encode()).await.unwrap();
+
+        let storage = MemoryStorage::new(&client.torrent.info);
+        assert_eq!(
+            Err(Error::new("Storage can't tell whether its files changed, resume data can't be validated.".to_string())),
+            client.load_resume_data(&storage, &path).await,
+        );
+        assert_eq!(None, client.have());
+        assert!(resume.check(&client.torrent, &[]).is_err());
+        std::fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn test_is_global() {
+        assert!(is_global(&"2001:db8::1".parse().unwrap()));
+        assert!(!is_global(&Ipv6Addr::LOCALHOST));
+        assert!(!is_global(&"fe80::1".parse().unwrap()));
+        assert!(!is_global(&"fd00::1".parse().unwrap()));
+    }
+
+    #[test]
+    fn test_generate_peer_id() {
+        let peer_id = generate_peer_id();
+
+        assert_eq!(PEER_ID_PREFIX, &peer_id[..8]);
+        assert!(peer_id[8..].iter().all(|b| b.is_ascii_alphanumeric()));
+    }
 }
@@ -1,6 +1,7 @@
 use std::{fmt, io};
-use crate::torrent;
+use crate::{bencoding, download, peer, storage, torrent};
 use http::uri::InvalidUri;
+use tokio::task::JoinError;
 
 #[derive(PartialEq, Debug)]
 pub struct Error {
@@ -37,6 +38,12 @@ impl From<torrent::error::Error> for Error {
     }
 }
 
+impl From<bencoding::error::Error> for Error {
+    fn from(err: bencoding::error::Error) -> Self {
+        Error::new(format!("{}", err))
+    }
+}
+
 impl From<hyper::error::Error> for Error {
     fn from(err: hyper::error::Error) -> Self {
         Error::new(format!("{}", err))
@@ -48,3 +55,27 @@ impl From<InvalidUri> for Error {
         Error::new(format!("{}", err))
     }
 }
+
+impl From<JoinError> for Error {
+    fn from(err: JoinError) -> Self {
+        Error::new(format!("{}", err))
+    }
+}
+
+impl From<peer::error::Error> for Error {
+    fn from(err: peer::error::Error) -> Self {
+        Error::new(format!("{}", err))
+    }
+}
+
+impl From<download::error::Error> for Error {
+    fn from(err: download::error::Error) -> Self {
+        Error::new(format!("{}", err))
+    }
+}
+
+impl From<storage::error::Error> for Error {
+    fn from(err: storage::error::Error) -> Self {
+        Error::new(format!("{}", err))
+    }
+}
@@ -1,2 +1,9 @@
+#[allow(clippy::module_inception)]
 pub mod client;
 pub mod error;
+pub mod announce_request;
+pub mod udp_tracker;
+pub mod scrape;
+pub mod announcer;
+pub mod tracker_manager;
+pub mod resume_data;
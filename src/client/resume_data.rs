@@ -0,0 +1,244 @@
+use std::convert::TryFrom;
+use std::net::SocketAddr;
+use std::str;
+
+use crate::bencoding::bencode::{Bencode, DictMap};
+use crate::bencoding::byte_string::ByteString;
+use crate::bencoding::{decoder, encoder};
+use crate::client::error::Error;
+use crate::client::tracker_manager::{Tracker, TrackerStatus};
+use crate::download::block::Block;
+use crate::storage::storage::FileStat;
+use crate::torrent::info_hash::InfoHash;
+use crate::torrent::torrent::Torrent;
+
+/// What a client knows about a download that is expensive to find out again,
+/// saved so that a restart doesn't have to hash the whole torrent. It is only
+/// to be trusted while the files are as they were when it was saved.
+#[derive(Eq, PartialEq, Clone, Debug)]
+pub struct ResumeData {
+    pub info_hash: InfoHash,
+    /// The verified pieces.
+    pub have: Vec<bool>,
+    /// Blocks of pieces that weren't complete yet, they are not verified.
+    pub partial_blocks: Vec<(Block, Vec<u8>)>,
+    pub files: Vec<FileStat>,
+    pub uploaded: u64,
+    pub downloaded: u64,
+    pub peers: Vec<SocketAddr>,
+    /// The trackers in the order they are tried, their statuses are not kept.
+    pub trackers: Vec<Vec<Tracker>>,
+}
+
+impl ResumeData {
+    pub fn from_bytes(data: &[u8]) -> Result<Self, Error> {
+        Self::from(decoder::decode(data.to_vec())?)
+    }
+
+    pub fn from(input: Bencode) -> Result<Self, Error> {
+        let info_hash = InfoHash::try_from(input.get_bytes("info hash")?)?;
+
+        let piece_count: usize = number(&input, "piece count")?;
+        let bitfield = input.get_bytes("pieces")?;
+        if bitfield.len() != piece_count.div_ceil(8) {
+            return Err(Error::new(format!(
+                "\"pieces\" is {} bytes, expected {} for {} pieces.", bitfield.len(), piece_count.div_ceil(8), piece_count,
+            )));
+        }
+        let have = (0..piece_count).map(|index| bitfield[index / 8] & (0x80 >> (index % 8)) != 0).collect();
+
+        let mut partial_blocks = Vec::new();
+        for block in input.get_list("blocks")? {
+            let data = block.get_bytes("data")?.to_vec();
+            let block = Block { index: number(block, "index")?, begin: number(block, "begin")?, length: data.len() as u32 };
+            partial_blocks.push((block, data));
+        }
+
+        let mut files = Vec::new();
+        for file in input.get_list("files")? {
+            files.push(FileStat { length: number(file, "length")?, mtime: number(file, "mtime")? });
+        }
+
+        let mut peers = Vec::new();
+        for peer in input.get_list("peers")? {
+            let addr = match peer {
+                Bencode::ByteString(s) => str::from_utf8(s).ok().and_then(|addr| addr.parse().ok()),
+                _ => None,
+            };
+            match addr {
+                Some(addr) => peers.push(addr),
+                None => return Err(Error::new("\"peers\" value is not a list of addresses.".to_string())),
+            }
+        }
+
+        let mut trackers = Vec::new();
+        for tier in input.get_list("trackers")? {
+            let tier = match tier {
+                Bencode::List(tier) => tier,
+                _ => return Err(Error::new("\"trackers\" value is not a list of Lists.".to_string())),
+            };
+
+            let mut parsed = Vec::new();
+            for tracker in tier {
+                parsed.push(Tracker {
+                    url: tracker.get_string("url")?,
+                    status: TrackerStatus::NotContacted,
+                    tracker_id: tracker.get_optional_string("tracker id")?,
+                });
+            }
+            trackers.push(parsed);
+        }
+
+        Ok(Self {
+            info_hash,
+            have,
+            partial_blocks,
+            files,
+            uploaded: number(&input, "uploaded")?,
+            downloaded: number(&input, "downloaded")?,
+            peers,
+            trackers,
+        })
+    }
+
+    pub fn to_bencode(&self) -> Bencode {
+        let mut bitfield = vec![0; self.have.len().div_ceil(8)];
+        for (index, _) in self.have.iter().enumerate().filter(|(_, have)| **have) {
+            bitfield[index / 8] |= 0x80 >> (index % 8);
+        }
+
+        let blocks = self.partial_blocks.iter()
+            .map(|(block, data)| dict(vec![
+                ("index", Bencode::Number(i64::from(block.index))),
+                ("begin", Bencode::Number(i64::from(block.begin))),
+                ("data", Bencode::ByteString(data.clone())),
+            ]))
+            .collect();
+
+        let files = self.files.iter()
+            .map(|file| dict(vec![
+                ("length", Bencode::Number(file.length as i64)),
+                ("mtime", Bencode::Number(file.mtime as i64)),
+            ]))
+            .collect();
+
+        let peers = self.peers.iter()
+            .map(|addr| Bencode::ByteString(addr.to_string().into_bytes()))
+            .collect();
+
+        let trackers = self.trackers.iter()
+            .map(|tier| Bencode::List(tier.iter()
+                .map(|tracker| {
+                    let mut entries = vec![("url", Bencode::ByteString(tracker.url.as_bytes().to_vec()))];
+                    if let Some(tracker_id) = &tracker.tracker_id {
+                        entries.push(("tracker id", Bencode::ByteString(tracker_id.as_bytes().to_vec())));
+                    }
+                    dict(entries)
+                })
+                .collect()
+            ))
+            .collect();
+
+        dict(vec![
+            ("info hash", Bencode::ByteString(self.info_hash.as_bytes().to_vec())),
+            ("piece count", Bencode::Number(self.have.len() as i64)),
+            ("pieces", Bencode::ByteString(bitfield)),
+            ("blocks", Bencode::List(blocks)),
+            ("files", Bencode::List(files)),
+            ("uploaded", Bencode::Number(self.uploaded as i64)),
+            ("downloaded", Bencode::Number(self.downloaded as i64)),
+            ("peers", Bencode::List(peers)),
+            ("trackers", Bencode::List(trackers)),
+        ])
+    }
+
+    pub fn encode(&self) -> Vec<u8> {
+        encoder::encode(self.to_bencode())
+    }
+
+    /// Checks that the data was saved for `torrent` and that its files, as
+    /// described by `files`, haven't been touched since.
+    pub fn check(&self, torrent: &Torrent, files: &[FileStat]) -> Result<(), Error> {
+        if self.info_hash != torrent.info_hash {
+            return Err(Error::new(format!("Resume data is for {}, not {}.", self.info_hash, torrent.info_hash)));
+        }
+        if self.have.len() != torrent.info.piece_count() as usize {
+            return Err(Error::new(format!(
+                "Resume data has {} pieces, the torrent has {}.", self.have.len(), torrent.info.piece_count(),
+            )));
+        }
+        let file_count = torrent.info.files().len();
+        if self.files.len() != file_count {
+            return Err(Error::new(format!("Resume data has {} files, the torrent has {}.", self.files.len(), file_count)));
+        }
+        if self.files.len() != files.len() {
+            return Err(Error::new(format!("Resume data has {} files, storage has {}.", self.files.len(), files.len())));
+        }
+        for (file, (saved, current)) in self.files.iter().zip(files).enumerate() {
+            if saved != current {
+                return Err(Error::new(format!("File {} changed since the resume data was saved.", file)));
+            }
+        }
+        Ok(())
+    }
+}
+
+fn dict(entries: Vec<(&str, Bencode)>) -> Bencode {
+    Bencode::Dict(entries.into_iter().map(|(key, value)| (ByteString::from_str(key), value)).collect::<DictMap>())
+}
+
+fn number<T: TryFrom<i64>>(input: &Bencode, key: &str) -> Result<T, Error> {
+    let number = input.get_number(key)?;
+    T::try_from(number).map_err(|_| Error::new(format!("\"{}\" value {} is out of range.", key, number)))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::download::block::BLOCK_SIZE;
+
+    fn resume_data() -> ResumeData {
+        ResumeData {
+            info_hash: InfoHash::new([7; 20]),
+            have: vec![true, false, false, true, false, false, false, false, true],
+            partial_blocks: vec![(Block { index: 1, begin: BLOCK_SIZE, length: 3 }, vec![1, 2, 3])],
+            files: vec![FileStat { length: 10, mtime: 1_600_000_000_123_456_789 }, FileStat { length: 0, mtime: 5 }],
+            uploaded: 42,
+            downloaded: 1024,
+            peers: vec!["127.0.0.1:6881".parse().unwrap(), "[::1]:51413".parse().unwrap()],
+            trackers: vec![
+                vec![
+                    Tracker { url: "http://b".to_string(), status: TrackerStatus::NotContacted, tracker_id: Some("id".to_string()) },
+                    Tracker { url: "http://a".to_string(), status: TrackerStatus::NotContacted, tracker_id: None },
+                ],
+                vec![Tracker { url: "udp://c".to_string(), status: TrackerStatus::NotContacted, tracker_id: None }],
+            ],
+        }
+    }
+
+    #[test]
+    fn test_round_trip() {
+        let encoded = resume_data().encode();
+
+        assert_eq!(Ok(resume_data()), ResumeData::from_bytes(&encoded));
+        assert_eq!(Ok(vec![0b1001_0000, 0b1000_0000]), decoder::decode(encoded).map(|input| input.get_bytes("pieces").unwrap().to_vec()));
+    }
+
+    #[test]
+    fn test_err_on_malformed_data() {
+        let mut input = resume_data().to_bencode();
+        if let Bencode::Dict(dict) = &mut input {
+            dict.insert(ByteString::from_str("piece count"), Bencode::Number(17));
+        }
+        assert_eq!(
+            Err(Error::new("\"pieces\" is 2 bytes, expected 3 for 17 pieces.".to_string())),
+            ResumeData::from(input),
+        );
+
+        let mut input = resume_data().to_bencode();
+        if let Bencode::Dict(dict) = &mut input {
+            dict.insert(ByteString::from_str("uploaded"), Bencode::Number(-1));
+        }
+        assert_eq!(Err(Error::new("\"uploaded\" value -1 is out of range.".to_string())), ResumeData::from(input));
+    }
+}
@@ -0,0 +1,116 @@
+use std::collections::BTreeMap;
+use std::result::Result;
+
+use crate::bencoding::decoder;
+use crate::client::error::Error;
+use crate::client::udp_tracker::UdpTracker;
+use crate::torrent::info_hash::InfoHash;
+use crate::torrent::scrape_info::ScrapeInfo;
+
+/// Scrapes `info_hashes` from the tracker at `announce`, over UDP for a
+/// `udp://` url and HTTP otherwise.
+pub async fn scrape(announce: &str, info_hashes: &[InfoHash]) -> Result<BTreeMap<InfoHash, ScrapeInfo>, Error> {
+    if announce.starts_with("udp://") {
+        scrape_udp(&mut UdpTracker::new(announce)?, info_hashes).await
+    } else {
+        scrape_http(announce, info_hashes).await
+    }
+}
+
+pub async fn scrape_udp(tracker: &mut UdpTracker, info_hashes: &[InfoHash]) -> Result<BTreeMap<InfoHash, ScrapeInfo>, Error> {
+    let stats = tracker.scrape(info_hashes).await?;
+    Ok(info_hashes.iter().cloned().zip(stats).collect())
+}
+
+pub async fn scrape_http(announce: &str, info_hashes: &[InfoHash]) -> Result<BTreeMap<InfoHash, ScrapeInfo>, Error> {
+    let uri: hyper::Uri = scrape_url(announce, info_hashes)?.parse()?;
+    let client = hyper::Client::new();
+
+    let resp = client.get(uri).await?;
+    let buf = hyper::body::to_bytes(resp).await?;
+    let response_data = decoder::decode(buf.to_vec())?;
+
+    Ok(ScrapeInfo::from_response(response_data)?)
+}
+
+/// Derives the scrape url by convention: the last path component of the
+/// announce url must start with `announce`, which is replaced by `scrape`.
+/// Trackers whose announce url doesn't follow this don't support scraping.
+pub fn scrape_url(announce: &str, info_hashes: &[InfoHash]) -> Result<String, Error> {
+    let (base, query) = match announce.find('?') {
+        Some(index) => (&announce[..index], &announce[index + 1..]),
+        None => (announce, ""),
+    };
+
+    let unsupported = || Error::new(format!("\"{}\" does not support scraping.", announce));
+    let slash = base.rfind('/').ok_or_else(unsupported)?;
+    let last = &base[slash + 1..];
+    if !last.starts_with("announce") {
+        return Err(unsupported());
+    }
+
+    let mut params: Vec<String> = info_hashes.iter()
+        .map(|info_hash| format!("info_hash={}", info_hash.url_encoded()))
+        .collect();
+    let query = query.trim_end_matches('&');
+    if !query.is_empty() {
+        params.insert(0, query.to_string());
+    }
+
+    Ok(format!("{}/scrape{}?{}", &base[..slash], &last["announce".len()..], params.join("&")))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use mockito::{mock, Matcher};
+
+    fn info_hashes() -> Vec<InfoHash> {
+        vec![InfoHash::new([b'a'; 20]), InfoHash::new([b'b'; 20])]
+    }
+
+    const QUERY: &str = "info_hash=aaaaaaaaaaaaaaaaaaaa&info_hash=bbbbbbbbbbbbbbbbbbbb";
+
+    #[test]
+    fn test_scrape_url() {
+        assert_eq!(
+            format!("http://example.com/scrape?{}", QUERY),
+            scrape_url("http://example.com/announce", &info_hashes()).unwrap(),
+        );
+        assert_eq!(
+            format!("http://example.com/x/scrape.php?{}", QUERY),
+            scrape_url("http://example.com/x/announce.php", &info_hashes()).unwrap(),
+        );
+        assert_eq!(
+            format!("http://example.com/scrape?passkey=abc&{}", QUERY),
+            scrape_url("http://example.com/announce?passkey=abc&", &info_hashes()).unwrap(),
+        );
+    }
+
+    #[test]
+    fn test_err_when_scraping_is_not_supported() {
+        assert_eq!(
+            Err(Error::new("\"http://example.com/a\" does not support scraping.".to_string())),
+            scrape_url("http://example.com/a", &info_hashes()),
+        );
+        assert!(scrape_url("http://example.com/announce/x", &info_hashes()).is_err());
+    }
+
+    #[tokio::test]
+    async fn test_scrape_http() {
+        let m = mock("GET", "/scrape")
+            .match_query(Matcher::Exact(QUERY.to_string()))
+            .with_status(200)
+            .with_body("d5:filesd20:aaaaaaaaaaaaaaaaaaaad8:completei5e10:downloadedi50e10:incompletei10eeee")
+            .create();
+
+        let result = scrape(&format!("{}/announce", mockito::server_url()), &info_hashes()).await.unwrap();
+
+        m.assert();
+        assert_eq!(
+            Some(&ScrapeInfo { complete: 5, downloaded: 50, incomplete: 10 }),
+            result.get(&InfoHash::new([b'a'; 20])),
+        );
+        assert_eq!(None, result.get(&InfoHash::new([b'b'; 20])));
+    }
+}
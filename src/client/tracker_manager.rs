@@ -0,0 +1,182 @@
+use std::fmt;
+use rand::Rng;
+use rand::seq::SliceRandom;
+
+#[derive(Eq, PartialEq, Clone, Debug)]
+pub enum TrackerStatus {
+    NotContacted,
+    Working,
+    Failed { error: String, failures: u32 },
+}
+
+#[derive(Eq, PartialEq, Clone, Debug)]
+pub struct Tracker {
+    pub url: String,
+    pub status: TrackerStatus,
+    /// The `tracker id` from the tracker's last response, sent back on later
+    /// announces.
+    pub tracker_id: Option<String>,
+}
+
+/// Orders the torrent's trackers as described by BEP 12: each tier is shuffled
+/// once, trackers are tried tier by tier, and a tracker that responds is moved
+/// to the front of its tier.
+#[derive(Eq, PartialEq, Clone, Debug)]
+pub struct TrackerManager {
+    tiers: Vec<Vec<Tracker>>,
+}
+
+impl TrackerManager {
+    pub fn new(tiers: Vec<Vec<String>>) -> Self {
+        Self::with_rng(tiers, &mut rand::thread_rng())
+    }
+
+    pub fn with_rng<R: Rng>(tiers: Vec<Vec<String>>, rng: &mut R) -> Self {
+        let tiers = tiers.into_iter()
+            .filter(|tier| !tier.is_empty())
+            .map(|mut tier| {
+                tier.shuffle(rng);
+                tier.into_iter()
+                    .map(|url| Tracker { url, status: TrackerStatus::NotContacted, tracker_id: None })
+                    .collect()
+            })
+            .collect();
+
+        Self { tiers }
+    }
+
+    /// Takes the tiers in the order given, e.g. restored from resume data,
+    /// instead of shuffling them again.
+    pub fn from_tiers(tiers: Vec<Vec<Tracker>>) -> Self {
+        Self { tiers: tiers.into_iter().filter(|tier| !tier.is_empty()).collect() }
+    }
+
+    pub fn tiers(&self) -> &[Vec<Tracker>] {
+        &self.tiers
+    }
+
+    /// The tracker urls in the order they should be tried.
+    pub fn urls(&self) -> Vec<String> {
+        self.tiers.iter().flatten().map(|tracker| tracker.url.clone()).collect()
+    }
+
+    pub fn status(&self, url: &str) -> Option<&TrackerStatus> {
+        self.tiers.iter().flatten()
+            .find(|tracker| tracker.url == url)
+            .map(|tracker| &tracker.status)
+    }
+
+    pub fn tracker_id(&self, url: &str) -> Option<String> {
+        self.tiers.iter().flatten()
+            .find(|tracker| tracker.url == url)
+            .and_then(|tracker| tracker.tracker_id.clone())
+    }
+
+    pub fn set_tracker_id(&mut self, url: &str, tracker_id: String) {
+        if let Some(tracker) = self.tiers.iter_mut().flatten().find(|tracker| tracker.url == url) {
+            tracker.tracker_id = Some(tracker_id);
+        }
+    }
+
+    pub fn mark_success(&mut self, url: &str) {
+        for tier in self.tiers.iter_mut() {
+            if let Some(index) = tier.iter().position(|tracker| tracker.url == url) {
+                let mut tracker = tier.remove(index);
+                tracker.status = TrackerStatus::Working;
+                tier.insert(0, tracker);
+                return;
+            }
+        }
+    }
+
+    pub fn mark_failure(&mut self, url: &str, error: String) {
+        for tracker in self.tiers.iter_mut().flatten() {
+            if tracker.url == url {
+                let failures = match tracker.status {
+                    TrackerStatus::Failed { failures, .. } => failures + 1,
+                    _ => 1,
+                };
+                tracker.status = TrackerStatus::Failed { error, failures };
+                return;
+            }
+        }
+    }
+}
+
+impl fmt::Display for TrackerStatus {
+    fn fmt(&self, fmt: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            TrackerStatus::NotContacted => write!(fmt, "not contacted"),
+            TrackerStatus::Working => write!(fmt, "working"),
+            TrackerStatus::Failed { error, failures } => write!(fmt, "failed {} times: {}", failures, error),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rand::SeedableRng;
+    use rand::rngs::StdRng;
+
+    fn tiers() -> Vec<Vec<String>> {
+        vec![
+            vec!["a".to_string(), "b".to_string(), "c".to_string()],
+            vec![],
+            vec!["d".to_string()],
+        ]
+    }
+
+    fn manager() -> TrackerManager {
+        TrackerManager::with_rng(tiers(), &mut StdRng::seed_from_u64(0))
+    }
+
+    #[test]
+    fn test_shuffles_within_tiers() {
+        let manager = manager();
+        let mut first_tier: Vec<String> = manager.tiers()[0].iter().map(|t| t.url.clone()).collect();
+        first_tier.sort();
+
+        assert_eq!(2, manager.tiers().len());
+        assert_eq!(tiers()[0], first_tier);
+        assert_eq!("d", manager.urls()[3]);
+    }
+
+    #[test]
+    fn test_mark_success_promotes_within_tier() {
+        let mut manager = manager();
+        let last = manager.tiers()[0][2].url.clone();
+        manager.mark_success(&last);
+
+        assert_eq!(last, manager.urls()[0]);
+        assert_eq!(Some(&TrackerStatus::Working), manager.status(&last));
+
+        manager.mark_success("d");
+        assert_eq!("d", manager.urls()[3]);
+    }
+
+    #[test]
+    fn test_mark_failure_counts_failures() {
+        let mut manager = manager();
+        let urls = manager.urls();
+        manager.mark_failure("d", "timeout".to_string());
+        manager.mark_failure("d", "refused".to_string());
+
+        assert_eq!(urls, manager.urls());
+        assert_eq!(
+            Some(&TrackerStatus::Failed { error: "refused".to_string(), failures: 2 }),
+            manager.status("d"),
+        );
+        assert_eq!(Some(&TrackerStatus::NotContacted), manager.status("a"));
+        assert_eq!(None, manager.status("z"));
+    }
+
+    #[test]
+    fn test_tracker_id() {
+        let mut manager = manager();
+        manager.set_tracker_id("d", "abc".to_string());
+
+        assert_eq!(Some("abc".to_string()), manager.tracker_id("d"));
+        assert_eq!(None, manager.tracker_id("a"));
+    }
+}
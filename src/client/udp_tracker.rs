@@ -0,0 +1,440 @@
+use std::convert::TryFrom;
+use std::fmt;
+use std::net::SocketAddr;
+use std::time::{Duration, Instant};
+use byteorder::{ByteOrder, BigEndian};
+use tokio::net::{self, UdpSocket};
+use tokio::time;
+
+use crate::client::announce_request::AnnounceRequest;
+use crate::client::error::Error;
+use crate::torrent::info_hash::InfoHash;
+use crate::torrent::peer::Peer;
+use crate::torrent::scrape_info::ScrapeInfo;
+use crate::torrent::tracker_info::TrackerInfo;
+use crate::torrent::tracker_response::TrackerResponse;
+
+const PROTOCOL_ID: u64 = 0x0417_2710_1980;
+
+const CONNECT: u32 = 0;
+const ANNOUNCE: u32 = 1;
+const SCRAPE: u32 = 2;
+const ERROR: u32 = 3;
+
+/// Trackers accept a connection id for a minute after handing it out.
+const CONNECTION_ID_LIFETIME: Duration = Duration::from_secs(60);
+const DEFAULT_TIMEOUT: Duration = Duration::from_secs(15);
+/// BEP 15 retries up to 8 times, which is over an hour of waiting, so we give
+/// up sooner and let the tracker manager move on to the next tracker.
+const DEFAULT_RETRIES: u32 = 3;
+const MAX_SCRAPE_HASHES: usize = 74;
+const MAX_PACKET_SIZE: usize = 8192;
+
+/// A `udp://` tracker (BEP 15). The connection id is cached between requests,
+/// each request is retransmitted with a timeout of `timeout * 2^n`.
+#[derive(Eq, PartialEq, Clone, Debug)]
+pub struct UdpTracker {
+    host: String,
+    port: u16,
+    connection: Option<(u64, Instant)>,
+    timeout: Duration,
+    max_retries: u32,
+}
+
+enum Reply {
+    Data(Vec<u8>),
+    Failure(String),
+}
+
+impl UdpTracker {
+    pub fn new(url: &str) -> Result<Self, Error> {
+        let uri: hyper::Uri = url.parse()?;
+        if uri.scheme_str() != Some("udp") {
+            return Err(Error::new(format!("\"{}\" is not a udp:// url.", url)));
+        }
+
+        let host = match uri.host() {
+            Some(host) => host.trim_start_matches('[').trim_end_matches(']').to_string(),
+            None => return Err(Error::new(format!("\"{}\" has no host.", url))),
+        };
+        let port = match uri.port_u16() {
+            Some(port) => port,
+            None => return Err(Error::new(format!("\"{}\" has no port.", url))),
+        };
+
+        Ok(Self { host, port, connection: None, timeout: DEFAULT_TIMEOUT, max_retries: DEFAULT_RETRIES })
+    }
+
+    pub fn with_timeout(mut self, timeout: Duration, max_retries: u32) -> Self {
+        self.timeout = timeout;
+        self.max_retries = max_retries;
+        self
+    }
+
+    pub async fn announce(&mut self, request: &AnnounceRequest) -> Result<TrackerResponse, Error> {
+        let (mut socket, addr) = self.socket().await?;
+
+        let data = match self.send(&mut socket, ANNOUNCE, &announce_body(request)).await? {
+            Reply::Data(data) => data,
+            Reply::Failure(reason) => return Ok(TrackerResponse::Failure { reason }),
+        };
+
+        if data.len() < 20 {
+            return Err(Error::new(format!("UDP announce response is {} bytes, expected at least 20.", data.len())));
+        }
+
+        let (size, parse): (usize, fn(&[u8]) -> Peer) = match addr {
+            SocketAddr::V4(_) => (6, Peer::from_compact_v4),
+            SocketAddr::V6(_) => (18, Peer::from_compact_v6),
+        };
+        let peers = &data[20..];
+        if !peers.len().is_multiple_of(size) {
+            return Err(Error::new(format!("UDP announce peers length {} is not a multiple of {}.", peers.len(), size)));
+        }
+
+        Ok(TrackerResponse::Success(TrackerInfo {
+            complete: Some(i64::from(BigEndian::read_i32(&data[16..20]))),
+            downloaded: None,
+            incomplete: Some(i64::from(BigEndian::read_i32(&data[12..16]))),
+            interval: i64::from(BigEndian::read_i32(&data[8..12])),
+            min_interval: None,
+            warning_message: None,
+            tracker_id: None,
+            peers: peers.chunks(size).map(parse).collect(),
+        }))
+    }
+
+    pub async fn scrape(&mut self, info_hashes: &[InfoHash]) -> Result<Vec<ScrapeInfo>, Error> {
+        if info_hashes.len() > MAX_SCRAPE_HASHES {
+            return Err(Error::new(format!("Can not scrape {} torrents at once, the limit is {}.", info_hashes.len(), MAX_SCRAPE_HASHES)));
+        }
+
+        let (mut socket, _) = self.socket().await?;
+        let body: Vec<u8> = info_hashes.iter().flat_map(|hash| hash.as_bytes().to_vec()).collect();
+
+        let data = match self.send(&mut socket, SCRAPE, &body).await? {
+            Reply::Data(data) => data,
+            Reply::Failure(reason) => return Err(Error::new(format!("Tracker failure: {}", reason))),
+        };
+
+        let expected = 8 + 12 * info_hashes.len();
+        if data.len() < expected {
+            return Err(Error::new(format!("UDP scrape response is {} bytes, expected {}.", data.len(), expected)));
+        }
+
+        Ok(data[8..expected].chunks(12).map(|stats| ScrapeInfo {
+            complete: i64::from(BigEndian::read_i32(&stats[..4])),
+            downloaded: i64::from(BigEndian::read_i32(&stats[4..8])),
+            incomplete: i64::from(BigEndian::read_i32(&stats[8..12])),
+        }).collect())
+    }
+
+    async fn socket(&self) -> Result<(UdpSocket, SocketAddr), Error> {
+        let addr = match net::lookup_host((self.host.as_str(), self.port)).await?.next() {
+            Some(addr) => addr,
+            None => return Err(Error::new(format!("Could not resolve \"{}\".", self.host))),
+        };
+
+        let local = if addr.is_ipv4() { "0.0.0.0:0" } else { "[::]:0" };
+        let socket = UdpSocket::bind(local).await?;
+        socket.connect(addr).await?;
+        Ok((socket, addr))
+    }
+
+    fn connection_id(&self) -> Option<u64> {
+        match self.connection {
+            Some((id, received)) if received.elapsed() < CONNECTION_ID_LIFETIME => Some(id),
+            _ => None,
+        }
+    }
+
+    /// Sends `action` with `body`, connecting first when there is no valid
+    /// connection id. A tracker that doesn't answer is retried with a doubled
+    /// timeout each time.
+    async fn send(&mut self, socket: &mut UdpSocket, action: u32, body: &[u8]) -> Result<Reply, Error> {
+        let mut attempt = 0;
+
+        while attempt <= self.max_retries {
+            let timeout = self.timeout * 2u32.pow(attempt);
+
+            let connection_id = match self.connection_id() {
+                Some(id) => id,
+                None => {
+                    let transaction_id = rand::random();
+                    let mut packet = [0; 16];
+                    BigEndian::write_u64(&mut packet[..8], PROTOCOL_ID);
+                    BigEndian::write_u32(&mut packet[8..12], CONNECT);
+                    BigEndian::write_u32(&mut packet[12..16], transaction_id);
+
+                    let data = match exchange(socket, &packet, transaction_id, timeout).await? {
+                        Some(data) => data,
+                        None => {
+                            attempt += 1;
+                            continue;
+                        },
+                    };
+
+                    match reply(data, CONNECT)? {
+                        Reply::Data(data) if data.len() >= 16 => {
+                            let id = BigEndian::read_u64(&data[8..16]);
+                            self.connection = Some((id, Instant::now()));
+                            id
+                        },
+                        Reply::Data(data) => {
+                            return Err(Error::new(format!("UDP connect response is {} bytes, expected 16.", data.len())));
+                        },
+                        failure => return Ok(failure),
+                    }
+                },
+            };
+
+            let transaction_id = rand::random();
+            let mut packet = vec![0; 16];
+            BigEndian::write_u64(&mut packet[..8], connection_id);
+            BigEndian::write_u32(&mut packet[8..12], action);
+            BigEndian::write_u32(&mut packet[12..16], transaction_id);
+            packet.extend_from_slice(body);
+
+            match exchange(socket, &packet, transaction_id, timeout).await? {
+                Some(data) => return reply(data, action),
+                None => attempt += 1,
+            }
+        }
+
+        Err(Error::new(format!("UDP tracker {}:{} did not respond.", self.host, self.port)))
+    }
+}
+
+/// Sends `packet` and waits up to `timeout` for the reply carrying
+/// `transaction_id`, replies to other transactions are dropped.
+async fn exchange(socket: &mut UdpSocket, packet: &[u8], transaction_id: u32, timeout: Duration) -> Result<Option<Vec<u8>>, Error> {
+    socket.send(packet).await?;
+
+    let deadline = time::Instant::now() + timeout;
+    let mut buf = vec![0; MAX_PACKET_SIZE];
+    loop {
+        let len = match time::timeout_at(deadline, socket.recv(&mut buf)).await {
+            Ok(result) => result?,
+            Err(_) => return Ok(None),
+        };
+
+        if len >= 8 && BigEndian::read_u32(&buf[4..8]) == transaction_id {
+            buf.truncate(len);
+            return Ok(Some(buf));
+        }
+    }
+}
+
+fn reply(data: Vec<u8>, action: u32) -> Result<Reply, Error> {
+    match BigEndian::read_u32(&data[..4]) {
+        received if received == action => Ok(Reply::Data(data)),
+        ERROR => Ok(Reply::Failure(String::from_utf8_lossy(&data[8..]).to_string())),
+        received => Err(Error::new(format!("UDP tracker replied with action {}, expected {}.", received, action))),
+    }
+}
+
+/// The announce request after the connection id, action and transaction id.
+fn announce_body(request: &AnnounceRequest) -> [u8; 82] {
+    let mut body = [0; 82];
+    body[..20].copy_from_slice(request.info_hash.as_bytes());
+    body[20..40].copy_from_slice(&request.peer_id);
+    BigEndian::write_u64(&mut body[40..48], request.downloaded);
+    BigEndian::write_u64(&mut body[48..56], request.left);
+    BigEndian::write_u64(&mut body[56..64], request.uploaded);
+    BigEndian::write_u32(&mut body[64..68], request.event.map_or(0, |event| event.udp_id()));
+    // body[68..72] is the ip, 0 lets the tracker use the packet's source.
+    // 0 when we don't send a key.
+    BigEndian::write_u32(&mut body[72..76], request.key.unwrap_or(0));
+    // -1 asks for the tracker's default, so larger values are clamped rather than wrapped.
+    BigEndian::write_i32(&mut body[76..80], request.numwant.map_or(-1, |numwant| i32::try_from(numwant).unwrap_or(i32::MAX)));
+    BigEndian::write_u16(&mut body[80..82], request.port);
+    body
+}
+
+impl fmt::Display for UdpTracker {
+    fn fmt(&self, fmt: &mut fmt::Formatter) -> fmt::Result {
+        write!(fmt, "udp://{}:{}", self.host, self.port)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::Arc;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use crate::client::announce_request::Event;
+
+    const CONNECTION_ID: u64 = 42;
+
+    /// Binds a UDP socket standing in for a tracker, `handler` gets each packet
+    /// and returns the reply, if any.
+    async fn stand_in<F>(mut handler: F) -> String
+        where F: FnMut(&[u8]) -> Option<Vec<u8>> + Send + 'static
+    {
+        let mut socket = UdpSocket::bind("127.0.0.1:0").await.unwrap();
+        let addr = socket.local_addr().unwrap();
+
+        tokio::spawn(async move {
+            let mut buf = [0; 2048];
+            loop {
+                let (len, from) = socket.recv_from(&mut buf).await.unwrap();
+                if let Some(reply) = handler(&buf[..len]) {
+                    socket.send_to(&reply, from).await.unwrap();
+                }
+            }
+        });
+
+        format!("udp://{}/announce", addr)
+    }
+
+    fn header(action: u32, transaction_id: &[u8]) -> Vec<u8> {
+        let mut reply = vec![0; 4];
+        BigEndian::write_u32(&mut reply, action);
+        reply.extend_from_slice(transaction_id);
+        reply
+    }
+
+    /// Answers like a tracker, counting the connect requests it sees.
+    fn tracker(connects: Arc<AtomicUsize>) -> impl FnMut(&[u8]) -> Option<Vec<u8>> {
+        move |packet| {
+            let transaction_id = &packet[12..16];
+            match BigEndian::read_u32(&packet[8..12]) {
+                CONNECT => {
+                    assert_eq!(PROTOCOL_ID, BigEndian::read_u64(&packet[..8]));
+                    connects.fetch_add(1, Ordering::SeqCst);
+                    let mut reply = header(CONNECT, transaction_id);
+                    reply.extend_from_slice(&CONNECTION_ID.to_be_bytes());
+                    Some(reply)
+                },
+                ANNOUNCE => {
+                    assert_eq!(CONNECTION_ID, BigEndian::read_u64(&packet[..8]));
+                    assert_eq!(98, packet.len());
+                    assert_eq!(2, BigEndian::read_u32(&packet[80..84]));
+                    assert_eq!(-1, BigEndian::read_i32(&packet[92..96]));
+                    assert_eq!(6881, BigEndian::read_u16(&packet[96..98]));
+
+                    let mut reply = header(ANNOUNCE, transaction_id);
+                    reply.extend_from_slice(&[0, 0, 7, 8, 0, 0, 0, 1, 0, 0, 0, 2]);
+                    reply.extend_from_slice(&[79, 122, 0, 120, 26, 65]);
+                    Some(reply)
+                },
+                SCRAPE => {
+                    assert_eq!(36, packet.len());
+                    let mut reply = header(SCRAPE, transaction_id);
+                    reply.extend_from_slice(&[0, 0, 0, 4, 0, 0, 0, 6, 0, 0, 0, 1]);
+                    Some(reply)
+                },
+                _ => None,
+            }
+        }
+    }
+
+    fn request() -> AnnounceRequest {
+        AnnounceRequest::new(InfoHash::new([1; 20]), *b"-RS0001-abcdefghijkl", 6881)
+            .left(100)
+            .event(Some(Event::Started))
+    }
+
+    #[tokio::test]
+    async fn test_announce() {
+        let connects = Arc::new(AtomicUsize::new(0));
+        let url = stand_in(tracker(connects.clone())).await;
+        let mut tracker = UdpTracker::new(&url).unwrap();
+
+        let expected = TrackerResponse::Success(TrackerInfo {
+            complete: Some(2),
+            downloaded: None,
+            incomplete: Some(1),
+            interval: 1800,
+            min_interval: None,
+            warning_message: None,
+            tracker_id: None,
+            peers: vec![Peer::new("79.122.0.120:6721".parse().unwrap())],
+        });
+
+        assert_eq!(Ok(expected.clone()), tracker.announce(&request()).await);
+        assert_eq!(Ok(expected), tracker.announce(&request()).await);
+        assert_eq!(1, connects.load(Ordering::SeqCst));
+    }
+
+    #[tokio::test]
+    async fn test_scrape() {
+        let url = stand_in(tracker(Arc::new(AtomicUsize::new(0)))).await;
+        let mut tracker = UdpTracker::new(&url).unwrap();
+
+        assert_eq!(
+            Ok(vec![ScrapeInfo { complete: 4, downloaded: 6, incomplete: 1 }]),
+            tracker.scrape(&[InfoHash::new([1; 20])]).await,
+        );
+    }
+
+    #[tokio::test]
+    async fn test_error_action_is_a_failure() {
+        let url = stand_in(|packet: &[u8]| {
+            let mut reply = header(ERROR, &packet[12..16]);
+            reply.extend_from_slice(b"torrent not found");
+            Some(reply)
+        }).await;
+        let mut tracker = UdpTracker::new(&url).unwrap();
+
+        assert_eq!(
+            Ok(TrackerResponse::Failure { reason: "torrent not found".to_string() }),
+            tracker.announce(&request()).await,
+        );
+    }
+
+    #[tokio::test]
+    async fn test_retransmits_when_a_packet_is_lost() {
+        let packets = Arc::new(AtomicUsize::new(0));
+        let mut inner = tracker(Arc::new(AtomicUsize::new(0)));
+        let seen = packets.clone();
+        let url = stand_in(move |packet: &[u8]| {
+            match seen.fetch_add(1, Ordering::SeqCst) {
+                0 => None,
+                _ => inner(packet),
+            }
+        }).await;
+        let mut tracker = UdpTracker::new(&url).unwrap().with_timeout(Duration::from_millis(20), 2);
+
+        assert!(tracker.announce(&request()).await.is_ok());
+        assert_eq!(3, packets.load(Ordering::SeqCst));
+    }
+
+    #[tokio::test]
+    async fn test_err_when_tracker_does_not_respond() {
+        let url = stand_in(|_: &[u8]| None).await;
+        let mut tracker = UdpTracker::new(&url).unwrap().with_timeout(Duration::from_millis(5), 1);
+
+        assert_eq!(
+            Err(Error::new(format!("UDP tracker {} did not respond.", &url["udp://".len()..url.len() - "/announce".len()]))),
+            tracker.announce(&request()).await,
+        );
+    }
+
+    #[test]
+    fn test_numwant_is_clamped() {
+        assert_eq!(50, BigEndian::read_i32(&announce_body(&request().numwant(Some(50)))[76..80]));
+        assert_eq!(i32::MAX, BigEndian::read_i32(&announce_body(&request().numwant(Some(u32::MAX)))[76..80]));
+    }
+
+    #[test]
+    fn test_key_is_sent_as_is() {
+        assert_eq!(0xdead_beef, BigEndian::read_u32(&announce_body(&request().key(Some(0xdead_beef)))[72..76]));
+        assert_eq!(0, BigEndian::read_u32(&announce_body(&request())[72..76]));
+    }
+
+    #[test]
+    fn test_new() {
+        let tracker = UdpTracker::new("udp://tracker.example.com:1337/announce").unwrap();
+        assert_eq!("udp://tracker.example.com:1337", format!("{}", tracker));
+
+        assert_eq!(
+            Err(Error::new("\"http://example.com:80/announce\" is not a udp:// url.".to_string())),
+            UdpTracker::new("http://example.com:80/announce"),
+        );
+        assert_eq!(
+            Err(Error::new("\"udp://example.com/announce\" has no port.".to_string())),
+            UdpTracker::new("udp://example.com/announce"),
+        );
+    }
+}
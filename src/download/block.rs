@@ -0,0 +1,58 @@
+use std::fmt;
+
+/// The size of the blocks pieces are requested in, peers drop requests for
+/// more than this.
+pub const BLOCK_SIZE: u32 = 16 * 1024;
+
+/// A range of one piece, the unit of a `request` message.
+#[derive(Eq, PartialEq, Ord, PartialOrd, Hash, Clone, Copy, Debug)]
+pub struct Block {
+    pub index: u32,
+    pub begin: u32,
+    pub length: u32,
+}
+
+impl Block {
+    /// Splits a piece of `piece_len` bytes into blocks, the last block holds
+    /// whatever is left.
+    pub fn split(index: u32, piece_len: u32) -> Vec<Block> {
+        (0..piece_len)
+            .step_by(BLOCK_SIZE as usize)
+            .map(|begin| Block { index, begin, length: BLOCK_SIZE.min(piece_len - begin) })
+            .collect()
+    }
+}
+
+impl fmt::Display for Block {
+    fn fmt(&self, fmt: &mut fmt::Formatter) -> fmt::Result {
+        write!(fmt, "Block: {{ index: {}, begin: {}, length: {} }}", self.index, self.begin, self.length)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_split_whole_blocks() {
+        assert_eq!(
+            vec![
+                Block { index: 3, begin: 0, length: BLOCK_SIZE },
+                Block { index: 3, begin: BLOCK_SIZE, length: BLOCK_SIZE },
+            ],
+            Block::split(3, 2 * BLOCK_SIZE),
+        );
+    }
+
+    #[test]
+    fn test_split_short_last_block() {
+        assert_eq!(
+            vec![
+                Block { index: 0, begin: 0, length: BLOCK_SIZE },
+                Block { index: 0, begin: BLOCK_SIZE, length: 100 },
+            ],
+            Block::split(0, BLOCK_SIZE + 100),
+        );
+        assert_eq!(vec![Block { index: 1, begin: 0, length: 7 }], Block::split(1, 7));
+    }
+}
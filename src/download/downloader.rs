@@ -0,0 +1,640 @@
+use std::collections::{BTreeMap, BTreeSet, HashMap};
+use std::net::SocketAddr;
+use std::mem;
+use std::sync::Arc;
+use tokio::sync::Notify;
+
+use crate::download::block::Block;
+use crate::download::error::Error;
+use crate::download::picker::{Picker, Priority};
+use crate::download::piece_buffer::{BlockState, PieceBuffer};
+use crate::download::verifier::Verifier;
+use crate::torrent::torrent_info::TorrentInfo;
+
+/// How many block requests are kept outstanding with each peer.
+pub const DEFAULT_PIPELINE_DEPTH: usize = 5;
+
+/// A piece with all of its blocks received.
+#[derive(Eq, PartialEq, Clone, Debug)]
+pub struct Piece {
+    pub index: u32,
+    pub data: Vec<u8>,
+}
+
+/// What became of a piece once its last block came in.
+#[derive(Eq, PartialEq, Clone, Debug)]
+pub enum Completed {
+    Verified(Piece),
+    /// The piece did not match its hash and will be downloaded again, `peers`
+    /// sent at least one of its blocks.
+    Failed { index: u32, peers: Vec<SocketAddr> },
+}
+
+/// What the end game, where the last blocks are requested from every peer
+/// that has them, cost us.
+#[derive(Eq, PartialEq, Clone, Copy, Default, Debug)]
+pub struct EndGameStats {
+    pub duplicate_requests: u64,
+    pub cancels: u64,
+    /// Bytes of blocks that arrived after another peer had sent them.
+    pub wasted_bytes: u64,
+}
+
+#[derive(Clone, Debug)]
+struct PeerState {
+    has: Vec<bool>,
+    choked: bool,
+    requested: BTreeSet<Block>,
+    cancels: Vec<Block>,
+    notify: Arc<Notify>,
+}
+
+/// Decides which blocks to request from which peer and reassembles the
+/// answers into pieces. It does no I/O, a `Session` per peer feeds it
+/// messages and sends the requests and cancels it hands out.
+#[derive(Clone, Debug)]
+pub struct Downloader {
+    piece_lens: Vec<u32>,
+    have: Vec<bool>,
+    in_progress: BTreeMap<u32, PieceBuffer>,
+    contributors: BTreeMap<u32, BTreeSet<SocketAddr>>,
+    hash_failures: HashMap<SocketAddr, u32>,
+    peers: HashMap<SocketAddr, PeerState>,
+    picker: Picker,
+    verifier: Verifier,
+    pipeline_depth: usize,
+    end_game_stats: EndGameStats,
+}
+
+impl Downloader {
+    pub fn new(info: &TorrentInfo) -> Self {
+        let piece_lens = (0..info.piece_count()).map(|index| info.piece_len(index)).collect::<Vec<_>>();
+        Self {
+            have: vec![false; piece_lens.len()],
+            piece_lens,
+            in_progress: BTreeMap::new(),
+            contributors: BTreeMap::new(),
+            hash_failures: HashMap::new(),
+            peers: HashMap::new(),
+            picker: Picker::new(info),
+            verifier: Verifier::new(info),
+            pipeline_depth: DEFAULT_PIPELINE_DEPTH,
+            end_game_stats: EndGameStats::default(),
+        }
+    }
+
+    pub fn with_pipeline_depth(mut self, pipeline_depth: usize) -> Self {
+        self.pipeline_depth = pipeline_depth;
+        self
+    }
+
+    /// Starts from the pieces we already have, e.g. found by a recheck.
+    pub fn with_have(mut self, have: &[bool]) -> Result<Self, Error> {
+        if have.len() != self.have.len() {
+            return Err(Error::new(format!("Have lists {} pieces, the torrent has {}.", have.len(), self.have.len())));
+        }
+        self.have.copy_from_slice(have);
+        Ok(self)
+    }
+
+    pub fn with_picker(mut self, picker: Picker) -> Self {
+        self.picker = picker;
+        self
+    }
+
+    /// Starts from blocks received before, e.g. kept in resume data, so that
+    /// they aren't downloaded again. Blocks of pieces we have are dropped, as
+    /// are pieces they would complete since those were never verified.
+    pub fn with_partial_blocks(mut self, blocks: &[(Block, Vec<u8>)]) -> Result<Self, Error> {
+        for (block, data) in blocks {
+            let piece_count = self.piece_count();
+            if block.index >= piece_count {
+                return Err(Error::new(format!("There is no piece {}, the torrent has {}.", block.index, piece_count)));
+            }
+            if self.have(block.index) {
+                continue;
+            }
+            let piece_len = self.piece_lens[block.index as usize];
+            self.in_progress.entry(block.index)
+                .or_insert_with(|| PieceBuffer::new(block.index, piece_len))
+                .add_block(block.begin, data)?;
+        }
+        self.in_progress.retain(|_, buffer| !buffer.is_complete());
+        Ok(self)
+    }
+
+    pub fn picker(&self) -> &Picker {
+        &self.picker
+    }
+
+    pub fn set_file_priority(&mut self, file: usize, priority: Priority) -> Result<(), Error> {
+        self.picker.set_file_priority(file, priority)
+    }
+
+    pub fn piece_count(&self) -> u32 {
+        self.piece_lens.len() as u32
+    }
+
+    pub fn have(&self, index: u32) -> bool {
+        self.have[index as usize]
+    }
+
+    pub fn is_complete(&self) -> bool {
+        self.have.iter().all(|have| *have)
+    }
+
+    /// Our pieces as a `bitfield` message payload.
+    pub fn bitfield(&self) -> Vec<u8> {
+        let mut bitfield = vec![0; self.have.len().div_ceil(8)];
+        for (index, _) in self.have.iter().enumerate().filter(|(_, have)| **have) {
+            bitfield[index / 8] |= 0x80 >> (index % 8);
+        }
+        bitfield
+    }
+
+    /// The blocks received of pieces that aren't complete yet.
+    pub fn partial_blocks(&self) -> Vec<(Block, Vec<u8>)> {
+        self.in_progress.values()
+            .flat_map(|buffer| buffer.received())
+            .map(|(block, data)| (block, data.to_vec()))
+            .collect()
+    }
+
+    /// How many pieces that failed their hash check `addr` sent blocks of.
+    pub fn hash_failures(&self, addr: &SocketAddr) -> u32 {
+        self.hash_failures.get(addr).cloned().unwrap_or(0)
+    }
+
+    pub fn end_game_stats(&self) -> EndGameStats {
+        self.end_game_stats
+    }
+
+    /// Whether every block we still want has been requested, from then on
+    /// outstanding blocks are requested again from other peers.
+    pub fn in_end_game(&self) -> bool {
+        let all_started = (0..self.piece_count())
+            .filter(|index| !self.have(*index) && self.picker.piece_priority(*index) != Priority::Skip)
+            .all(|index| self.in_progress.contains_key(&index));
+        all_started && self.in_progress.values().all(|buffer| buffer.missing().is_empty())
+    }
+
+    /// Peers start out choking us and having nothing. The returned `Notify` is
+    /// signalled when the peer has cancels to send or the download completes.
+    pub fn add_peer(&mut self, addr: SocketAddr) -> Arc<Notify> {
+        let has = vec![false; self.piece_lens.len()];
+        let notify = Arc::new(Notify::new());
+        let peer = PeerState { has, choked: true, requested: BTreeSet::new(), cancels: vec![], notify: notify.clone() };
+        self.peers.insert(addr, peer);
+        notify
+    }
+
+    /// The `cancel` messages to send `addr`, for blocks another peer sent first.
+    pub fn take_cancels(&mut self, addr: &SocketAddr) -> Vec<Block> {
+        match self.peers.get_mut(addr) {
+            Some(peer) => mem::take(&mut peer.cancels),
+            None => vec![],
+        }
+    }
+
+    /// Forgets the peer, its outstanding requests go to the other peers.
+    pub fn remove_peer(&mut self, addr: &SocketAddr) {
+        if let Some(peer) = self.peers.remove(addr) {
+            self.release(&peer.requested);
+            self.picker.peer_lost(&peer.has);
+        }
+    }
+
+    pub fn peer_has(&mut self, addr: &SocketAddr, index: u32) -> Result<(), Error> {
+        let piece_count = self.piece_count();
+        let peer = self.peer_mut(addr)?;
+        if index >= piece_count {
+            return Err(Error::new(format!("Peer {} has piece {}, there are only {}.", addr, index, piece_count)));
+        }
+        if !mem::replace(&mut peer.has[index as usize], true) {
+            self.picker.peer_has(index);
+        }
+        Ok(())
+    }
+
+    /// Applies a `bitfield` message, the high bit of the first byte is piece 0.
+    pub fn peer_bitfield(&mut self, addr: &SocketAddr, bitfield: &[u8]) -> Result<(), Error> {
+        let piece_count = self.piece_lens.len();
+        let expected = piece_count.div_ceil(8);
+        if bitfield.len() != expected {
+            return Err(Error::new(format!("Bitfield is {} bytes, expected {}.", bitfield.len(), expected)));
+        }
+
+        let has: Vec<bool> = (0..piece_count).map(|index| bitfield[index / 8] & (0x80 >> (index % 8)) != 0).collect();
+        let peer = self.peer_mut(addr)?;
+        let before = mem::replace(&mut peer.has, has.clone());
+        self.picker.peer_changed(&before, &has);
+        Ok(())
+    }
+
+    /// A choking peer drops our requests, so they are handed out again.
+    pub fn peer_choked(&mut self, addr: &SocketAddr, choked: bool) -> Result<(), Error> {
+        let peer = self.peer_mut(addr)?;
+        peer.choked = choked;
+        if choked {
+            let requested = mem::take(&mut peer.requested);
+            self.release(&requested);
+        }
+        Ok(())
+    }
+
+    /// Whether the peer has a piece we still need.
+    pub fn wants(&self, addr: &SocketAddr) -> bool {
+        match self.peers.get(addr) {
+            Some(peer) => peer.has.iter().zip(&self.have).any(|(has, have)| *has && !*have),
+            None => false,
+        }
+    }
+
+    /// The requests to send `addr` to fill its pipeline. Blocks of pieces
+    /// already under way go first so that pieces complete, then new pieces
+    /// are started in the order of the picker.
+    pub fn next_requests(&mut self, addr: &SocketAddr) -> Vec<Block> {
+        let (choked, outstanding) = match self.peers.get(addr) {
+            Some(peer) => (peer.choked, peer.requested.len()),
+            None => return vec![],
+        };
+        if choked || outstanding >= self.pipeline_depth {
+            return vec![];
+        }
+
+        let mut wanted = self.pipeline_depth - outstanding;
+        let mut blocks = Vec::new();
+        for index in self.candidates(addr) {
+            let piece_len = self.piece_lens[index as usize];
+            let buffer = self.in_progress.entry(index).or_insert_with(|| PieceBuffer::new(index, piece_len));
+            for block in buffer.missing().into_iter().take(wanted) {
+                buffer.mark_requested(&block);
+                blocks.push(block);
+            }
+            wanted = self.pipeline_depth - outstanding - blocks.len();
+            if wanted == 0 {
+                break;
+            }
+        }
+
+        if let Some(peer) = self.peers.get_mut(addr) {
+            peer.requested.extend(blocks.iter().cloned());
+        }
+
+        if wanted > 0 && self.in_end_game() {
+            let duplicates = self.duplicates(addr, wanted);
+            self.end_game_stats.duplicate_requests += duplicates.len() as u64;
+            blocks.extend(duplicates);
+        }
+        blocks
+    }
+
+    /// Stores a block `addr` sent us and cancels it with the other peers it was
+    /// requested from. Once the last block of a piece is in the piece is
+    /// checked against its hash, blocks we already have are dropped.
+    pub fn block_received(&mut self, addr: &SocketAddr, index: u32, begin: u32, data: &[u8]) -> Result<Option<Completed>, Error> {
+        let block = Block { index, begin, length: data.len() as u32 };
+        self.peer_mut(addr)?.requested.remove(&block);
+
+        let buffer = match self.in_progress.get_mut(&index) {
+            Some(buffer) if !buffer.is_received(&block) => buffer,
+            _ => {
+                self.end_game_stats.wasted_bytes += u64::from(block.length);
+                return Ok(None);
+            },
+        };
+        buffer.add_block(begin, data)?;
+        let complete = buffer.is_complete();
+        self.cancel_elsewhere(addr, &block);
+        self.contributors.entry(index).or_default().insert(*addr);
+        if !complete {
+            return Ok(None);
+        }
+
+        let data = self.in_progress.remove(&index).unwrap().into_data();
+        let contributors = self.contributors.remove(&index).unwrap_or_default();
+        if !self.verifier.verify(index, &data) {
+            for peer in &contributors {
+                *self.hash_failures.entry(*peer).or_insert(0) += 1;
+            }
+            return Ok(Some(Completed::Failed { index, peers: contributors.into_iter().collect() }));
+        }
+
+        self.have[index as usize] = true;
+        if self.is_complete() {
+            // Wakes the sessions so that they see the download is done.
+            for peer in self.peers.values() {
+                peer.notify.notify();
+            }
+        }
+        Ok(Some(Completed::Verified(Piece { index, data })))
+    }
+
+    /// Pieces to request from `addr`, in the order to request them.
+    fn candidates(&self, addr: &SocketAddr) -> Vec<u32> {
+        let peer = &self.peers[addr];
+        let (started, new): (Vec<u32>, Vec<u32>) = (0..self.piece_count())
+            .filter(|index| peer.has[*index as usize] && !self.have[*index as usize])
+            .partition(|index| self.in_progress.contains_key(index));
+        let verified = self.have.iter().filter(|have| **have).count();
+        let new = self.picker.pick(&new, verified, &mut rand::thread_rng());
+        started.into_iter().chain(new).collect()
+    }
+
+    /// Blocks other peers were asked for that `addr` has and wasn't asked
+    /// for yet.
+    fn duplicates(&mut self, addr: &SocketAddr, wanted: usize) -> Vec<Block> {
+        let peer = &self.peers[addr];
+        let duplicates: Vec<Block> = self.in_progress.values()
+            .filter(|buffer| peer.has[buffer.index() as usize])
+            .flat_map(|buffer| buffer.blocks().into_iter().filter(move |block| buffer.state(block) == BlockState::Requested))
+            .filter(|block| !peer.requested.contains(block))
+            .take(wanted)
+            .collect();
+
+        if let Some(peer) = self.peers.get_mut(addr) {
+            peer.requested.extend(duplicates.iter().cloned());
+        }
+        duplicates
+    }
+
+    /// Withdraws the requests for `block` made of peers other than `addr`.
+    fn cancel_elsewhere(&mut self, addr: &SocketAddr, block: &Block) {
+        for (other, peer) in self.peers.iter_mut() {
+            if other != addr && peer.requested.remove(block) {
+                peer.cancels.push(*block);
+                peer.notify.notify();
+                self.end_game_stats.cancels += 1;
+            }
+        }
+    }
+
+    /// Hands blocks back out unless another peer was asked for them too.
+    fn release(&mut self, blocks: &BTreeSet<Block>) {
+        for block in blocks {
+            if self.peers.values().any(|peer| peer.requested.contains(block)) {
+                continue;
+            }
+            if let Some(buffer) = self.in_progress.get_mut(&block.index) {
+                buffer.release(block);
+            }
+        }
+    }
+
+    fn peer_mut(&mut self, addr: &SocketAddr) -> Result<&mut PeerState, Error> {
+        match self.peers.get_mut(addr) {
+            Some(peer) => Ok(peer),
+            None => Err(Error::new(format!("Unknown peer {}.", addr))),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::bencoding::bencode::DictMap;
+    use crate::download::block::BLOCK_SIZE;
+    use crate::download::verifier;
+    use crate::torrent::file_layout::Layout;
+
+    const PIECE_LENS: [u32; 3] = [2 * BLOCK_SIZE, 2 * BLOCK_SIZE, 100];
+
+    /// Two full pieces of two blocks each and a last piece of one short block,
+    /// every byte of a piece is its index.
+    fn info() -> TorrentInfo {
+        let pieces = (0..3).flat_map(|index| verifier::digest(&piece_data(index)).to_vec()).collect();
+        TorrentInfo {
+            layout: Layout::SingleFile { length: 4 * i64::from(BLOCK_SIZE) + 100 },
+            name: "derek".to_string(),
+            piece_length: 2 * i64::from(BLOCK_SIZE),
+            private: None,
+            pieces,
+            extra: DictMap::new(),
+        }
+    }
+
+    fn piece_data(index: u32) -> Vec<u8> {
+        vec![index as u8; PIECE_LENS[index as usize] as usize]
+    }
+
+    fn addr(port: u16) -> SocketAddr {
+        SocketAddr::from(([127, 0, 0, 1], port))
+    }
+
+    const SEEDER: u8 = 0b1110_0000;
+
+    fn unchoked_peer(downloader: &mut Downloader, addr: &SocketAddr, bitfield: u8) {
+        downloader.add_peer(*addr);
+        downloader.peer_bitfield(addr, &[bitfield]).unwrap();
+        downloader.peer_choked(addr, false).unwrap();
+    }
+
+    #[test]
+    fn test_fills_pipeline_rarest_first() {
+        let picker = Picker::new(&info()).with_random_first(0);
+        let mut downloader = Downloader::new(&info()).with_pipeline_depth(3).with_picker(picker);
+        unchoked_peer(&mut downloader, &addr(1), SEEDER);
+        unchoked_peer(&mut downloader, &addr(2), 0b1100_0000);
+        unchoked_peer(&mut downloader, &addr(3), 0b0100_0000);
+
+        assert_eq!(
+            vec![
+                Block { index: 2, begin: 0, length: 100 },
+                Block { index: 0, begin: 0, length: BLOCK_SIZE },
+                Block { index: 0, begin: BLOCK_SIZE, length: BLOCK_SIZE },
+            ],
+            downloader.next_requests(&addr(1)),
+        );
+        assert_eq!(Vec::<Block>::new(), downloader.next_requests(&addr(1)));
+    }
+
+    #[test]
+    fn test_nothing_requested_while_choked() {
+        let mut downloader = Downloader::new(&info());
+        downloader.add_peer(addr(1));
+        downloader.peer_has(&addr(1), 0).unwrap();
+
+        assert!(downloader.wants(&addr(1)));
+        assert_eq!(Vec::<Block>::new(), downloader.next_requests(&addr(1)));
+    }
+
+    #[test]
+    fn test_only_requests_pieces_the_peer_has() {
+        let mut downloader = Downloader::new(&info());
+        downloader.add_peer(addr(1));
+        downloader.peer_has(&addr(1), 2).unwrap();
+        downloader.peer_has(&addr(1), 2).unwrap();
+        downloader.peer_choked(&addr(1), false).unwrap();
+
+        assert_eq!(1, downloader.picker().availability(2));
+        assert_eq!(vec![Block { index: 2, begin: 0, length: 100 }], downloader.next_requests(&addr(1)));
+    }
+
+    #[test]
+    fn test_skipped_files_are_not_requested() {
+        let mut downloader = Downloader::new(&info());
+        downloader.set_file_priority(0, Priority::Skip).unwrap();
+        unchoked_peer(&mut downloader, &addr(1), SEEDER);
+
+        assert_eq!(Vec::<Block>::new(), downloader.next_requests(&addr(1)));
+        downloader.remove_peer(&addr(1));
+        assert_eq!(0, downloader.picker().availability(0));
+    }
+
+    #[test]
+    fn test_reassembles_pieces() {
+        let mut downloader = Downloader::new(&info()).with_pipeline_depth(10);
+        unchoked_peer(&mut downloader, &addr(1), SEEDER);
+        let blocks = downloader.next_requests(&addr(1));
+        assert_eq!(5, blocks.len());
+
+        let mut pieces = Vec::new();
+        for block in blocks.iter().rev() {
+            let data = vec![block.index as u8; block.length as usize];
+            match downloader.block_received(&addr(1), block.index, block.begin, &data).unwrap() {
+                Some(Completed::Verified(piece)) => pieces.push(piece),
+                Some(failed) => panic!("Unexpected {:?}.", failed),
+                None => (),
+            }
+        }
+
+        pieces.sort_by_key(|piece| piece.index);
+        assert_eq!(vec![0, 1, 2], pieces.iter().map(|piece| piece.index).collect::<Vec<_>>());
+        assert_eq!(Piece { index: 2, data: piece_data(2) }, pieces[2]);
+        assert_eq!(piece_data(1), pieces[1].data);
+        assert!(downloader.is_complete());
+        assert!(!downloader.wants(&addr(1)));
+    }
+
+    #[test]
+    fn test_resumes_from_partial_blocks() {
+        let mut downloader = Downloader::new(&info());
+        unchoked_peer(&mut downloader, &addr(1), 0b1000_0000);
+        let first = downloader.next_requests(&addr(1))[0];
+        downloader.block_received(&addr(1), 0, first.begin, &vec![0; BLOCK_SIZE as usize]).unwrap();
+        let partial = downloader.partial_blocks();
+        assert_eq!(vec![(first, vec![0; BLOCK_SIZE as usize])], partial);
+
+        let mut resumed = Downloader::new(&info()).with_partial_blocks(&partial).unwrap();
+        unchoked_peer(&mut resumed, &addr(1), 0b1000_0000);
+        let rest = resumed.next_requests(&addr(1));
+        assert_eq!(vec![Block { index: 0, begin: BLOCK_SIZE - first.begin, length: BLOCK_SIZE }], rest);
+        assert!(matches!(
+            resumed.block_received(&addr(1), 0, rest[0].begin, &vec![0; BLOCK_SIZE as usize]),
+            Ok(Some(Completed::Verified(_))),
+        ));
+    }
+
+    #[test]
+    fn test_partial_blocks_of_pieces_we_have_are_dropped() {
+        let blocks = vec![(Block { index: 2, begin: 0, length: 100 }, piece_data(2))];
+        let downloader = Downloader::new(&info()).with_have(&[false, false, true]).unwrap().with_partial_blocks(&blocks).unwrap();
+        assert_eq!(Vec::<(Block, Vec<u8>)>::new(), downloader.partial_blocks());
+
+        // A block that would complete the piece can't be trusted without a hash check.
+        let downloader = Downloader::new(&info()).with_partial_blocks(&blocks).unwrap();
+        assert_eq!(Vec::<(Block, Vec<u8>)>::new(), downloader.partial_blocks());
+
+        let blocks = vec![(Block { index: 3, begin: 0, length: 1 }, vec![0])];
+        assert_eq!(
+            Err(Error::new("There is no piece 3, the torrent has 3.".to_string())),
+            Downloader::new(&info()).with_partial_blocks(&blocks).map(|_| ()),
+        );
+    }
+
+    #[test]
+    fn test_bad_piece_is_downloaded_again() {
+        let mut downloader = Downloader::new(&info()).with_pipeline_depth(1);
+        unchoked_peer(&mut downloader, &addr(1), 0b1000_0000);
+        unchoked_peer(&mut downloader, &addr(2), 0b1000_0000);
+        let first = downloader.next_requests(&addr(1))[0];
+        let second = downloader.next_requests(&addr(2))[0];
+
+        let good = vec![0; BLOCK_SIZE as usize];
+        let bad = vec![9; BLOCK_SIZE as usize];
+        assert_eq!(Ok(None), downloader.block_received(&addr(1), 0, first.begin, &good));
+        assert_eq!(
+            Ok(Some(Completed::Failed { index: 0, peers: vec![addr(1), addr(2)] })),
+            downloader.block_received(&addr(2), 0, second.begin, &bad),
+        );
+
+        assert!(!downloader.have(0));
+        assert_eq!(1, downloader.hash_failures(&addr(2)));
+        assert_eq!(0, downloader.hash_failures(&addr(3)));
+        assert_eq!(vec![first], downloader.next_requests(&addr(1)));
+    }
+
+    #[test]
+    fn test_end_game_duplicates_and_cancels() {
+        let mut downloader = Downloader::new(&info());
+        unchoked_peer(&mut downloader, &addr(1), 0b0010_0000);
+        unchoked_peer(&mut downloader, &addr(2), 0b0010_0000);
+        let block = Block { index: 2, begin: 0, length: 100 };
+
+        assert_eq!(vec![block], downloader.next_requests(&addr(1)));
+        assert!(!downloader.in_end_game());
+        assert_eq!(Vec::<Block>::new(), downloader.next_requests(&addr(2)));
+
+        downloader.have[0] = true;
+        downloader.have[1] = true;
+        assert!(downloader.in_end_game());
+        assert_eq!(vec![block], downloader.next_requests(&addr(2)));
+        assert_eq!(Vec::<Block>::new(), downloader.next_requests(&addr(2)));
+
+        let completed = downloader.block_received(&addr(2), 2, 0, &piece_data(2)).unwrap();
+        assert_eq!(Some(Completed::Verified(Piece { index: 2, data: piece_data(2) })), completed);
+        assert_eq!(vec![block], downloader.take_cancels(&addr(1)));
+        assert_eq!(Vec::<Block>::new(), downloader.take_cancels(&addr(2)));
+
+        assert_eq!(Ok(None), downloader.block_received(&addr(1), 2, 0, &piece_data(2)));
+        assert_eq!(EndGameStats { duplicate_requests: 1, cancels: 1, wasted_bytes: 100 }, downloader.end_game_stats());
+    }
+
+    #[test]
+    fn test_starts_from_pieces_we_have() {
+        let mut downloader = Downloader::new(&info()).with_have(&[true, false, true]).unwrap();
+        unchoked_peer(&mut downloader, &addr(1), SEEDER);
+
+        assert_eq!(vec![0b1010_0000], downloader.bitfield());
+        assert_eq!(vec![1, 1], downloader.next_requests(&addr(1)).iter().map(|block| block.index).collect::<Vec<_>>());
+
+        assert_eq!(
+            Err(Error::new("Have lists 2 pieces, the torrent has 3.".to_string())),
+            Downloader::new(&info()).with_have(&[true, false]).map(|_| ()),
+        );
+    }
+
+    #[test]
+    fn test_choke_and_disconnect_release_requests() {
+        let mut downloader = Downloader::new(&info()).with_pipeline_depth(2);
+        unchoked_peer(&mut downloader, &addr(1), 0b1000_0000);
+        unchoked_peer(&mut downloader, &addr(2), 0b1000_0000);
+        let first = downloader.next_requests(&addr(1));
+
+        downloader.peer_choked(&addr(1), true).unwrap();
+        assert_eq!(first, downloader.next_requests(&addr(2)));
+
+        downloader.remove_peer(&addr(2));
+        downloader.peer_choked(&addr(1), false).unwrap();
+        assert_eq!(first, downloader.next_requests(&addr(1)));
+    }
+
+    #[test]
+    fn test_err_on_bad_peer_messages() {
+        let mut downloader = Downloader::new(&info());
+        downloader.add_peer(addr(1));
+
+        assert_eq!(
+            Err(Error::new("Bitfield is 2 bytes, expected 1.".to_string())),
+            downloader.peer_bitfield(&addr(1), &[0, 0]),
+        );
+        assert_eq!(
+            Err(Error::new("Peer 127.0.0.1:1 has piece 3, there are only 3.".to_string())),
+            downloader.peer_has(&addr(1), 3),
+        );
+        assert_eq!(
+            Err(Error::new("Unknown peer 127.0.0.1:2.".to_string())),
+            downloader.peer_choked(&addr(2), false),
+        );
+    }
+}
@@ -0,0 +1,8 @@
+pub mod block;
+pub mod piece_buffer;
+pub mod picker;
+pub mod downloader;
+pub mod session;
+pub mod verifier;
+pub mod recheck;
+pub mod error;
@@ -0,0 +1,233 @@
+use std::cmp::Reverse;
+use rand::Rng;
+use rand::seq::SliceRandom;
+
+use crate::download::error::Error;
+use crate::torrent::file_layout::TorrentFile;
+use crate::torrent::torrent_info::TorrentInfo;
+
+/// Until this many pieces are verified pieces are picked at random, so that we
+/// quickly have something to trade instead of all waiting on the rarest piece.
+pub const DEFAULT_RANDOM_FIRST: usize = 4;
+
+#[derive(Eq, PartialEq, Ord, PartialOrd, Clone, Copy, Debug)]
+pub enum Priority {
+    /// Not downloaded unless the piece is shared with a wanted file.
+    Skip,
+    Normal,
+    High,
+}
+
+/// Orders the pieces to start next: by the priority of their files, then
+/// rarest-first among the connected peers with ties broken at random.
+#[derive(Eq, PartialEq, Clone, Debug)]
+pub struct Picker {
+    availability: Vec<u32>,
+    files: Vec<TorrentFile>,
+    file_priorities: Vec<Priority>,
+    piece_priorities: Vec<Priority>,
+    piece_length: i64,
+    random_first: usize,
+}
+
+impl Picker {
+    pub fn new(info: &TorrentInfo) -> Self {
+        let files = info.files();
+        let piece_count = info.piece_count() as usize;
+        Self {
+            availability: vec![0; piece_count],
+            file_priorities: vec![Priority::Normal; files.len()],
+            piece_priorities: vec![Priority::Normal; piece_count],
+            files,
+            piece_length: info.piece_length,
+            random_first: DEFAULT_RANDOM_FIRST,
+        }
+    }
+
+    pub fn with_random_first(mut self, random_first: usize) -> Self {
+        self.random_first = random_first;
+        self
+    }
+
+    /// How many connected peers have piece `index`.
+    pub fn availability(&self, index: u32) -> u32 {
+        self.availability[index as usize]
+    }
+
+    /// A peer announced piece `index` with a `have` message.
+    pub fn peer_has(&mut self, index: u32) {
+        self.availability[index as usize] += 1;
+    }
+
+    /// A peer's `has` replaced what it had before, e.g. with its bitfield.
+    pub fn peer_changed(&mut self, before: &[bool], after: &[bool]) {
+        for (count, (before, after)) in self.availability.iter_mut().zip(before.iter().zip(after)) {
+            match (before, after) {
+                (false, true) => *count += 1,
+                (true, false) => *count -= 1,
+                _ => (),
+            }
+        }
+    }
+
+    /// A peer with the pieces in `has` went away.
+    pub fn peer_lost(&mut self, has: &[bool]) {
+        self.peer_changed(has, &vec![false; has.len()]);
+    }
+
+    pub fn file_priority(&self, file: usize) -> Priority {
+        self.file_priorities[file]
+    }
+
+    /// Sets the priority of the `file`th file, a piece gets the highest
+    /// priority of the files it holds data of.
+    pub fn set_file_priority(&mut self, file: usize, priority: Priority) -> Result<(), Error> {
+        match self.file_priorities.get_mut(file) {
+            Some(current) => *current = priority,
+            None => return Err(Error::new(format!("There is no file {}, the torrent has {}.", file, self.files.len()))),
+        }
+
+        for (index, piece_priority) in self.piece_priorities.iter_mut().enumerate() {
+            let start = index as i64 * self.piece_length;
+            let end = start + self.piece_length;
+            *piece_priority = self.files.iter()
+                .zip(&self.file_priorities)
+                .filter(|(file, _)| file.offset < end && start < file.offset + file.length)
+                .map(|(_, priority)| *priority)
+                .max()
+                .unwrap_or(Priority::Normal);
+        }
+        Ok(())
+    }
+
+    pub fn piece_priority(&self, index: u32) -> Priority {
+        self.piece_priorities[index as usize]
+    }
+
+    /// Orders `candidates`, pieces some peer can give us that we don't have
+    /// yet, in the order to start them. Skipped pieces are left out. While
+    /// fewer than the random-first count of pieces are `verified` the order
+    /// ignores availability.
+    pub fn pick<R: Rng>(&self, candidates: &[u32], verified: usize, rng: &mut R) -> Vec<u32> {
+        let mut picked: Vec<u32> = candidates.iter()
+            .cloned()
+            .filter(|index| self.piece_priority(*index) != Priority::Skip)
+            .collect();
+
+        picked.shuffle(rng);
+        if verified < self.random_first {
+            picked.sort_by_key(|index| Reverse(self.piece_priority(*index)));
+        } else {
+            picked.sort_by_key(|index| (Reverse(self.piece_priority(*index)), self.availability(*index)));
+        }
+        picked
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rand::SeedableRng;
+    use rand::rngs::StdRng;
+    use crate::bencoding::bencode::DictMap;
+    use crate::torrent::file_layout::{FileEntry, Layout};
+
+    /// Four pieces of 10 bytes, file `a` holds pieces 0 and 1 and the first
+    /// bytes of piece 2, which `b` shares.
+    fn info() -> TorrentInfo {
+        TorrentInfo {
+            layout: Layout::MultiFile {
+                files: vec![
+                    FileEntry { length: 25, path: vec!["a".to_string()], extra: DictMap::new() },
+                    FileEntry { length: 15, path: vec!["b".to_string()], extra: DictMap::new() },
+                ],
+            },
+            name: "dir".to_string(),
+            piece_length: 10,
+            private: None,
+            pieces: vec![0; 80],
+            extra: DictMap::new(),
+        }
+    }
+
+    fn rng() -> StdRng {
+        StdRng::seed_from_u64(0)
+    }
+
+    #[test]
+    fn test_availability() {
+        let mut picker = Picker::new(&info());
+        picker.peer_changed(&[false; 4], &[true, true, false, false]);
+        picker.peer_changed(&[false; 4], &[true, false, false, false]);
+        picker.peer_has(3);
+        picker.peer_lost(&[true, true, false, false]);
+
+        assert_eq!(vec![1, 0, 0, 1], (0..4).map(|index| picker.availability(index)).collect::<Vec<_>>());
+    }
+
+    #[test]
+    fn test_rarest_first() {
+        let mut picker = Picker::new(&info()).with_random_first(0);
+        picker.peer_changed(&[false; 4], &[true; 4]);
+        picker.peer_changed(&[false; 4], &[true, true, false, true]);
+        picker.peer_changed(&[false; 4], &[true, false, false, true]);
+        picker.peer_has(3);
+
+        assert_eq!(vec![2, 1, 0, 3], picker.pick(&[0, 1, 2, 3], 0, &mut rng()));
+        assert_eq!(vec![1, 0], picker.pick(&[0, 1], 0, &mut rng()));
+    }
+
+    #[test]
+    fn test_ties_are_broken_at_random() {
+        let picker = Picker::new(&info()).with_random_first(0);
+
+        let orders: Vec<Vec<u32>> = (0..20)
+            .map(|seed| picker.pick(&[0, 1, 2, 3], 0, &mut StdRng::seed_from_u64(seed)))
+            .collect();
+        assert!(orders.iter().any(|order| order != &orders[0]));
+    }
+
+    #[test]
+    fn test_random_first_ignores_availability() {
+        let mut picker = Picker::new(&info()).with_random_first(2);
+        picker.peer_changed(&[false; 4], &[true, true, true, false]);
+
+        let orders: Vec<Vec<u32>> = (0..20)
+            .map(|seed| picker.pick(&[0, 1, 2, 3], 1, &mut StdRng::seed_from_u64(seed)))
+            .collect();
+        assert!(orders.iter().any(|order| order[0] != 3));
+        assert_eq!(3, picker.pick(&[0, 1, 2, 3], 2, &mut rng())[0]);
+    }
+
+    #[test]
+    fn test_file_priorities() {
+        let mut picker = Picker::new(&info()).with_random_first(0);
+        picker.set_file_priority(0, Priority::Skip).unwrap();
+
+        assert_eq!(vec![Priority::Skip, Priority::Skip, Priority::Normal, Priority::Normal],
+                   (0..4).map(|index| picker.piece_priority(index)).collect::<Vec<_>>());
+        assert_eq!(vec![2, 3], sorted(picker.pick(&[0, 1, 2, 3], 0, &mut rng())));
+
+        picker.set_file_priority(0, Priority::High).unwrap();
+        picker.peer_has(0);
+        picker.peer_has(1);
+        let picked = picker.pick(&[0, 1, 2, 3], 0, &mut rng());
+        assert_eq!(Priority::High, picker.piece_priority(2));
+        assert_eq!(2, picked[0]);
+        assert_eq!(vec![0, 1], sorted(picked[1..3].to_vec()));
+        assert_eq!(3, picked[3]);
+    }
+
+    #[test]
+    fn test_err_when_file_does_not_exist() {
+        assert_eq!(
+            Err(Error::new("There is no file 2, the torrent has 2.".to_string())),
+            Picker::new(&info()).set_file_priority(2, Priority::High),
+        );
+    }
+
+    fn sorted(mut pieces: Vec<u32>) -> Vec<u32> {
+        pieces.sort();
+        pieces
+    }
+}
@@ -0,0 +1,145 @@
+use crate::download::block::{Block, BLOCK_SIZE};
+use crate::download::error::Error;
+
+#[derive(Eq, PartialEq, Clone, Copy, Debug)]
+pub enum BlockState {
+    Missing,
+    Requested,
+    Received,
+}
+
+/// Reassembles one piece from its blocks.
+#[derive(Eq, PartialEq, Clone, Debug)]
+pub struct PieceBuffer {
+    index: u32,
+    data: Vec<u8>,
+    blocks: Vec<BlockState>,
+}
+
+impl PieceBuffer {
+    pub fn new(index: u32, piece_len: u32) -> Self {
+        let block_count = Block::split(index, piece_len).len();
+        Self { index, data: vec![0; piece_len as usize], blocks: vec![BlockState::Missing; block_count] }
+    }
+
+    pub fn index(&self) -> u32 {
+        self.index
+    }
+
+    pub fn blocks(&self) -> Vec<Block> {
+        Block::split(self.index, self.data.len() as u32)
+    }
+
+    pub fn state(&self, block: &Block) -> BlockState {
+        self.blocks[(block.begin / BLOCK_SIZE) as usize]
+    }
+
+    /// The blocks nobody has been asked for yet.
+    pub fn missing(&self) -> Vec<Block> {
+        self.blocks().into_iter().filter(|block| self.state(block) == BlockState::Missing).collect()
+    }
+
+    /// Whether `block` is one of this piece's blocks and is already in.
+    pub fn is_received(&self, block: &Block) -> bool {
+        block.begin.is_multiple_of(BLOCK_SIZE)
+            && self.blocks.get((block.begin / BLOCK_SIZE) as usize) == Some(&BlockState::Received)
+    }
+
+    pub fn mark_requested(&mut self, block: &Block) {
+        self.set_state(block, BlockState::Requested);
+    }
+
+    /// Puts a requested block back so that it is handed out again.
+    pub fn release(&mut self, block: &Block) {
+        if self.state(block) == BlockState::Requested {
+            self.set_state(block, BlockState::Missing);
+        }
+    }
+
+    /// Copies a received block into place, it must line up with one of the
+    /// blocks the piece was split into.
+    pub fn add_block(&mut self, begin: u32, data: &[u8]) -> Result<(), Error> {
+        let expected = self.blocks().into_iter().find(|block| block.begin == begin);
+        match expected {
+            Some(block) if block.length as usize == data.len() => {
+                let start = begin as usize;
+                self.data[start..start + data.len()].copy_from_slice(data);
+                self.set_state(&block, BlockState::Received);
+                Ok(())
+            },
+            _ => Err(Error::new(format!(
+                "Block at {} of {} bytes does not fit piece {}.", begin, data.len(), self.index,
+            ))),
+        }
+    }
+
+    /// The blocks that are in, with their data.
+    pub fn received(&self) -> Vec<(Block, &[u8])> {
+        self.blocks().into_iter()
+            .filter(|block| self.state(block) == BlockState::Received)
+            .map(|block| {
+                let start = block.begin as usize;
+                (block, &self.data[start..start + block.length as usize])
+            })
+            .collect()
+    }
+
+    pub fn is_complete(&self) -> bool {
+        self.blocks.iter().all(|state| *state == BlockState::Received)
+    }
+
+    pub fn into_data(self) -> Vec<u8> {
+        self.data
+    }
+
+    fn set_state(&mut self, block: &Block, state: BlockState) {
+        self.blocks[(block.begin / BLOCK_SIZE) as usize] = state;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_reassembles_out_of_order_blocks() {
+        let len = BLOCK_SIZE + 3;
+        let mut buffer = PieceBuffer::new(0, len);
+        let first = vec![1; BLOCK_SIZE as usize];
+
+        buffer.add_block(BLOCK_SIZE, &[2, 3, 4]).unwrap();
+        assert!(!buffer.is_complete());
+        assert_eq!(vec![(Block { index: 0, begin: BLOCK_SIZE, length: 3 }, &[2, 3, 4][..])], buffer.received());
+        assert!(buffer.is_received(&Block { index: 0, begin: BLOCK_SIZE, length: 3 }));
+        assert!(!buffer.is_received(&Block { index: 0, begin: 0, length: BLOCK_SIZE }));
+        assert!(!buffer.is_received(&Block { index: 0, begin: 2 * BLOCK_SIZE, length: 3 }));
+        buffer.add_block(0, &first).unwrap();
+        assert!(buffer.is_complete());
+
+        let mut expected = first;
+        expected.extend_from_slice(&[2, 3, 4]);
+        assert_eq!(expected, buffer.into_data());
+    }
+
+    #[test]
+    fn test_request_and_release() {
+        let mut buffer = PieceBuffer::new(0, 2 * BLOCK_SIZE);
+        let blocks = buffer.blocks();
+
+        buffer.mark_requested(&blocks[0]);
+        assert_eq!(vec![blocks[1]], buffer.missing());
+        buffer.release(&blocks[0]);
+        assert_eq!(blocks, buffer.missing());
+    }
+
+    #[test]
+    fn test_err_when_block_does_not_fit() {
+        let mut buffer = PieceBuffer::new(4, BLOCK_SIZE + 3);
+
+        assert_eq!(
+            Err(Error::new(format!("Block at {} of 4 bytes does not fit piece 4.", BLOCK_SIZE))),
+            buffer.add_block(BLOCK_SIZE, &[0; 4]),
+        );
+        assert!(buffer.add_block(1, &[0; 3]).is_err());
+    }
+}
@@ -0,0 +1,78 @@
+use crate::download::verifier::Verifier;
+use crate::storage::storage::Storage;
+use crate::torrent::torrent_info::TorrentInfo;
+
+/// How far a recheck has come, reported after each piece.
+#[derive(Eq, PartialEq, Clone, Copy, Debug)]
+pub struct Progress {
+    pub checked: u32,
+    pub total: u32,
+    /// Pieces found so far that match their hash.
+    pub have: u32,
+}
+
+/// Hashes every piece in `storage` against the torrent's piece hashes and
+/// returns which pieces we have. Pieces that can't be read, e.g. because a
+/// file is shorter than it should be, are missing rather than an error.
+pub fn recheck<F>(info: &TorrentInfo, storage: &dyn Storage, mut progress: F) -> Vec<bool>
+    where F: FnMut(Progress)
+{
+    let verifier = Verifier::new(info);
+    let total = info.piece_count();
+    let mut have = Vec::with_capacity(total as usize);
+    let mut verified_count = 0;
+
+    for index in 0..total {
+        let piece_len = info.piece_len(index) as usize;
+        let verified = match storage.read_block(index, 0, piece_len) {
+            Ok(data) => verifier.verify(index, &data),
+            Err(_) => false,
+        };
+        have.push(verified);
+        verified_count += u32::from(verified);
+        progress(Progress { checked: index + 1, total, have: verified_count });
+    }
+
+    have
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::bencoding::bencode::DictMap;
+    use crate::download::verifier;
+    use crate::storage::memory::MemoryStorage;
+    use crate::torrent::file_layout::Layout;
+
+    fn info() -> TorrentInfo {
+        TorrentInfo {
+            layout: Layout::SingleFile { length: 10 },
+            name: "derek".to_string(),
+            piece_length: 4,
+            private: None,
+            pieces: [&b"abcd"[..], b"efgh", b"ij"].iter().flat_map(|piece| verifier::digest(piece).to_vec()).collect(),
+            extra: DictMap::new(),
+        }
+    }
+
+    #[test]
+    fn test_recheck_finds_verified_pieces() {
+        let storage = MemoryStorage::new(&info());
+        storage.write_block(0, 0, b"abcd").unwrap();
+        storage.write_block(1, 0, b"efgX").unwrap();
+        storage.write_block(2, 0, b"ij").unwrap();
+
+        let mut reports = Vec::new();
+        let have = recheck(&info(), &storage, |progress| reports.push(progress));
+
+        assert_eq!(vec![true, false, true], have);
+        assert_eq!(
+            vec![
+                Progress { checked: 1, total: 3, have: 1 },
+                Progress { checked: 2, total: 3, have: 1 },
+                Progress { checked: 3, total: 3, have: 2 },
+            ],
+            reports,
+        );
+    }
+}
@@ -0,0 +1,323 @@
+use std::net::SocketAddr;
+use std::sync::Arc;
+use std::time::Duration;
+use futures_util::{SinkExt, StreamExt};
+use tokio::io::{AsyncRead, AsyncWrite};
+use tokio::sync::{mpsc, Mutex, Notify};
+use tokio::time::{self, Instant};
+use tokio_util::codec::Framed;
+
+use crate::download::downloader::{Completed, Downloader, Piece};
+use crate::download::error::Error;
+use crate::peer::codec::MessageCodec;
+use crate::peer::message::Message;
+
+/// How many pieces that fail their hash check a peer may send us before it is
+/// dropped.
+pub const DEFAULT_MAX_HASH_FAILURES: u32 = 3;
+
+/// How long a peer may send nothing at all before it is dropped, peers send
+/// keep-alives well within this.
+pub const DEFAULT_IDLE_TIMEOUT: Duration = Duration::from_secs(120);
+
+/// How long we stay quiet before sending a keep-alive.
+const KEEP_ALIVE_INTERVAL: Duration = Duration::from_secs(60);
+
+/// Downloads from one peer over a connection that has completed the
+/// handshake. The `Downloader` is shared with the sessions of the other peers,
+/// verified pieces are sent to `pieces`.
+pub struct Session<S> {
+    framed: Framed<S, MessageCodec>,
+    addr: SocketAddr,
+    downloader: Arc<Mutex<Downloader>>,
+    pieces: mpsc::Sender<Piece>,
+    interested: bool,
+    max_hash_failures: u32,
+    idle_timeout: Duration,
+    last_sent: Instant,
+}
+
+impl<S> Session<S>
+    where S: AsyncRead + AsyncWrite + Unpin
+{
+    pub fn new(framed: Framed<S, MessageCodec>, addr: SocketAddr, downloader: Arc<Mutex<Downloader>>, pieces: mpsc::Sender<Piece>) -> Self {
+        Self {
+            framed,
+            addr,
+            downloader,
+            pieces,
+            interested: false,
+            max_hash_failures: DEFAULT_MAX_HASH_FAILURES,
+            idle_timeout: DEFAULT_IDLE_TIMEOUT,
+            last_sent: Instant::now(),
+        }
+    }
+
+    pub fn with_max_hash_failures(mut self, max_hash_failures: u32) -> Self {
+        self.max_hash_failures = max_hash_failures;
+        self
+    }
+
+    pub fn with_idle_timeout(mut self, idle_timeout: Duration) -> Self {
+        self.idle_timeout = idle_timeout;
+        self
+    }
+
+    /// Runs until the torrent is complete, the peer hangs up, goes quiet for
+    /// longer than the idle timeout or sent too many corrupt pieces. The
+    /// peer's outstanding requests are handed back to the downloader either
+    /// way.
+    pub async fn run(mut self) -> Result<(), Error> {
+        let notify = self.downloader.lock().await.add_peer(self.addr);
+        let result = self.exchange(&notify).await;
+        self.downloader.lock().await.remove_peer(&self.addr);
+        result
+    }
+
+    /// Handles messages as they arrive, and wakes up in between when another
+    /// peer sent a block we requested from this one as well, or when it is
+    /// time for a keep-alive.
+    async fn exchange(&mut self, notify: &Notify) -> Result<(), Error> {
+        let mut last_received = Instant::now();
+        loop {
+            let mut keep_alive = false;
+            let message = tokio::select! {
+                message = self.framed.next() => match message {
+                    Some(message) => Some(message?),
+                    None => return Ok(()),
+                },
+                _ = notify.notified() => None,
+                _ = time::delay_until(last_received + self.idle_timeout) => {
+                    return Err(Error::new(format!("Peer {} sent nothing for {:?}.", self.addr, self.idle_timeout)));
+                },
+                _ = time::delay_until(self.last_sent + KEEP_ALIVE_INTERVAL) => { keep_alive = true; None },
+            };
+            if message.is_some() {
+                last_received = Instant::now();
+            }
+            if keep_alive {
+                self.send(Message::KeepAlive).await?;
+            }
+
+            let mut downloader = self.downloader.lock().await;
+            let completed = match message {
+                Some(Message::Bitfield(bitfield)) => { downloader.peer_bitfield(&self.addr, &bitfield)?; None },
+                Some(Message::Have { index }) => { downloader.peer_has(&self.addr, index)?; None },
+                Some(Message::Choke) => { downloader.peer_choked(&self.addr, true)?; None },
+                Some(Message::Unchoke) => { downloader.peer_choked(&self.addr, false)?; None },
+                Some(Message::Piece { index, begin, block }) => downloader.block_received(&self.addr, index, begin, &block)?,
+                _ => None,
+            };
+            let hash_failures = downloader.hash_failures(&self.addr);
+            let complete = downloader.is_complete();
+            let interested = !self.interested && downloader.wants(&self.addr);
+            let cancels = downloader.take_cancels(&self.addr);
+            let requests = downloader.next_requests(&self.addr);
+            drop(downloader);
+
+            for block in cancels {
+                self.send(Message::Cancel { index: block.index, begin: block.begin, length: block.length }).await?;
+            }
+            if let Some(Completed::Failed { .. }) = completed {
+                if hash_failures >= self.max_hash_failures {
+                    return Err(Error::new(format!(
+                        "Peer {} sent {} pieces that failed their hash check.", self.addr, hash_failures,
+                    )));
+                }
+            }
+            if let Some(Completed::Verified(piece)) = completed {
+                if self.pieces.send(piece).await.is_err() {
+                    return Err(Error::new("Nobody is receiving pieces anymore.".to_string()));
+                }
+            }
+            if complete {
+                return Ok(());
+            }
+            if interested {
+                self.send(Message::Interested).await?;
+                self.interested = true;
+            }
+            for block in requests {
+                self.send(Message::Request { index: block.index, begin: block.begin, length: block.length }).await?;
+            }
+        }
+    }
+
+    async fn send(&mut self, message: Message) -> Result<(), Error> {
+        self.framed.send(message).await?;
+        self.last_sent = Instant::now();
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tokio::net::{TcpListener, TcpStream};
+    use crate::bencoding::bencode::DictMap;
+    use crate::download::verifier;
+    use crate::torrent::file_layout::Layout;
+    use crate::torrent::torrent_info::TorrentInfo;
+
+    const LENGTH: usize = 40000;
+
+    /// A full piece of two blocks and a short last piece of one short block.
+    fn info() -> TorrentInfo {
+        TorrentInfo {
+            layout: Layout::SingleFile { length: LENGTH as i64 },
+            name: "derek".to_string(),
+            piece_length: 32768,
+            private: None,
+            pieces: data().chunks(32768).flat_map(|piece| verifier::digest(piece).to_vec()).collect(),
+            extra: DictMap::new(),
+        }
+    }
+
+    fn data() -> Vec<u8> {
+        (0..LENGTH).map(|i| (i % 251) as u8).collect()
+    }
+
+    enum Seeder {
+        Honest,
+        /// Answers every request with the wrong bytes.
+        Corrupt,
+        /// Never answers, it passes on what it receives instead.
+        Stalled(mpsc::Sender<Message>),
+        /// Sends nothing at all but keeps the connection open.
+        Silent,
+    }
+
+    /// Accepts one connection, unchokes and answers every request from `data`
+    /// the way `behaviour` says.
+    async fn seeder(mut behaviour: Seeder) -> SocketAddr {
+        let mut listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+
+        tokio::spawn(async move {
+            let (stream, _) = listener.accept().await.unwrap();
+            let mut framed = Framed::new(stream, MessageCodec::new());
+            if let Seeder::Silent = behaviour {
+                while let Some(Ok(_)) = framed.next().await {}
+                return;
+            }
+            framed.send(Message::Bitfield(vec![0b1100_0000])).await.unwrap();
+            assert_eq!(Some(Ok(Message::Interested)), framed.next().await);
+            framed.send(Message::Unchoke).await.unwrap();
+
+            let data = data();
+            while let Some(Ok(message)) = framed.next().await {
+                match (message, &mut behaviour) {
+                    (message, Seeder::Stalled(received)) => received.send(message).await.unwrap(),
+                    (Message::Request { index, begin, length }, behaviour) => {
+                        let start = index as usize * 32768 + begin as usize;
+                        let mut block = data[start..start + length as usize].to_vec();
+                        if let Seeder::Corrupt = behaviour {
+                            block.iter_mut().for_each(|byte| *byte = !*byte);
+                        }
+                        // The session may have hung up on a corrupt seeder already.
+                        if framed.send(Message::Piece { index, begin, block }).await.is_err() {
+                            break;
+                        }
+                    },
+                    _ => (),
+                }
+            }
+        });
+
+        addr
+    }
+
+    async fn session(addr: SocketAddr, downloader: &Arc<Mutex<Downloader>>, pieces: &mpsc::Sender<Piece>) -> Session<TcpStream> {
+        let stream = TcpStream::connect(addr).await.unwrap();
+        Session::new(Framed::new(stream, MessageCodec::new()), addr, downloader.clone(), pieces.clone())
+    }
+
+    #[tokio::test]
+    async fn test_downloads_all_pieces() {
+        let addr = seeder(Seeder::Honest).await;
+        let downloader = Arc::new(Mutex::new(Downloader::new(&info()).with_pipeline_depth(2)));
+        let (sender, mut receiver) = mpsc::channel(2);
+
+        session(addr, &downloader, &sender).await.run().await.unwrap();
+        drop(sender);
+
+        let mut pieces = Vec::new();
+        while let Some(piece) = receiver.recv().await {
+            pieces.push(piece);
+        }
+        pieces.sort_by_key(|piece| piece.index);
+
+        assert_eq!(vec![32768, LENGTH - 32768], pieces.iter().map(|piece| piece.data.len()).collect::<Vec<_>>());
+        assert_eq!(data(), pieces.into_iter().flat_map(|piece| piece.data).collect::<Vec<_>>());
+        assert!(downloader.lock().await.is_complete());
+    }
+
+    #[tokio::test]
+    async fn test_peer_sending_corrupt_pieces_is_dropped() {
+        let addr = seeder(Seeder::Corrupt).await;
+        let downloader = Arc::new(Mutex::new(Downloader::new(&info()).with_pipeline_depth(1)));
+        let (sender, mut receiver) = mpsc::channel(2);
+
+        let result = session(addr, &downloader, &sender).await.with_max_hash_failures(2).run().await;
+        drop(sender);
+
+        assert_eq!(Err(Error::new(format!("Peer {} sent 2 pieces that failed their hash check.", addr))), result);
+        assert_eq!(None, receiver.recv().await);
+        let downloader = downloader.lock().await;
+        assert_eq!(2, downloader.hash_failures(&addr));
+        assert!(!downloader.wants(&addr));
+    }
+
+    #[tokio::test]
+    async fn test_silent_peer_is_dropped() {
+        let addr = seeder(Seeder::Silent).await;
+        let downloader = Arc::new(Mutex::new(Downloader::new(&info())));
+        let (sender, _receiver) = mpsc::channel(2);
+
+        let timeout = Duration::from_millis(50);
+        let result = session(addr, &downloader, &sender).await.with_idle_timeout(timeout).run().await;
+
+        assert_eq!(Err(Error::new(format!("Peer {} sent nothing for 50ms.", addr))), result);
+    }
+
+    #[tokio::test]
+    async fn test_end_game_requests_from_another_peer_and_cancels() {
+        let (stalled_sender, mut stalled) = mpsc::channel(16);
+        let stalled_addr = seeder(Seeder::Stalled(stalled_sender)).await;
+        let addr = seeder(Seeder::Honest).await;
+        let downloader = Arc::new(Mutex::new(Downloader::new(&info()).with_pipeline_depth(10)));
+        let (sender, mut receiver) = mpsc::channel(2);
+
+        let stalled_session = tokio::spawn(session(stalled_addr, &downloader, &sender).await.run());
+        let mut requests = Vec::new();
+        while requests.len() < 3 {
+            requests.push(stalled.recv().await.unwrap());
+        }
+        session(addr, &downloader, &sender).await.run().await.unwrap();
+        stalled_session.await.unwrap().unwrap();
+        drop(sender);
+
+        let mut cancels = Vec::new();
+        while let Some(message) = stalled.recv().await {
+            cancels.push(message);
+        }
+        let mut expected: Vec<Message> = requests.into_iter()
+            .map(|request| match request {
+                Message::Request { index, begin, length } => Message::Cancel { index, begin, length },
+                other => panic!("Unexpected {}.", other),
+            })
+            .collect();
+        expected.sort_by_key(|cancel| format!("{:?}", cancel));
+        cancels.sort_by_key(|cancel| format!("{:?}", cancel));
+        assert_eq!(expected, cancels);
+
+        let mut pieces = 0;
+        while receiver.recv().await.is_some() {
+            pieces += 1;
+        }
+        assert_eq!(2, pieces);
+        let stats = downloader.lock().await.end_game_stats();
+        assert_eq!(3, stats.duplicate_requests);
+        assert_eq!(3, stats.cancels);
+    }
+}
@@ -0,0 +1,74 @@
+use sha1::Digest;
+
+use crate::torrent::torrent_info::TorrentInfo;
+
+/// Checks downloaded pieces against the SHA-1 hashes in the torrent's
+/// `pieces`.
+#[derive(Eq, PartialEq, Clone, Debug)]
+pub struct Verifier {
+    hashes: Vec<[u8; 20]>,
+}
+
+impl Verifier {
+    pub fn new(info: &TorrentInfo) -> Self {
+        let hashes = info.piece_hashes()
+            .map(|hash| {
+                let mut bytes = [0; 20];
+                bytes.copy_from_slice(hash);
+                bytes
+            })
+            .collect();
+        Self { hashes }
+    }
+
+    /// Whether `data` is piece `index`, false for indexes past the last piece.
+    pub fn verify(&self, index: u32, data: &[u8]) -> bool {
+        match self.hashes.get(index as usize) {
+            Some(hash) => digest(data) == *hash,
+            None => false,
+        }
+    }
+}
+
+pub fn digest(data: &[u8]) -> [u8; 20] {
+    let mut bytes = [0; 20];
+    bytes.copy_from_slice(sha1::Sha1::digest(data).as_slice());
+    bytes
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::bencoding::bencode::DictMap;
+    use crate::torrent::file_layout::Layout;
+
+    #[test]
+    fn test_verify() {
+        let mut pieces = digest(b"abcd").to_vec();
+        pieces.extend_from_slice(&digest(b"ef"));
+        let verifier = Verifier::new(&TorrentInfo {
+            layout: Layout::SingleFile { length: 6 },
+            name: "derek".to_string(),
+            piece_length: 4,
+            private: None,
+            pieces,
+            extra: DictMap::new(),
+        });
+
+        assert!(verifier.verify(0, b"abcd"));
+        assert!(verifier.verify(1, b"ef"));
+        assert!(!verifier.verify(1, b"eg"));
+        assert!(!verifier.verify(2, b""));
+    }
+
+    #[test]
+    fn test_digest() {
+        assert_eq!(
+            [
+                0xa9, 0x99, 0x3e, 0x36, 0x47, 0x06, 0x81, 0x6a, 0xba, 0x3e,
+                0x25, 0x71, 0x78, 0x50, 0xc2, 0x6c, 0x9c, 0xd0, 0xd8, 0x9d,
+            ],
+            digest(b"abc"),
+        );
+    }
+}
@@ -0,0 +1,8 @@
+pub mod bencoding;
+pub mod torrent;
+pub mod client;
+pub mod peer;
+pub mod download;
+pub mod storage;
+#[cfg(test)]
+mod test_util;
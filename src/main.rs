@@ -1,11 +1,8 @@
 use std::fs;
-use std::io::Read;
-use tokio;
-mod bencoding;
-mod torrent;
-mod client;
-use torrent::torrent::Torrent;
-use client::client::Client;
+use std::io::{BufReader, Read};
+use torrent_rs::bencoding;
+use torrent_rs::torrent::torrent::Torrent;
+use torrent_rs::client::client::Client;
 
 #[tokio::main]
 async fn main() -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
@@ -13,11 +10,11 @@ async fn main() -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
     let file = fs::File::open(filename)?;
     let input = fs::read(filename).expect("Unable to read file");
 
-    let stream_data = bencoding::stream_decoder::decode(&mut file.bytes())?;
+    let stream_data = bencoding::stream_decoder::decode(&mut BufReader::new(file).bytes())?;
 
-    let data = bencoding::decoder::decode(input);
+    let data = bencoding::decoder::decode(input.clone())?;
     assert_eq!(data, stream_data);
-    let torrent = Torrent::from(stream_data)?;
+    let torrent = Torrent::from_bytes(&input)?;
 
     let mut client = Client::new(torrent);
     let tracker_info = client.tracker_info().await?;
@@ -0,0 +1,155 @@
+use bytes::{Buf, BytesMut};
+use byteorder::{ByteOrder, BigEndian};
+use tokio_util::codec::{Decoder, Encoder};
+
+use crate::peer::error::Error;
+use crate::peer::message::Message;
+
+/// Large enough for a 16 KiB block or the bitfield of a torrent with 2 million
+/// pieces.
+pub const DEFAULT_MAX_MESSAGE_LEN: usize = 1 << 18;
+
+/// Frames peer wire messages, for use with `tokio_util::codec::Framed` once
+/// the handshake is done. Messages longer than `max_len` are rejected before
+/// they are buffered.
+#[derive(Eq, PartialEq, Clone, Copy, Debug)]
+pub struct MessageCodec {
+    max_len: usize,
+}
+
+impl MessageCodec {
+    pub fn new() -> Self {
+        Self::with_max_len(DEFAULT_MAX_MESSAGE_LEN)
+    }
+
+    pub fn with_max_len(max_len: usize) -> Self {
+        Self { max_len }
+    }
+}
+
+impl Default for MessageCodec {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Decoder for MessageCodec {
+    type Item = Message;
+    type Error = Error;
+
+    fn decode(&mut self, src: &mut BytesMut) -> Result<Option<Message>, Error> {
+        if src.len() < 4 {
+            return Ok(None);
+        }
+
+        let len = BigEndian::read_u32(&src[..4]) as usize;
+        if len > self.max_len {
+            return Err(Error::new(format!("Message is {} bytes, the limit is {}.", len, self.max_len)));
+        }
+
+        if src.len() < 4 + len {
+            src.reserve(4 + len - src.len());
+            return Ok(None);
+        }
+
+        src.advance(4);
+        let payload = src.split_to(len);
+        Message::from_payload(&payload).map(Some)
+    }
+}
+
+impl Encoder<Message> for MessageCodec {
+    type Error = Error;
+
+    fn encode(&mut self, message: Message, dst: &mut BytesMut) -> Result<(), Error> {
+        let len = message.payload_len();
+        if len > self.max_len {
+            return Err(Error::new(format!("Message is {} bytes, the limit is {}.", len, self.max_len)));
+        }
+
+        dst.extend_from_slice(&message.to_bytes());
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tokio::io::AsyncWriteExt;
+    use tokio::net::{TcpListener, TcpStream};
+    use tokio::stream::StreamExt;
+    use tokio_util::codec::FramedRead;
+
+    fn encode(messages: Vec<Message>) -> BytesMut {
+        let mut codec = MessageCodec::new();
+        let mut dst = BytesMut::new();
+        for message in messages {
+            codec.encode(message, &mut dst).unwrap();
+        }
+        dst
+    }
+
+    #[test]
+    fn test_decodes_consecutive_frames() {
+        let mut src = encode(vec![Message::Unchoke, Message::KeepAlive, Message::Have { index: 3 }]);
+        let mut codec = MessageCodec::new();
+
+        assert_eq!(Ok(Some(Message::Unchoke)), codec.decode(&mut src));
+        assert_eq!(Ok(Some(Message::KeepAlive)), codec.decode(&mut src));
+        assert_eq!(Ok(Some(Message::Have { index: 3 })), codec.decode(&mut src));
+        assert_eq!(Ok(None), codec.decode(&mut src));
+    }
+
+    #[test]
+    fn test_waits_for_a_whole_frame() {
+        let bytes = encode(vec![Message::Piece { index: 0, begin: 0, block: vec![1, 2, 3] }]);
+        let mut codec = MessageCodec::new();
+        let mut src = BytesMut::new();
+
+        for byte in &bytes[..bytes.len() - 1] {
+            src.extend_from_slice(&[*byte]);
+            assert_eq!(Ok(None), codec.decode(&mut src));
+        }
+        src.extend_from_slice(&bytes[bytes.len() - 1..]);
+        assert_eq!(Ok(Some(Message::Piece { index: 0, begin: 0, block: vec![1, 2, 3] })), codec.decode(&mut src));
+    }
+
+    #[test]
+    fn test_err_when_message_is_too_long() {
+        let mut codec = MessageCodec::with_max_len(8);
+        let mut src = BytesMut::from(&[0, 0, 0, 9, 5][..]);
+
+        assert_eq!(Err(Error::new("Message is 9 bytes, the limit is 8.".to_string())), codec.decode(&mut src));
+        assert_eq!(
+            Err(Error::new("Message is 9 bytes, the limit is 8.".to_string())),
+            codec.encode(Message::Bitfield(vec![0; 8]), &mut BytesMut::new()),
+        );
+    }
+
+    #[test]
+    fn test_err_when_length_does_not_fit_the_id() {
+        let mut src = BytesMut::from(&[0, 0, 0, 2, 2, 0][..]);
+        assert_eq!(
+            Err(Error::new("Message 2 payload is 1 bytes, expected 0.".to_string())),
+            MessageCodec::new().decode(&mut src),
+        );
+    }
+
+    #[tokio::test]
+    async fn test_framed_over_tcp() {
+        let mut listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        tokio::spawn(async move {
+            let (mut stream, _) = listener.accept().await.unwrap();
+            stream.write_all(&encode(vec![Message::Interested, Message::Port(6881)])).await.unwrap();
+        });
+
+        let stream = TcpStream::connect(addr).await.unwrap();
+        let messages: Vec<Message> = FramedRead::new(stream, MessageCodec::new())
+            .map(|message| message.unwrap())
+            .collect()
+            .await;
+
+        assert_eq!(vec![Message::Interested, Message::Port(6881)], messages);
+    }
+}
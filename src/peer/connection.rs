@@ -0,0 +1,172 @@
+use std::fmt;
+use std::net::SocketAddr;
+use std::time::Duration;
+use tokio::io::{AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt};
+use tokio::net::TcpStream;
+use tokio::time;
+use tokio_util::codec::Framed;
+
+use crate::peer::codec::MessageCodec;
+use crate::peer::error::Error;
+use crate::peer::handshake::{Handshake, HANDSHAKE_LEN};
+use crate::torrent::info_hash::InfoHash;
+use crate::torrent::peer::Peer;
+
+pub const DEFAULT_HANDSHAKE_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// A TCP connection to a peer that has completed the handshake.
+#[derive(Debug)]
+pub struct Connection {
+    stream: TcpStream,
+    addr: SocketAddr,
+    remote: Handshake,
+}
+
+impl Connection {
+    /// Connects to `peer` and exchanges handshakes, giving up when both don't
+    /// finish within `timeout`.
+    pub async fn connect(peer: &Peer, ours: &Handshake, timeout: Duration) -> Result<Self, Error> {
+        let connect = async {
+            let mut stream = TcpStream::connect(peer.addr).await?;
+            let remote = handshake(&mut stream, ours, peer.peer_id).await?;
+            Ok(Self { stream, addr: peer.addr, remote })
+        };
+
+        match time::timeout(timeout, connect).await {
+            Ok(result) => result,
+            Err(_) => Err(Error::new(format!("Handshake with {} timed out.", peer.addr))),
+        }
+    }
+
+    pub fn addr(&self) -> SocketAddr {
+        self.addr
+    }
+
+    /// The remote's handshake, with its peer id and extension bits.
+    pub fn remote(&self) -> &Handshake {
+        &self.remote
+    }
+
+    pub fn peer_id(&self) -> &[u8; 20] {
+        &self.remote.peer_id
+    }
+
+    pub fn into_stream(self) -> TcpStream {
+        self.stream
+    }
+
+    /// Frames the rest of the connection as peer wire messages.
+    pub fn into_framed(self) -> Framed<TcpStream, MessageCodec> {
+        Framed::new(self.stream, MessageCodec::new())
+    }
+}
+
+/// Sends our handshake and reads the remote's, which must be for the same
+/// torrent. When the tracker told us the peer's id it has to match as well.
+pub async fn handshake<S>(stream: &mut S, ours: &Handshake, expected_peer_id: Option<[u8; 20]>) -> Result<Handshake, Error>
+    where S: AsyncRead + AsyncWrite + Unpin
+{
+    stream.write_all(&ours.to_bytes()).await?;
+
+    let mut bytes = [0; HANDSHAKE_LEN];
+    stream.read_exact(&mut bytes).await?;
+    let remote = Handshake::from_bytes(&bytes)?;
+
+    check_info_hash(&ours.info_hash, &remote.info_hash)?;
+    if let Some(expected) = expected_peer_id {
+        if expected != remote.peer_id {
+            return Err(Error::new("Peer id does not match the one the tracker gave.".to_string()));
+        }
+    }
+
+    Ok(remote)
+}
+
+fn check_info_hash(ours: &InfoHash, theirs: &InfoHash) -> Result<(), Error> {
+    if ours != theirs {
+        return Err(Error::new(format!("Peer sent info hash {}, expected {}.", theirs, ours)));
+    }
+    Ok(())
+}
+
+impl fmt::Display for Connection {
+    fn fmt(&self, fmt: &mut fmt::Formatter) -> fmt::Result {
+        write!(fmt, "Connection: {{ addr: {}, peer_id: {} }}", self.addr, String::from_utf8_lossy(&self.remote.peer_id))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tokio::net::TcpListener;
+
+    const REMOTE_ID: [u8; 20] = *b"-XX0001-remoteremote";
+
+    fn ours() -> Handshake {
+        Handshake::new(InfoHash::new([1; 20]), *b"-RS0001-abcdefghijkl")
+    }
+
+    /// Accepts one connection and answers with `reply` once our handshake
+    /// has arrived, `None` never answers.
+    async fn remote(reply: Option<Handshake>) -> Peer {
+        let mut listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+
+        tokio::spawn(async move {
+            let (mut stream, _) = listener.accept().await.unwrap();
+            let mut bytes = [0; HANDSHAKE_LEN];
+            stream.read_exact(&mut bytes).await.unwrap();
+            match reply {
+                Some(reply) => stream.write_all(&reply.to_bytes()).await.unwrap(),
+                None => time::delay_for(Duration::from_secs(1)).await,
+            }
+        });
+
+        Peer::new(addr)
+    }
+
+    #[tokio::test]
+    async fn test_connect() {
+        let mut theirs = Handshake::new(InfoHash::new([1; 20]), REMOTE_ID);
+        theirs.reserved[5] = 0x10;
+        let peer = remote(Some(theirs)).await;
+
+        let connection = Connection::connect(&peer, &ours(), DEFAULT_HANDSHAKE_TIMEOUT).await.unwrap();
+
+        assert_eq!(&REMOTE_ID, connection.peer_id());
+        assert!(connection.remote().supports_extension_protocol());
+        assert_eq!(peer.addr, connection.addr());
+    }
+
+    #[tokio::test]
+    async fn test_err_when_info_hash_differs() {
+        let peer = remote(Some(Handshake::new(InfoHash::new([2; 20]), REMOTE_ID))).await;
+
+        let error = Connection::connect(&peer, &ours(), DEFAULT_HANDSHAKE_TIMEOUT).await.unwrap_err();
+        assert_eq!(
+            Error::new(format!("Peer sent info hash {}, expected {}.", InfoHash::new([2; 20]), InfoHash::new([1; 20]))),
+            error,
+        );
+    }
+
+    #[tokio::test]
+    async fn test_err_when_peer_id_differs_from_tracker() {
+        let mut peer = remote(Some(Handshake::new(InfoHash::new([1; 20]), REMOTE_ID))).await;
+        peer.peer_id = Some([0; 20]);
+
+        assert_eq!(
+            Err(Error::new("Peer id does not match the one the tracker gave.".to_string())),
+            Connection::connect(&peer, &ours(), DEFAULT_HANDSHAKE_TIMEOUT).await.map(|c| *c.peer_id()),
+        );
+    }
+
+    #[tokio::test]
+    async fn test_err_when_handshake_stalls() {
+        let peer = remote(None).await;
+
+        assert_eq!(
+            Err(Error::new(format!("Handshake with {} timed out.", peer.addr))),
+            Connection::connect(&peer, &ours(), Duration::from_millis(50)).await.map(|c| *c.peer_id()),
+        );
+    }
+}
@@ -0,0 +1,122 @@
+use std::convert::TryFrom;
+use std::fmt;
+
+use crate::peer::error::Error;
+use crate::torrent::info_hash::InfoHash;
+
+pub const PROTOCOL: &[u8; 19] = b"BitTorrent protocol";
+pub const HANDSHAKE_LEN: usize = 1 + 19 + 8 + 20 + 20;
+
+/// The first message on a peer connection: the protocol string, 8 reserved
+/// bytes advertising extensions, then the torrent's info hash and the
+/// sender's peer id.
+#[derive(Eq, PartialEq, Clone, Copy, Debug)]
+pub struct Handshake {
+    pub reserved: [u8; 8],
+    pub info_hash: InfoHash,
+    pub peer_id: [u8; 20],
+}
+
+impl Handshake {
+    pub fn new(info_hash: InfoHash, peer_id: [u8; 20]) -> Self {
+        Self { reserved: [0; 8], info_hash, peer_id }
+    }
+
+    pub fn to_bytes(&self) -> [u8; HANDSHAKE_LEN] {
+        let mut bytes = [0; HANDSHAKE_LEN];
+        bytes[0] = PROTOCOL.len() as u8;
+        bytes[1..20].copy_from_slice(PROTOCOL);
+        bytes[20..28].copy_from_slice(&self.reserved);
+        bytes[28..48].copy_from_slice(self.info_hash.as_bytes());
+        bytes[48..68].copy_from_slice(&self.peer_id);
+        bytes
+    }
+
+    pub fn from_bytes(bytes: &[u8]) -> Result<Self, Error> {
+        if bytes.len() != HANDSHAKE_LEN {
+            return Err(Error::new(format!("Handshake is {} bytes, expected {}.", bytes.len(), HANDSHAKE_LEN)));
+        }
+        if bytes[0] as usize != PROTOCOL.len() || &bytes[1..20] != PROTOCOL {
+            return Err(Error::new("Peer does not speak the BitTorrent protocol.".to_string()));
+        }
+
+        let mut reserved = [0; 8];
+        reserved.copy_from_slice(&bytes[20..28]);
+        let info_hash = InfoHash::try_from(&bytes[28..48])?;
+        let mut peer_id = [0; 20];
+        peer_id.copy_from_slice(&bytes[48..68]);
+
+        Ok(Self { reserved, info_hash, peer_id })
+    }
+
+    /// BEP 10, the extension protocol.
+    pub fn supports_extension_protocol(&self) -> bool {
+        self.reserved[5] & 0x10 != 0
+    }
+
+    /// BEP 6, the fast extension.
+    pub fn supports_fast(&self) -> bool {
+        self.reserved[7] & 0x04 != 0
+    }
+
+    /// BEP 5, the DHT.
+    pub fn supports_dht(&self) -> bool {
+        self.reserved[7] & 0x01 != 0
+    }
+}
+
+impl fmt::Display for Handshake {
+    fn fmt(&self, fmt: &mut fmt::Formatter) -> fmt::Result {
+        write!(fmt, "Handshake: {{ info_hash: {}, peer_id: {} }}", self.info_hash, String::from_utf8_lossy(&self.peer_id))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn handshake() -> Handshake {
+        Handshake::new(InfoHash::new([1; 20]), *b"-RS0001-abcdefghijkl")
+    }
+
+    #[test]
+    fn test_round_trip() {
+        let bytes = handshake().to_bytes();
+
+        assert_eq!(19, bytes[0]);
+        assert_eq!(PROTOCOL, &bytes[1..20]);
+        assert_eq!(Ok(handshake()), Handshake::from_bytes(&bytes));
+    }
+
+    #[test]
+    fn test_extension_bits() {
+        let mut handshake = handshake();
+        assert!(!handshake.supports_extension_protocol());
+        assert!(!handshake.supports_fast());
+        assert!(!handshake.supports_dht());
+
+        handshake.reserved = [0, 0, 0, 0, 0, 0x10, 0, 0x05];
+        assert!(handshake.supports_extension_protocol());
+        assert!(handshake.supports_fast());
+        assert!(handshake.supports_dht());
+    }
+
+    #[test]
+    fn test_err_when_protocol_is_wrong() {
+        let mut bytes = handshake().to_bytes();
+        bytes[1] = b'b';
+
+        assert_eq!(
+            Err(Error::new("Peer does not speak the BitTorrent protocol.".to_string())),
+            Handshake::from_bytes(&bytes),
+        );
+    }
+
+    #[test]
+    fn test_err_when_length_is_wrong() {
+        assert_eq!(
+            Err(Error::new("Handshake is 20 bytes, expected 68.".to_string())),
+            Handshake::from_bytes(&handshake().to_bytes()[..20]),
+        );
+    }
+}
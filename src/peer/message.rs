@@ -0,0 +1,228 @@
+use std::fmt;
+use byteorder::{ByteOrder, BigEndian};
+
+use crate::peer::error::Error;
+
+const CHOKE: u8 = 0;
+const UNCHOKE: u8 = 1;
+const INTERESTED: u8 = 2;
+const NOT_INTERESTED: u8 = 3;
+const HAVE: u8 = 4;
+const BITFIELD: u8 = 5;
+const REQUEST: u8 = 6;
+const PIECE: u8 = 7;
+const CANCEL: u8 = 8;
+const PORT: u8 = 9;
+
+/// A message of the peer wire protocol, after the handshake every message is
+/// a 4 byte length followed by a 1 byte id and its payload.
+#[derive(Eq, PartialEq, Clone, Debug)]
+pub enum Message {
+    KeepAlive,
+    Choke,
+    Unchoke,
+    Interested,
+    NotInterested,
+    Have { index: u32 },
+    Bitfield(Vec<u8>),
+    Request { index: u32, begin: u32, length: u32 },
+    Piece { index: u32, begin: u32, block: Vec<u8> },
+    Cancel { index: u32, begin: u32, length: u32 },
+    Port(u16),
+}
+
+impl Message {
+    /// Parses a message from the bytes following its length prefix.
+    pub fn from_payload(payload: &[u8]) -> Result<Self, Error> {
+        let (id, body) = match payload.split_first() {
+            Some((id, body)) => (*id, body),
+            None => return Ok(Message::KeepAlive),
+        };
+
+        let message = match id {
+            CHOKE => { expect_len(id, body, 0)?; Message::Choke },
+            UNCHOKE => { expect_len(id, body, 0)?; Message::Unchoke },
+            INTERESTED => { expect_len(id, body, 0)?; Message::Interested },
+            NOT_INTERESTED => { expect_len(id, body, 0)?; Message::NotInterested },
+            HAVE => {
+                expect_len(id, body, 4)?;
+                Message::Have { index: BigEndian::read_u32(body) }
+            },
+            BITFIELD => Message::Bitfield(body.to_vec()),
+            REQUEST | CANCEL => {
+                expect_len(id, body, 12)?;
+                let index = BigEndian::read_u32(&body[..4]);
+                let begin = BigEndian::read_u32(&body[4..8]);
+                let length = BigEndian::read_u32(&body[8..12]);
+                if id == REQUEST {
+                    Message::Request { index, begin, length }
+                } else {
+                    Message::Cancel { index, begin, length }
+                }
+            },
+            PIECE => {
+                if body.len() < 8 {
+                    return Err(Error::new(format!("Message {} payload is {} bytes, expected at least 8.", id, body.len())));
+                }
+                Message::Piece {
+                    index: BigEndian::read_u32(&body[..4]),
+                    begin: BigEndian::read_u32(&body[4..8]),
+                    block: body[8..].to_vec(),
+                }
+            },
+            PORT => {
+                expect_len(id, body, 2)?;
+                Message::Port(BigEndian::read_u16(body))
+            },
+            _ => return Err(Error::new(format!("Unknown message id {}.", id))),
+        };
+
+        Ok(message)
+    }
+
+    /// The length of the payload, i.e. the value of the length prefix.
+    pub fn payload_len(&self) -> usize {
+        match self {
+            Message::KeepAlive => 0,
+            Message::Choke | Message::Unchoke | Message::Interested | Message::NotInterested => 1,
+            Message::Have { .. } => 5,
+            Message::Bitfield(bitfield) => 1 + bitfield.len(),
+            Message::Request { .. } | Message::Cancel { .. } => 13,
+            Message::Piece { block, .. } => 9 + block.len(),
+            Message::Port(_) => 3,
+        }
+    }
+
+    /// Appends the message, with its length prefix, to `dst`.
+    pub fn write_to(&self, dst: &mut Vec<u8>) {
+        let mut buf = [0; 4];
+        BigEndian::write_u32(&mut buf, self.payload_len() as u32);
+        dst.extend_from_slice(&buf);
+
+        match self {
+            Message::KeepAlive => (),
+            Message::Choke => dst.push(CHOKE),
+            Message::Unchoke => dst.push(UNCHOKE),
+            Message::Interested => dst.push(INTERESTED),
+            Message::NotInterested => dst.push(NOT_INTERESTED),
+            Message::Have { index } => {
+                dst.push(HAVE);
+                push_u32(dst, *index);
+            },
+            Message::Bitfield(bitfield) => {
+                dst.push(BITFIELD);
+                dst.extend_from_slice(bitfield);
+            },
+            Message::Request { index, begin, length } | Message::Cancel { index, begin, length } => {
+                dst.push(if let Message::Request { .. } = self { REQUEST } else { CANCEL });
+                push_u32(dst, *index);
+                push_u32(dst, *begin);
+                push_u32(dst, *length);
+            },
+            Message::Piece { index, begin, block } => {
+                dst.push(PIECE);
+                push_u32(dst, *index);
+                push_u32(dst, *begin);
+                dst.extend_from_slice(block);
+            },
+            Message::Port(port) => {
+                dst.push(PORT);
+                let mut buf = [0; 2];
+                BigEndian::write_u16(&mut buf, *port);
+                dst.extend_from_slice(&buf);
+            },
+        }
+    }
+
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let mut bytes = Vec::with_capacity(4 + self.payload_len());
+        self.write_to(&mut bytes);
+        bytes
+    }
+}
+
+fn expect_len(id: u8, body: &[u8], expected: usize) -> Result<(), Error> {
+    if body.len() != expected {
+        return Err(Error::new(format!("Message {} payload is {} bytes, expected {}.", id, body.len(), expected)));
+    }
+    Ok(())
+}
+
+fn push_u32(dst: &mut Vec<u8>, value: u32) {
+    let mut buf = [0; 4];
+    BigEndian::write_u32(&mut buf, value);
+    dst.extend_from_slice(&buf);
+}
+
+impl fmt::Display for Message {
+    fn fmt(&self, fmt: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Message::Bitfield(bitfield) => write!(fmt, "Bitfield({} bytes)", bitfield.len()),
+            Message::Piece { index, begin, block } => write!(fmt, "Piece {{ index: {}, begin: {}, block: {} bytes }}", index, begin, block.len()),
+            other => write!(fmt, "{:?}", other),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Each message and the bytes it's sent as on the wire.
+    fn captured() -> Vec<(Message, Vec<u8>)> {
+        vec![
+            (Message::KeepAlive, vec![0, 0, 0, 0]),
+            (Message::Choke, vec![0, 0, 0, 1, 0]),
+            (Message::Unchoke, vec![0, 0, 0, 1, 1]),
+            (Message::Interested, vec![0, 0, 0, 1, 2]),
+            (Message::NotInterested, vec![0, 0, 0, 1, 3]),
+            (Message::Have { index: 258 }, vec![0, 0, 0, 5, 4, 0, 0, 1, 2]),
+            (Message::Bitfield(vec![0xff, 0x80]), vec![0, 0, 0, 3, 5, 0xff, 0x80]),
+            (
+                Message::Request { index: 1, begin: 16384, length: 16384 },
+                vec![0, 0, 0, 13, 6, 0, 0, 0, 1, 0, 0, 0x40, 0, 0, 0, 0x40, 0],
+            ),
+            (
+                Message::Piece { index: 1, begin: 2, block: vec![b'h', b'i'] },
+                vec![0, 0, 0, 11, 7, 0, 0, 0, 1, 0, 0, 0, 2, b'h', b'i'],
+            ),
+            (
+                Message::Cancel { index: 1, begin: 16384, length: 16384 },
+                vec![0, 0, 0, 13, 8, 0, 0, 0, 1, 0, 0, 0x40, 0, 0, 0, 0x40, 0],
+            ),
+            (Message::Port(6881), vec![0, 0, 0, 3, 9, 0x1a, 0xe1]),
+        ]
+    }
+
+    #[test]
+    fn test_round_trip_captured_bytes() {
+        for (message, bytes) in captured() {
+            assert_eq!(bytes, message.to_bytes(), "encoding {}", message);
+            assert_eq!(Ok(message), Message::from_payload(&bytes[4..]));
+        }
+    }
+
+    #[test]
+    fn test_err_when_fixed_length_is_wrong() {
+        assert_eq!(
+            Err(Error::new("Message 4 payload is 3 bytes, expected 4.".to_string())),
+            Message::from_payload(&[4, 0, 0, 1]),
+        );
+        assert_eq!(
+            Err(Error::new("Message 1 payload is 1 bytes, expected 0.".to_string())),
+            Message::from_payload(&[1, 0]),
+        );
+        assert_eq!(
+            Err(Error::new("Message 7 payload is 4 bytes, expected at least 8.".to_string())),
+            Message::from_payload(&[7, 0, 0, 0, 1]),
+        );
+    }
+
+    #[test]
+    fn test_err_when_id_is_unknown() {
+        assert_eq!(
+            Err(Error::new("Unknown message id 42.".to_string())),
+            Message::from_payload(&[42]),
+        );
+    }
+}
@@ -0,0 +1,5 @@
+pub mod handshake;
+pub mod connection;
+pub mod message;
+pub mod codec;
+pub mod error;
@@ -0,0 +1,202 @@
+use std::fs::{self, File, OpenOptions};
+use std::io::{Read, Seek, SeekFrom, Write};
+use std::path::{Path, PathBuf};
+use std::time::UNIX_EPOCH;
+
+use crate::storage::error::Error;
+use crate::storage::file_map::FileMap;
+use crate::storage::storage::{FileStat, Storage};
+use crate::torrent::torrent_info::TorrentInfo;
+
+static ZEROS: [u8; 64 * 1024] = [0; 64 * 1024];
+
+/// How files are brought to their full length when they are created.
+#[derive(Eq, PartialEq, Clone, Copy, Debug)]
+pub enum Allocation {
+    /// Only the length is set, the file system allocates blocks as they are
+    /// written.
+    Sparse,
+    /// Zeros are written up front so the download can't run out of space
+    /// halfway.
+    Full,
+}
+
+/// Stores the torrent data in its files under a download directory.
+#[derive(Eq, PartialEq, Clone, Debug)]
+pub struct DiskStorage {
+    root: PathBuf,
+    map: FileMap,
+}
+
+impl DiskStorage {
+    /// Creates the torrent's directories and files under `root`. Existing
+    /// files are kept so that a download can pick up where it left off.
+    pub fn create(info: &TorrentInfo, root: &Path, allocation: Allocation) -> Result<Self, Error> {
+        let storage = Self { root: root.to_path_buf(), map: FileMap::new(info) };
+
+        for (file, path) in storage.map.paths().iter().enumerate() {
+            let path = storage.root.join(path);
+            if let Some(parent) = path.parent() {
+                fs::create_dir_all(parent)?;
+            }
+            let handle = OpenOptions::new().write(true).create(true).truncate(false).open(&path)?;
+            allocate(handle, storage.map.file_len(file), allocation)?;
+        }
+
+        Ok(storage)
+    }
+
+    pub fn root(&self) -> &Path {
+        &self.root
+    }
+
+    fn path(&self, file: usize) -> PathBuf {
+        self.root.join(&self.map.paths()[file])
+    }
+}
+
+impl Storage for DiskStorage {
+    fn write_block(&self, index: u32, begin: u32, data: &[u8]) -> Result<(), Error> {
+        let mut written = 0;
+        for span in self.map.spans(index, begin, data.len())? {
+            let mut file = OpenOptions::new().write(true).open(self.path(span.file))?;
+            file.seek(SeekFrom::Start(span.offset))?;
+            file.write_all(&data[written..written + span.length])?;
+            written += span.length;
+        }
+        Ok(())
+    }
+
+    fn read_block(&self, index: u32, begin: u32, length: usize) -> Result<Vec<u8>, Error> {
+        let mut data = vec![0; length];
+        let mut read = 0;
+        for span in self.map.spans(index, begin, length)? {
+            let mut file = File::open(self.path(span.file))?;
+            file.seek(SeekFrom::Start(span.offset))?;
+            file.read_exact(&mut data[read..read + span.length])?;
+            read += span.length;
+        }
+        Ok(data)
+    }
+
+    fn file_stats(&self) -> Result<Option<Vec<FileStat>>, Error> {
+        (0..self.map.paths().len())
+            .map(|file| {
+                let metadata = fs::metadata(self.path(file))?;
+                let mtime = metadata.modified()?.duration_since(UNIX_EPOCH).unwrap_or_default();
+                Ok(FileStat { length: metadata.len(), mtime: mtime.as_nanos() as u64 })
+            })
+            .collect::<Result<Vec<_>, Error>>()
+            .map(Some)
+    }
+}
+
+fn allocate(mut file: File, length: u64, allocation: Allocation) -> Result<(), Error> {
+    let current = file.metadata()?.len();
+    if current >= length {
+        return Ok(());
+    }
+
+    match allocation {
+        Allocation::Sparse => file.set_len(length)?,
+        Allocation::Full => {
+            file.seek(SeekFrom::Start(current))?;
+            let mut remaining = length - current;
+            while remaining > 0 {
+                let chunk = remaining.min(ZEROS.len() as u64) as usize;
+                file.write_all(&ZEROS[..chunk])?;
+                remaining -= chunk as u64;
+            }
+        },
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::bencoding::bencode::DictMap;
+    use crate::torrent::file_layout::{FileEntry, Layout};
+    use crate::test_util::scratch_dir;
+
+    /// Pieces of 4 bytes over `dir/a` of 3 bytes, an empty file and
+    /// `dir/sub/b` of 6 bytes.
+    fn info() -> TorrentInfo {
+        let file = |path: &[&str], length| FileEntry {
+            length,
+            path: path.iter().map(|part| part.to_string()).collect(),
+            extra: DictMap::new(),
+        };
+        TorrentInfo {
+            layout: Layout::MultiFile { files: vec![file(&["a"], 3), file(&["empty"], 0), file(&["sub", "b"], 6)] },
+            name: "dir".to_string(),
+            piece_length: 4,
+            private: None,
+            pieces: vec![0; 60],
+            extra: DictMap::new(),
+        }
+    }
+
+    #[test]
+    fn test_create_lays_out_files() {
+        let root = scratch_dir("disk-create");
+        DiskStorage::create(&info(), &root, Allocation::Full).unwrap();
+
+        assert_eq!(vec![0; 3], fs::read(root.join("dir/a")).unwrap());
+        assert_eq!(0, fs::metadata(root.join("dir/empty")).unwrap().len());
+        assert_eq!(6, fs::metadata(root.join("dir/sub/b")).unwrap().len());
+        fs::remove_dir_all(root).unwrap();
+    }
+
+    #[test]
+    fn test_blocks_round_trip_across_files() {
+        let root = scratch_dir("disk-blocks");
+        let storage = DiskStorage::create(&info(), &root, Allocation::Sparse).unwrap();
+
+        storage.write_block(0, 0, b"abcd").unwrap();
+        storage.write_block(1, 0, b"efgh").unwrap();
+        storage.write_block(2, 0, b"i").unwrap();
+
+        assert_eq!(b"abc".to_vec(), fs::read(root.join("dir/a")).unwrap());
+        assert_eq!(b"defghi".to_vec(), fs::read(root.join("dir/sub/b")).unwrap());
+        assert_eq!(b"cd".to_vec(), storage.read_block(0, 2, 2).unwrap());
+        assert_eq!(b"efgh".to_vec(), storage.read_block(1, 0, 4).unwrap());
+        fs::remove_dir_all(root).unwrap();
+    }
+
+    #[test]
+    fn test_create_keeps_existing_data() {
+        let root = scratch_dir("disk-existing");
+        DiskStorage::create(&info(), &root, Allocation::Sparse).unwrap().write_block(0, 0, b"abcd").unwrap();
+
+        let storage = DiskStorage::create(&info(), &root, Allocation::Full).unwrap();
+        assert_eq!(b"abcd".to_vec(), storage.read_block(0, 0, 4).unwrap());
+        fs::remove_dir_all(root).unwrap();
+    }
+
+    #[test]
+    fn test_file_stats() {
+        let root = scratch_dir("disk-stats");
+        let storage = DiskStorage::create(&info(), &root, Allocation::Sparse).unwrap();
+
+        let stats = storage.file_stats().unwrap().unwrap();
+        assert_eq!(vec![3, 0, 6], stats.iter().map(|stat| stat.length).collect::<Vec<_>>());
+        assert!(stats.iter().all(|stat| stat.mtime > 0));
+
+        fs::remove_file(root.join("dir/empty")).unwrap();
+        assert!(storage.file_stats().is_err());
+        fs::remove_dir_all(root).unwrap();
+    }
+
+    #[test]
+    fn test_err_when_block_is_past_the_end() {
+        let root = scratch_dir("disk-past-end");
+        let storage = DiskStorage::create(&info(), &root, Allocation::Sparse).unwrap();
+
+        assert_eq!(
+            Err(Error::new("2 bytes at 0 of piece 2 are past the end of the torrent.".to_string())),
+            storage.write_block(2, 0, b"ij"),
+        );
+        fs::remove_dir_all(root).unwrap();
+    }
+}
@@ -0,0 +1,127 @@
+use std::path::PathBuf;
+
+use crate::storage::error::Error;
+use crate::torrent::torrent_info::TorrentInfo;
+
+/// The part of one file a range of torrent data falls into.
+#[derive(Eq, PartialEq, Clone, Debug)]
+pub struct FileSpan {
+    /// Index into the torrent's files.
+    pub file: usize,
+    pub offset: u64,
+    pub length: usize,
+}
+
+/// Maps ranges of pieces onto the torrent's files. The torrent data is the
+/// files concatenated, so a piece can start in one file and end in another.
+#[derive(Eq, PartialEq, Clone, Debug)]
+pub struct FileMap {
+    paths: Vec<PathBuf>,
+    /// `(offset, length)` of each file in the torrent data.
+    ranges: Vec<(u64, u64)>,
+    piece_length: u64,
+    length: u64,
+}
+
+impl FileMap {
+    pub fn new(info: &TorrentInfo) -> Self {
+        let files = info.files();
+        Self {
+            paths: files.iter().map(|file| file.path.clone()).collect(),
+            ranges: files.iter().map(|file| (file.offset as u64, file.length as u64)).collect(),
+            piece_length: info.piece_length as u64,
+            length: info.length() as u64,
+        }
+    }
+
+    /// Paths relative to the download directory.
+    pub fn paths(&self) -> &[PathBuf] {
+        &self.paths
+    }
+
+    pub fn file_len(&self, file: usize) -> u64 {
+        self.ranges[file].1
+    }
+
+    /// The length of the torrent data.
+    pub fn len(&self) -> u64 {
+        self.length
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.length == 0
+    }
+
+    /// Where `length` bytes at `begin` of piece `index` start in the torrent
+    /// data, when they are within the piece and the torrent.
+    pub fn offset(&self, index: u32, begin: u32, length: usize) -> Result<u64, Error> {
+        let start = u64::from(index) * self.piece_length + u64::from(begin);
+        if u64::from(begin) + length as u64 > self.piece_length || start + length as u64 > self.length {
+            return Err(Error::new(format!(
+                "{} bytes at {} of piece {} are past the end of the torrent.", length, begin, index,
+            )));
+        }
+        Ok(start)
+    }
+
+    /// The spans `length` bytes at `begin` of piece `index` cover, in order.
+    /// Empty files never get a span.
+    pub fn spans(&self, index: u32, begin: u32, length: usize) -> Result<Vec<FileSpan>, Error> {
+        let start = self.offset(index, begin, length)?;
+        let end = start + length as u64;
+
+        let spans = self.ranges.iter()
+            .enumerate()
+            .filter(|(_, (offset, file_len))| *file_len > 0 && *offset < end && start < offset + file_len)
+            .map(|(file, (offset, file_len))| {
+                let from = start.max(*offset);
+                let to = end.min(offset + file_len);
+                FileSpan { file, offset: from - offset, length: (to - from) as usize }
+            })
+            .collect();
+        Ok(spans)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::bencoding::bencode::DictMap;
+    use crate::torrent::file_layout::{FileEntry, Layout};
+
+    /// Pieces of 4 bytes over files of 3, 0 and 6 bytes.
+    fn file_map() -> FileMap {
+        let file = |name: &str, length| FileEntry { length, path: vec![name.to_string()], extra: DictMap::new() };
+        FileMap::new(&TorrentInfo {
+            layout: Layout::MultiFile { files: vec![file("a", 3), file("empty", 0), file("b", 6)] },
+            name: "dir".to_string(),
+            piece_length: 4,
+            private: None,
+            pieces: vec![0; 60],
+            extra: DictMap::new(),
+        })
+    }
+
+    #[test]
+    fn test_span_within_a_file() {
+        assert_eq!(Ok(vec![FileSpan { file: 2, offset: 1, length: 2 }]), file_map().spans(1, 0, 2));
+    }
+
+    #[test]
+    fn test_piece_spanning_files() {
+        assert_eq!(
+            Ok(vec![FileSpan { file: 0, offset: 0, length: 3 }, FileSpan { file: 2, offset: 0, length: 1 }]),
+            file_map().spans(0, 0, 4),
+        );
+        assert_eq!(Ok(vec![FileSpan { file: 2, offset: 5, length: 1 }]), file_map().spans(2, 0, 1));
+    }
+
+    #[test]
+    fn test_err_past_the_end() {
+        assert_eq!(
+            Err(Error::new("2 bytes at 0 of piece 2 are past the end of the torrent.".to_string())),
+            file_map().spans(2, 0, 2),
+        );
+        assert!(file_map().spans(0, 3, 2).is_err());
+    }
+}
@@ -0,0 +1,81 @@
+use std::sync::Mutex;
+
+use crate::storage::error::Error;
+use crate::storage::file_map::FileMap;
+use crate::storage::storage::Storage;
+use crate::torrent::torrent_info::TorrentInfo;
+
+/// Keeps the torrent data in memory, for tests and for callers that don't
+/// want to touch the disk.
+#[derive(Debug)]
+pub struct MemoryStorage {
+    map: FileMap,
+    data: Mutex<Vec<u8>>,
+}
+
+impl MemoryStorage {
+    pub fn new(info: &TorrentInfo) -> Self {
+        let map = FileMap::new(info);
+        let data = Mutex::new(vec![0; map.len() as usize]);
+        Self { map, data }
+    }
+
+    /// The torrent data, the files concatenated.
+    pub fn data(&self) -> Vec<u8> {
+        self.data.lock().unwrap().clone()
+    }
+}
+
+impl Storage for MemoryStorage {
+    fn write_block(&self, index: u32, begin: u32, data: &[u8]) -> Result<(), Error> {
+        let start = self.map.offset(index, begin, data.len())? as usize;
+        self.data.lock().unwrap()[start..start + data.len()].copy_from_slice(data);
+        Ok(())
+    }
+
+    fn read_block(&self, index: u32, begin: u32, length: usize) -> Result<Vec<u8>, Error> {
+        let start = self.map.offset(index, begin, length)? as usize;
+        Ok(self.data.lock().unwrap()[start..start + length].to_vec())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::bencoding::bencode::DictMap;
+    use crate::torrent::file_layout::Layout;
+
+    fn info() -> TorrentInfo {
+        TorrentInfo {
+            layout: Layout::SingleFile { length: 6 },
+            name: "derek".to_string(),
+            piece_length: 4,
+            private: None,
+            pieces: vec![0; 40],
+            extra: DictMap::new(),
+        }
+    }
+
+    #[test]
+    fn test_blocks_round_trip() {
+        let storage = MemoryStorage::new(&info());
+
+        storage.write_block(1, 0, b"ef").unwrap();
+        storage.write_block(0, 1, b"bcd").unwrap();
+
+        assert_eq!(b"\0bcdef".to_vec(), storage.data());
+        assert_eq!(b"cd".to_vec(), storage.read_block(0, 2, 2).unwrap());
+        assert_eq!(b"e".to_vec(), storage.read_block(1, 0, 1).unwrap());
+    }
+
+    #[test]
+    fn test_err_when_block_is_past_the_end() {
+        let storage = MemoryStorage::new(&info());
+
+        assert_eq!(
+            Err(Error::new("3 bytes at 0 of piece 1 are past the end of the torrent.".to_string())),
+            storage.write_block(1, 0, b"efg"),
+        );
+        assert!(storage.read_block(0, 2, 3).is_err());
+    }
+}
@@ -0,0 +1,6 @@
+#[allow(clippy::module_inception)]
+pub mod storage;
+pub mod file_map;
+pub mod disk;
+pub mod memory;
+pub mod error;
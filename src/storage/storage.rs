@@ -0,0 +1,27 @@
+use crate::storage::error::Error;
+
+/// The size and modification time of one of the torrent's files, kept with
+/// resume data to notice files that changed while we weren't running.
+#[derive(Eq, PartialEq, Clone, Copy, Debug)]
+pub struct FileStat {
+    pub length: u64,
+    /// Nanoseconds since the Unix epoch.
+    pub mtime: u64,
+}
+
+/// Where the torrent data goes, addressed by piece like the peer wire
+/// protocol. Storage is shared between the tasks of a download, so it takes
+/// `&self` and handles its own locking.
+pub trait Storage: Send + Sync {
+    fn write_block(&self, index: u32, begin: u32, data: &[u8]) -> Result<(), Error>;
+
+    /// Reads a block back, e.g. to check a piece or to send it to a peer.
+    fn read_block(&self, index: u32, begin: u32, length: usize) -> Result<Vec<u8>, Error>;
+
+    /// The stats of each of the torrent's files, or `None` when the storage
+    /// can't tell whether its data changed, e.g. because it doesn't outlive
+    /// the process. Resume data is never trusted without them.
+    fn file_stats(&self) -> Result<Option<Vec<FileStat>>, Error> {
+        Ok(None)
+    }
+}
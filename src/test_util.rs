@@ -0,0 +1,9 @@
+use std::path::PathBuf;
+use std::{env, fs, process};
+
+/// A fresh directory for one test.
+pub fn scratch_dir(name: &str) -> PathBuf {
+    let dir = env::temp_dir().join(format!("torrent-rs-{}-{}", name, process::id()));
+    let _ = fs::remove_dir_all(&dir);
+    dir
+}
@@ -0,0 +1,259 @@
+use std::str;
+use std::path::PathBuf;
+use std::result::Result;
+
+use crate::bencoding::bencode::{Bencode, DictMap};
+use crate::bencoding::byte_string::ByteString;
+use crate::torrent::error::Error;
+
+/// Whether the torrent's `info` dict describes one file (`length`) or a
+/// directory of files (`files`).
+#[derive(Eq, PartialEq, Clone, Debug)]
+pub enum Layout {
+    SingleFile { length: i64 },
+    MultiFile { files: Vec<FileEntry> },
+}
+
+/// One entry of the `files` list, `path` is relative to the directory named by
+/// the torrent's `name`.
+#[derive(Eq, PartialEq, Clone, Debug)]
+pub struct FileEntry {
+    pub length: i64,
+    pub path: Vec<String>,
+    /// Keys we don't model, e.g. `md5sum` or `attr`.
+    pub extra: DictMap,
+}
+
+/// A file of the torrent and where it starts in the concatenated torrent data.
+#[derive(Eq, PartialEq, Clone, Debug)]
+pub struct TorrentFile {
+    pub path: PathBuf,
+    pub length: i64,
+    pub offset: i64,
+}
+
+impl Layout {
+    pub fn from(input: &Bencode) -> Result<Self, Error> {
+        if !input.contains_key("files") {
+            let length = input.get_number("length")?;
+            check_length(length)?;
+            return Ok(Layout::SingleFile { length });
+        }
+
+        let mut files = Vec::new();
+        for entry in input.get_list("files")? {
+            files.push(FileEntry::from(entry)?);
+        }
+
+        if files.is_empty() {
+            return Err(Error::new("\"files\" list is empty.".to_string()));
+        }
+        if files.iter().try_fold(0i64, |total, file| total.checked_add(file.length)).is_none() {
+            return Err(Error::new("\"files\" lengths add up to more than 2^63 - 1 bytes.".to_string()));
+        }
+
+        Ok(Layout::MultiFile { files })
+    }
+
+    /// The total length of the torrent data. Parsed layouts never overflow,
+    /// one built by hand saturates rather than panicking.
+    pub fn length(&self) -> i64 {
+        match self {
+            Layout::SingleFile { length } => *length,
+            Layout::MultiFile { files } => files.iter().fold(0i64, |total, f| total.saturating_add(f.length)),
+        }
+    }
+
+    /// The files on disk, `name` is the file name of a single file torrent or
+    /// the directory of a multi-file one.
+    pub fn files(&self, name: &str) -> Vec<TorrentFile> {
+        match self {
+            Layout::SingleFile { length } => vec![
+                TorrentFile { path: PathBuf::from(name), length: *length, offset: 0 },
+            ],
+            Layout::MultiFile { files } => {
+                let mut offset = 0;
+                files.iter().map(|file| {
+                    let mut path = PathBuf::from(name);
+                    path.extend(&file.path);
+                    let torrent_file = TorrentFile { path, length: file.length, offset };
+                    offset += file.length;
+                    torrent_file
+                }).collect()
+            },
+        }
+    }
+
+    pub fn insert_into(&self, dict: &mut DictMap) {
+        match self {
+            Layout::SingleFile { length } => {
+                dict.insert(ByteString::from_str("length"), Bencode::Number(*length));
+            },
+            Layout::MultiFile { files } => {
+                dict.insert(
+                    ByteString::from_str("files"),
+                    Bencode::List(files.iter().map(|f| f.to_bencode()).collect()),
+                );
+            },
+        }
+    }
+}
+
+impl FileEntry {
+    pub fn from(input: &Bencode) -> Result<Self, Error> {
+        let length = input.get_number("length")?;
+        check_length(length)?;
+
+        let mut path = Vec::new();
+        for component in input.get_list("path")? {
+            let component = match component {
+                Bencode::ByteString(s) => match str::from_utf8(s) {
+                    Ok(utf8) => utf8.to_string(),
+                    Err(_) => return Err(Error::new("\"path\" not valid utf-8.".to_string())),
+                },
+                _ => return Err(Error::new("\"path\" value is not a list of ByteStrings.".to_string())),
+            };
+            check_path_component(&component)?;
+            path.push(component);
+        }
+
+        if path.is_empty() {
+            return Err(Error::new("\"path\" list is empty.".to_string()));
+        }
+
+        let extra = input.unknown_entries(&["length", "path"]);
+
+        Ok(Self { length, path, extra })
+    }
+
+    pub fn to_bencode(&self) -> Bencode {
+        let mut dict = self.extra.clone();
+        dict.insert(ByteString::from_str("length"), Bencode::Number(self.length));
+        dict.insert(
+            ByteString::from_str("path"),
+            Bencode::List(self.path.iter().map(|p| Bencode::ByteString(p.as_bytes().to_vec())).collect()),
+        );
+        Bencode::Dict(dict)
+    }
+}
+
+fn check_length(length: i64) -> Result<(), Error> {
+    if length < 0 {
+        return Err(Error::new(format!("File length {} is negative.", length)));
+    }
+    Ok(())
+}
+
+/// Rejects path components that would escape the torrent's directory once
+/// joined onto it.
+pub fn check_path_component(component: &str) -> Result<(), Error> {
+    let invalid = component.is_empty()
+        || component == "."
+        || component == ".."
+        || component.contains(['/', '\\', '\0']);
+
+    if invalid {
+        return Err(Error::new(format!("Invalid path component \"{}\".", component)));
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::bencoding::decoder::decode;
+
+    fn layout(data: &[u8]) -> Result<Layout, Error> {
+        Layout::from(&decode(data.to_vec()).unwrap())
+    }
+
+    fn multi_file() -> Layout {
+        Layout::MultiFile {
+            files: vec![
+                FileEntry { length: 10, path: vec!["a.txt".to_string()], extra: DictMap::new() },
+                FileEntry { length: 0, path: vec!["sub".to_string(), "empty".to_string()], extra: DictMap::new() },
+                FileEntry { length: 5, path: vec!["sub".to_string(), "b.txt".to_string()], extra: DictMap::new() },
+            ],
+        }
+    }
+
+    #[test]
+    fn test_single_file() {
+        let result = layout(b"d6:lengthi4e4:name5:dereke").unwrap();
+
+        assert_eq!(Layout::SingleFile { length: 4 }, result);
+        assert_eq!(4, result.length());
+        assert_eq!(
+            vec![TorrentFile { path: PathBuf::from("derek"), length: 4, offset: 0 }],
+            result.files("derek"),
+        );
+    }
+
+    #[test]
+    fn test_multi_file() {
+        let result = layout(b"d5:filesld6:lengthi10e4:pathl5:a.txteed6:lengthi0e4:pathl3:sub5:emptyeed6:lengthi5e4:pathl3:sub5:b.txteee4:name3:dire").unwrap();
+
+        assert_eq!(multi_file(), result);
+        assert_eq!(15, result.length());
+        assert_eq!(
+            vec![
+                TorrentFile { path: PathBuf::from("dir/a.txt"), length: 10, offset: 0 },
+                TorrentFile { path: PathBuf::from("dir/sub/empty"), length: 0, offset: 10 },
+                TorrentFile { path: PathBuf::from("dir/sub/b.txt"), length: 5, offset: 10 },
+            ],
+            result.files("dir"),
+        );
+    }
+
+    #[test]
+    fn test_insert_into_round_trips() {
+        let mut dict = DictMap::new();
+        multi_file().insert_into(&mut dict);
+
+        assert_eq!(Ok(multi_file()), Layout::from(&Bencode::Dict(dict)));
+    }
+
+    #[test]
+    fn test_err_when_length_and_files_are_missing() {
+        assert_eq!(
+            Err(Error::new("\"length\" key is not present in torrent file.".to_string())),
+            layout(b"d4:name5:dereke"),
+        );
+    }
+
+    #[test]
+    fn test_err_when_path_escapes_the_directory() {
+        assert_eq!(
+            Err(Error::new("Invalid path component \"..\".".to_string())),
+            layout(b"d5:filesld6:lengthi1e4:pathl2:..6:passwdeeee"),
+        );
+    }
+
+    #[test]
+    fn test_err_when_path_is_empty() {
+        assert_eq!(
+            Err(Error::new("\"path\" list is empty.".to_string())),
+            layout(b"d5:filesld6:lengthi1e4:pathleeee"),
+        );
+    }
+
+    #[test]
+    fn test_err_when_length_is_negative() {
+        assert_eq!(
+            Err(Error::new("File length -1 is negative.".to_string())),
+            layout(b"d6:lengthi-1ee"),
+        );
+        assert_eq!(
+            Err(Error::new("File length -5 is negative.".to_string())),
+            layout(b"d5:filesld6:lengthi10e4:pathl1:aeed6:lengthi-5e4:pathl1:beeee"),
+        );
+    }
+
+    #[test]
+    fn test_err_when_total_length_overflows() {
+        assert_eq!(
+            Err(Error::new("\"files\" lengths add up to more than 2^63 - 1 bytes.".to_string())),
+            layout(b"d5:filesld6:lengthi9223372036854775807e4:pathl1:aeed6:lengthi9223372036854775807e4:pathl1:beeee"),
+        );
+    }
+}
@@ -0,0 +1,87 @@
+use std::fmt;
+use std::convert::TryFrom;
+use sha1::Digest;
+use percent_encoding;
+
+use crate::torrent::error::Error;
+
+/// The SHA-1 of the bencoded `info` dict, identifying a torrent to trackers and peers.
+#[derive(Eq, PartialEq, Ord, PartialOrd, Hash, Clone, Copy, Debug)]
+pub struct InfoHash([u8; 20]);
+
+impl InfoHash {
+    pub fn new(bytes: [u8; 20]) -> Self {
+        InfoHash(bytes)
+    }
+
+    /// Hashes the encoded `info` dict, these must be the bytes as they appear in
+    /// the torrent file.
+    pub fn digest(encoded_info: &[u8]) -> Self {
+        let mut hasher = sha1::Sha1::new();
+        hasher.input(encoded_info);
+        let mut bytes = [0; 20];
+        bytes.copy_from_slice(hasher.result().as_slice());
+        InfoHash(bytes)
+    }
+
+    pub fn as_bytes(&self) -> &[u8; 20] {
+        &self.0
+    }
+
+    pub fn url_encoded(&self) -> String {
+        percent_encoding::percent_encode(&self.0, percent_encoding::NON_ALPHANUMERIC).to_string()
+    }
+}
+
+impl TryFrom<&[u8]> for InfoHash {
+    type Error = Error;
+
+    fn try_from(bytes: &[u8]) -> Result<Self, Error> {
+        if bytes.len() != 20 {
+            return Err(Error::new(format!("Info hash is {} bytes, expected 20.", bytes.len())));
+        }
+
+        let mut hash = [0; 20];
+        hash.copy_from_slice(bytes);
+        Ok(InfoHash(hash))
+    }
+}
+
+impl fmt::Display for InfoHash {
+    fn fmt(&self, fmt: &mut fmt::Formatter) -> fmt::Result {
+        for byte in self.0.iter() {
+            write!(fmt, "{:02x}", byte)?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn info_hash() -> InfoHash {
+        InfoHash::digest(b"d6:lengthi4e4:name5:derek12:piece lengthi100e6:pieces3:z\xc3\x287:privatei1ee")
+    }
+
+    #[test]
+    fn test_url_encoded() {
+        assert_eq!("%3AJ%9A%B3%D7%3E%D0t%BDD%DDz%A5%EE%9D%DE%8C%AD%28%AE", info_hash().url_encoded());
+    }
+
+    #[test]
+    fn test_display() {
+        assert_eq!("3a4a9ab3d73ed074bd44dd7aa5ee9dde8cad28ae", format!("{}", info_hash()));
+    }
+
+    #[test]
+    fn test_try_from_slice() {
+        let bytes = info_hash().as_bytes().to_vec();
+
+        assert_eq!(Ok(info_hash()), InfoHash::try_from(&bytes[..]));
+        assert_eq!(
+            Err(Error::new("Info hash is 3 bytes, expected 20.".to_string())),
+            InfoHash::try_from(&bytes[..3]),
+        );
+    }
+}
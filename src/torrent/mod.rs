@@ -1,5 +1,10 @@
+#[allow(clippy::module_inception)]
 pub mod torrent;
 pub mod torrent_info;
+pub mod file_layout;
+pub mod info_hash;
 pub mod tracker_info;
+pub mod tracker_response;
+pub mod scrape_info;
 pub mod peer;
 pub mod error;
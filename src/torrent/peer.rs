@@ -1,37 +1,166 @@
 use std::fmt;
-use std::net::Ipv4Addr;
+use std::convert::TryFrom;
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr, ToSocketAddrs};
+use byteorder::{ByteOrder, BigEndian};
+
+use crate::bencoding::bencode::Bencode;
+use crate::torrent::error::Error;
 
 #[derive(Eq, PartialEq, Clone, Debug)]
 pub struct Peer {
-    pub ip: Ipv4Addr,
-    pub port: u16,
+    pub addr: SocketAddr,
+    pub peer_id: Option<[u8; 20]>,
+}
+
+impl Peer {
+    pub fn new(addr: SocketAddr) -> Self {
+        Self { addr, peer_id: None }
+    }
+
+    /// Parses one entry of a compact `peers` list, 4 bytes of ip then 2 of port.
+    pub fn from_compact_v4(bytes: &[u8]) -> Self {
+        let ip = Ipv4Addr::from(BigEndian::read_u32(&bytes[..4]));
+        let port = BigEndian::read_u16(&bytes[4..6]);
+        Self::new(SocketAddr::new(ip.into(), port))
+    }
+
+    /// Parses one entry of a compact `peers6` list, 16 bytes of ip then 2 of port.
+    pub fn from_compact_v6(bytes: &[u8]) -> Self {
+        let mut octets = [0; 16];
+        octets.copy_from_slice(&bytes[..16]);
+        let port = BigEndian::read_u16(&bytes[16..18]);
+        Self::new(SocketAddr::new(Ipv6Addr::from(octets).into(), port))
+    }
+
+    /// Parses a `{peer id, ip, port}` dict, `ip` may be a hostname in which case
+    /// it is resolved. Returns `None` when the host doesn't resolve.
+    pub fn from_dict(input: Bencode) -> Result<Option<Self>, Error> {
+        let host = input.get_string("ip")?;
+        let port = input.get_number("port")?;
+        let port = match u16::try_from(port) {
+            Ok(port) => port,
+            Err(_) => return Err(Error::new(format!("Peer port {} is out of range.", port))),
+        };
+
+        let peer_id = if input.contains_key("peer id") {
+            let bytes = input.remove_bytestring("peer id")?;
+            match <[u8; 20]>::try_from(&bytes[..]) {
+                Ok(peer_id) => Some(peer_id),
+                Err(_) => return Err(Error::new(format!("Peer id is {} bytes, expected 20.", bytes.len()))),
+            }
+        } else {
+            None
+        };
+
+        Ok(resolve(&host, port).map(|addr| Self { addr, peer_id }))
+    }
+
+    pub fn ip(&self) -> IpAddr {
+        self.addr.ip()
+    }
+
+    pub fn port(&self) -> u16 {
+        self.addr.port()
+    }
+}
+
+fn resolve(host: &str, port: u16) -> Option<SocketAddr> {
+    (host, port).to_socket_addrs().ok()?.next()
 }
 
 impl fmt::Display for Peer {
     fn fmt(&self, fmt: &mut fmt::Formatter) -> fmt::Result {
-        write!(fmt, "{}:{}", self.ip, self.port)
+        write!(fmt, "{}", self.addr)
     }
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::bencoding::decoder::decode;
+
+    fn peer_from_dict(data: &[u8]) -> Result<Option<Peer>, Error> {
+        Peer::from_dict(decode(data.to_vec()).unwrap())
+    }
 
     #[test]
     fn test_to_string() {
-        let peer = Peer { ip: Ipv4Addr::new(192, 168, 2, 11), port: 6881 };
+        let peer = Peer::new(SocketAddr::new(Ipv4Addr::new(192, 168, 2, 11).into(), 6881));
         assert_eq!("192.168.2.11:6881", peer.to_string());
     }
 
     #[test]
     fn test_display() {
-        let peer = Peer { ip: Ipv4Addr::new(192, 168, 2, 11), port: 6881 };
+        let peer = Peer::new(SocketAddr::new(Ipv4Addr::new(192, 168, 2, 11).into(), 6881));
         assert_eq!("192.168.2.11:6881", format!("{}", peer));
     }
 
     #[test]
     fn test_debug() {
-        let peer = Peer { ip: Ipv4Addr::new(192, 168, 2, 11), port: 6881 };
-        assert_eq!("Peer { ip: 192.168.2.11, port: 6881 }", format!("{:?}", peer));
+        let peer = Peer::new(SocketAddr::new(Ipv4Addr::new(192, 168, 2, 11).into(), 6881));
+        assert_eq!("Peer { addr: 192.168.2.11:6881, peer_id: None }", format!("{:?}", peer));
+    }
+
+    #[test]
+    fn test_display_ipv6() {
+        let peer = Peer::new(SocketAddr::new(Ipv6Addr::LOCALHOST.into(), 6881));
+        assert_eq!("[::1]:6881", format!("{}", peer));
+    }
+
+    #[test]
+    fn test_from_compact_v4() {
+        let expected = Peer::new("79.122.0.120:6721".parse().unwrap());
+        assert_eq!(expected, Peer::from_compact_v4(&[79, 122, 0, 120, 26, 65]));
+    }
+
+    #[test]
+    fn test_from_compact_v6() {
+        let mut bytes = vec![0x20, 0x01, 0x0d, 0xb8, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 1];
+        bytes.extend_from_slice(&[26, 225]);
+
+        let expected = Peer::new("[2001:db8::1]:6881".parse().unwrap());
+        assert_eq!(expected, Peer::from_compact_v6(&bytes));
+    }
+
+    #[test]
+    fn test_from_dict() {
+        let expected = Peer {
+            addr: "10.0.0.1:6881".parse().unwrap(),
+            peer_id: Some(*b"-RS0001-abcdefghijkl"),
+        };
+
+        assert_eq!(
+            Ok(Some(expected)),
+            peer_from_dict(b"d2:ip8:10.0.0.17:peer id20:-RS0001-abcdefghijkl4:porti6881ee"),
+        );
+    }
+
+    #[test]
+    fn test_from_dict_resolves_hostnames() {
+        let peer = peer_from_dict(b"d2:ip9:localhost4:porti6881ee").unwrap().unwrap();
+        assert!(peer.ip().is_loopback());
+        assert_eq!(6881, peer.port());
+    }
+
+    #[test]
+    fn test_from_dict_with_ipv6_address() {
+        let expected = Peer::new("[2001:db8::1]:6881".parse().unwrap());
+        assert_eq!(Ok(Some(expected)), peer_from_dict(b"d2:ip11:2001:db8::14:porti6881ee"));
+    }
+
+    #[test]
+    fn test_err_when_port_is_out_of_range() {
+        assert_eq!(
+            Err(Error::new("Peer port 65536 is out of range.".to_string())),
+            peer_from_dict(b"d2:ip8:10.0.0.14:porti65536ee"),
+        );
+    }
+
+    #[test]
+    fn test_err_when_peer_id_is_the_wrong_length() {
+        assert_eq!(
+            Err(Error::new("Peer id is 3 bytes, expected 20.".to_string())),
+            peer_from_dict(b"d2:ip8:10.0.0.17:peer id3:abc4:porti1ee"),
+        );
     }
 }
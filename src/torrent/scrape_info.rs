@@ -0,0 +1,97 @@
+use std::fmt;
+use std::convert::TryFrom;
+use std::collections::BTreeMap;
+use std::result::Result;
+
+use crate::bencoding::bencode::Bencode;
+use crate::torrent::error::Error;
+use crate::torrent::info_hash::InfoHash;
+
+/// Swarm statistics for one torrent from a tracker scrape.
+#[derive(Eq, PartialEq, Clone, Debug)]
+pub struct ScrapeInfo {
+    pub complete: i64,
+    pub downloaded: i64,
+    pub incomplete: i64,
+}
+
+impl ScrapeInfo {
+    pub fn from(input: &Bencode) -> Result<Self, Error> {
+        let complete = input.get_number("complete")?;
+        let downloaded = input.get_number("downloaded")?;
+        let incomplete = input.get_number("incomplete")?;
+
+        Ok(Self { complete, downloaded, incomplete })
+    }
+
+    /// Parses the body of an HTTP scrape, the `files` dict maps each raw info
+    /// hash to its statistics.
+    pub fn from_response(input: Bencode) -> Result<BTreeMap<InfoHash, Self>, Error> {
+        if let Some(reason) = input.get_optional_string("failure reason")? {
+            return Err(Error::new(format!("Tracker failure: {}", reason)));
+        }
+
+        let files = match input.remove("files")? {
+            Bencode::Dict(files) => files,
+            _ => return Err(Error::new("\"files\" value is not a Dict.".to_string())),
+        };
+
+        let mut result = BTreeMap::new();
+        for (info_hash, stats) in files {
+            let info_hash = InfoHash::try_from(&info_hash.unwrap()[..])?;
+            result.insert(info_hash, ScrapeInfo::from(&stats)?);
+        }
+        Ok(result)
+    }
+}
+
+impl fmt::Display for ScrapeInfo {
+    fn fmt(&self, fmt: &mut fmt::Formatter) -> fmt::Result {
+        write!(fmt, "ScrapeInfo: {{ complete: {}, downloaded: {}, incomplete: {} }}", self.complete, self.downloaded, self.incomplete)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::bencoding::decoder::decode;
+
+    fn from_response(data: &[u8]) -> Result<BTreeMap<InfoHash, ScrapeInfo>, Error> {
+        ScrapeInfo::from_response(decode(data.to_vec()).unwrap())
+    }
+
+    #[test]
+    fn test_from_response() {
+        let data = b"d5:filesd20:aaaaaaaaaaaaaaaaaaaad8:completei5e10:downloadedi50e10:incompletei10ee20:bbbbbbbbbbbbbbbbbbbbd8:completei1e10:downloadedi2e10:incompletei3eeee";
+
+        let mut expected = BTreeMap::new();
+        expected.insert(InfoHash::new([b'a'; 20]), ScrapeInfo { complete: 5, downloaded: 50, incomplete: 10 });
+        expected.insert(InfoHash::new([b'b'; 20]), ScrapeInfo { complete: 1, downloaded: 2, incomplete: 3 });
+
+        assert_eq!(Ok(expected), from_response(data));
+    }
+
+    #[test]
+    fn test_err_on_failure_reason() {
+        assert_eq!(
+            Err(Error::new("Tracker failure: scrape disabled".to_string())),
+            from_response(b"d14:failure reason15:scrape disablede"),
+        );
+    }
+
+    #[test]
+    fn test_err_when_info_hash_is_the_wrong_length() {
+        assert_eq!(
+            Err(Error::new("Info hash is 3 bytes, expected 20.".to_string())),
+            from_response(b"d5:filesd3:abcd8:completei5e10:downloadedi50e10:incompletei10eeee"),
+        );
+    }
+
+    #[test]
+    fn test_err_when_a_counter_is_missing() {
+        assert_eq!(
+            Err(Error::new("\"downloaded\" key is not present in torrent file.".to_string())),
+            from_response(b"d5:filesd20:aaaaaaaaaaaaaaaaaaaad8:completei5eeee"),
+        );
+    }
+}
@@ -1,54 +1,175 @@
 use std::{str, fmt};
 use std::result::Result;
 
-use crate::bencoding::bencode::Bencode;
+use crate::bencoding::{decoder, encoder};
+use crate::bencoding::bencode::{Bencode, DictMap};
+use crate::bencoding::byte_string::ByteString;
 use crate::torrent::torrent_info::TorrentInfo;
+use crate::torrent::info_hash::InfoHash;
 use crate::torrent::error::Error;
 
 #[derive(Eq, PartialEq, Clone, Debug)]
 pub struct Torrent {
-    pub announce: String,
-    pub created_by: String,
-    pub creation_date: i64,
-    pub encoding: String,
+    /// Only optional when `announce-list` has trackers to use instead.
+    pub announce: Option<String>,
+    /// Tiers of tracker urls from `announce-list` (BEP 12).
+    pub announce_list: Option<Vec<Vec<String>>>,
+    pub created_by: Option<String>,
+    pub creation_date: Option<i64>,
+    pub encoding: Option<String>,
     pub info: TorrentInfo,
+    pub info_hash: InfoHash,
+    /// Top level keys we don't model (`comment`, `url-list`, ...), kept so the
+    /// torrent can be re-encoded unchanged.
+    pub extra: DictMap,
 }
 
+const KNOWN_KEYS: [&str; 6] = ["announce", "announce-list", "created by", "creation date", "encoding", "info"];
+
 impl Torrent {
-    pub fn from(input: Bencode) -> Result<Self, Error> {        
-        let announce = input.get_string("announce")?;
-        let created_by = input.get_string("created by")?;
-        let encoding = input.get_string("encoding")?;
-        let creation_date = input.get_number("creation date")?;
-        let info = TorrentInfo::from(input.remove("info")?)?;
-        
+    /// Parses a torrent file, the info hash is computed over the `info` dict
+    /// exactly as it is encoded in `data`.
+    pub fn from_bytes(data: &[u8]) -> Result<Self, Error> {
+        let input = decoder::decode_ref(data)?.to_bencode();
+        let raw_info = match decoder::value_span(data, "info")? {
+            Some(span) => &data[span],
+            None => return Err(Error::new("\"info\" key is not present in torrent file.".to_string())),
+        };
+
+        Self::from_parts(input, Some(raw_info))
+    }
+
+    /// Builds a torrent from an already decoded file, the info hash is computed
+    /// by re-encoding the `info` dict, which only matches the original if it was
+    /// canonically encoded. Prefer `from_bytes` when the raw file is available.
+    pub fn from(input: Bencode) -> Result<Self, Error> {
+        Self::from_parts(input, None)
+    }
+
+    fn from_parts(input: Bencode, raw_info: Option<&[u8]>) -> Result<Self, Error> {
+        let announce_list = parse_announce_list(&input)?;
+        let announce = match &announce_list {
+            Some(tiers) if !tiers.is_empty() => input.get_optional_string("announce")?,
+            _ => Some(input.get_string("announce")?),
+        };
+        let created_by = input.get_optional_string("created by")?;
+        let encoding = input.get_optional_string("encoding")?;
+        let creation_date = input.get_optional_number("creation date")?;
+        let extra = input.unknown_entries(&KNOWN_KEYS);
+        let info_bencode = input.remove("info")?;
+        let info_hash = match raw_info {
+            Some(raw) => InfoHash::digest(raw),
+            None => InfoHash::digest(&encoder::encode(info_bencode.clone())),
+        };
+        let info = TorrentInfo::from(info_bencode)?;
+
         Ok(
             Self {
                 announce,
+                announce_list,
                 created_by,
                 creation_date,
                 encoding,
                 info,
+                info_hash,
+                extra,
             }
         )
     }
 
-    pub fn announce_url(&self) -> Result<String, Error> {
-        let mut announce_vec = self.announce.as_bytes().to_vec();
+    /// The tracker tiers to announce to, `announce-list` takes precedence over
+    /// `announce` when present.
+    pub fn trackers(&self) -> Vec<Vec<String>> {
+        match (&self.announce_list, &self.announce) {
+            (Some(tiers), _) if !tiers.is_empty() => tiers.clone(),
+            (_, Some(announce)) => vec![vec![announce.clone()]],
+            _ => Vec::new(),
+        }
+    }
 
-        for &byte in b"?info_hash=" {
-            announce_vec.push(byte);
+    pub fn to_bencode(&self) -> Bencode {
+        let mut dict = self.extra.clone();
+        if let Some(announce) = &self.announce {
+            dict.insert(
+                ByteString::from_str("announce"),
+                Bencode::ByteString(announce.as_bytes().to_vec()),
+            );
         }
 
-        for &byte in self.info.sha1().as_bytes() {
-            announce_vec.push(byte);
+        if let Some(tiers) = &self.announce_list {
+            let tiers = tiers.iter()
+                .map(|tier| Bencode::List(
+                    tier.iter().map(|url| Bencode::ByteString(url.as_bytes().to_vec())).collect()
+                ))
+                .collect();
+            dict.insert(
+                ByteString::from_str("announce-list"),
+                Bencode::List(tiers),
+            );
         }
 
-        match str::from_utf8(announce_vec.as_slice()) {
-            Ok(v) => Ok(v.to_string()),
-            Err(_) => Err(Error::new("Failed to parse url.".to_string())),
+        if let Some(created_by) = &self.created_by {
+            dict.insert(
+                ByteString::from_str("created by"),
+                Bencode::ByteString(created_by.as_bytes().to_vec()),
+            );
+        }
+
+        if let Some(creation_date) = self.creation_date {
+            dict.insert(
+                ByteString::from_str("creation date"),
+                Bencode::Number(creation_date),
+            );
+        }
+
+        if let Some(encoding) = &self.encoding {
+            dict.insert(
+                ByteString::from_str("encoding"),
+                Bencode::ByteString(encoding.as_bytes().to_vec()),
+            );
+        }
+
+        dict.insert(
+            ByteString::from_str("info"),
+            self.info.to_bencode(),
+        );
+
+        Bencode::Dict(dict)
+    }
+
+    /// Re-encodes the torrent file, for a canonically encoded input this gives
+    /// back the original bytes.
+    pub fn encode(&self) -> Vec<u8> {
+        encoder::encode(self.to_bencode())
+    }
+}
+
+fn parse_announce_list(input: &Bencode) -> Result<Option<Vec<Vec<String>>>, Error> {
+    if !input.contains_key("announce-list") {
+        return Ok(None);
+    }
+
+    let mut tiers = Vec::new();
+    for tier in input.get_list("announce-list")? {
+        let urls = match tier {
+            Bencode::List(urls) => urls,
+            _ => return Err(Error::new("\"announce-list\" value is not a list of Lists.".to_string())),
+        };
+
+        let mut parsed = Vec::new();
+        for url in urls {
+            match url {
+                Bencode::ByteString(s) => match str::from_utf8(s) {
+                    Ok(utf8) => parsed.push(utf8.to_string()),
+                    Err(_) => return Err(Error::new("\"announce-list\" not valid utf-8.".to_string())),
+                },
+                _ => return Err(Error::new("\"announce-list\" tier is not a list of ByteStrings.".to_string())),
+            }
         }
+        tiers.push(parsed);
     }
+
+    Ok(Some(tiers))
 }
 
 impl fmt::Display for Torrent {
@@ -59,25 +180,39 @@ impl fmt::Display for Torrent {
 
 fn format(fmt: &mut fmt::Formatter, v: &Torrent) -> fmt::Result {
     write!(fmt, "Torrent: {{ ")?;
-    write!(fmt, "announce: \"{}\", ", v.announce)?;
-    write!(fmt, "created_by: \"{}\", ", v.created_by)?;
-    write!(fmt, "creation_date: {}, ", v.creation_date)?;
-    write!(fmt, "encoding: \"{}\", ", v.encoding)?;
-    write!(fmt, "info: {} ", v.info)?;
+    write!(fmt, "announce: {:?}, ", v.announce)?;
+    write!(fmt, "announce_list: {:?}, ", v.announce_list)?;
+    write!(fmt, "created_by: {:?}, ", v.created_by)?;
+    write!(fmt, "creation_date: {:?}, ", v.creation_date)?;
+    write!(fmt, "encoding: {:?}, ", v.encoding)?;
+    write!(fmt, "info: {}, ", v.info)?;
+    write!(fmt, "info_hash: {} ", v.info_hash)?;
     write!(fmt, "}}")
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::torrent::file_layout::Layout;
     use crate::bencoding::decoder::decode;
 
     fn torrent(data: &[u8]) -> Result<Torrent, Error> {
         Torrent::from(
-            decode(data.to_vec())
+            decode(data.to_vec()).unwrap()
         )
     }
 
+    /// A single piece hash, the first bytes are not valid UTF-8.
+    fn pieces() -> Vec<u8> {
+        let mut pieces = vec![b'z', 195, 40];
+        pieces.extend_from_slice(&[b'z'; 17]);
+        pieces
+    }
+
+    fn info_hash() -> InfoHash {
+        InfoHash::digest(b"d6:lengthi4e4:name5:derek12:piece lengthi100e6:pieces20:z\xc3\x28zzzzzzzzzzzzzzzzz7:privatei1ee")
+    }
+
     fn assert_result_matches_error(msg: String, result: Result<Torrent, Error>) {
         let actual = match result {
             Ok(_) => panic!("Unexpected Ok value."),
@@ -101,19 +236,26 @@ mod tests {
     #[test]
     fn test_err_when_announce_is_present() {
         let result = torrent(b"d8:announce3:yese");
-        assert_result_matches_error("\"created by\" key is not present in torrent file.".to_string(), result);
+        assert_result_matches_error("\"info\" key is not present in torrent file.".to_string(), result);
     }
 
     #[test]
-    fn test_err_when_created_by_is_present() {
-        let result = torrent(b"d8:announce3:yes10:created by5:dereke");
-        assert_result_matches_error("\"encoding\" key is not present in torrent file.".to_string(), result);
+    fn test_err_when_created_by_is_not_a_string() {
+        let result = torrent(b"d8:announce3:yes10:created byi3ee");
+        assert_result_matches_error("\"created by\" value is not a ByteString.".to_string(), result);
     }
 
     #[test]
-    fn test_err_when_encoding_is_present() {
-        let result = torrent(b"d8:announce3:yes10:created by5:derek8:encoding5:UTF-8e");
-        assert_result_matches_error("\"creation date\" key is not present in torrent file.".to_string(), result);
+    fn test_ok_when_optional_values_are_missing() {
+        let result = torrent(
+            b"d8:announce3:yes4:infod6:lengthi4e4:name5:derek12:piece lengthi100e6:pieces20:z\xc3\x28zzzzzzzzzzzzzzzzzee"
+        ).unwrap();
+
+        assert_eq!(None, result.created_by);
+        assert_eq!(None, result.encoding);
+        assert_eq!(None, result.creation_date);
+        assert_eq!(None, result.info.private);
+        assert_eq!(DictMap::new(), result.extra);
     }
 
     #[test]
@@ -125,46 +267,113 @@ mod tests {
     #[test]
     fn test_ok_when_all_values_are_present() {
         let result = torrent(
-            b"d8:announce3:yes10:created by5:derek8:encoding5:UTF-813:creation datei170e4:infod6:lengthi4e4:name5:derek12:piece lengthi100e6:pieces3:z\xc3\x287:privatei1eee"
+            b"d8:announce3:yes10:created by5:derek8:encoding5:UTF-813:creation datei170e4:infod6:lengthi4e4:name5:derek12:piece lengthi100e6:pieces20:z\xc3\x28zzzzzzzzzzzzzzzzz7:privatei1eee"
         );
 
         let expected_info = TorrentInfo {
-            length: 4,
+            layout: Layout::SingleFile { length: 4 },
             name: "derek".to_string(),
             piece_length: 100,
-            private: true,
-            pieces: vec![b'z', 195, 40],
+            private: Some(1),
+            pieces: pieces(),
+            extra: DictMap::new(),
         };
 
         let expected = Torrent {
-            announce: "yes".to_string(),
-            created_by: "derek".to_string(),
-            encoding: "UTF-8".to_string(),
-            creation_date: 170,
+            announce: Some("yes".to_string()),
+            announce_list: None,
+            created_by: Some("derek".to_string()),
+            encoding: Some("UTF-8".to_string()),
+            creation_date: Some(170),
             info: expected_info,
+            info_hash: info_hash(),
+            extra: DictMap::new(),
         };
 
         assert_eq!(Ok(expected), result);
     }
 
     #[test]
-    fn test_announce_url_when_all_values_are_present() {
-        let expected_info = TorrentInfo {
-            length: 4,
-            name: "derek".to_string(),
-            piece_length: 100,
-            private: true,
-            pieces: vec![b'z', 195, 40],
-        };
+    fn test_from_bytes_hashes_the_raw_info_dict() {
+        let raw_info = b"d6:lengthi4e4:name5:derek12:piece lengthi100e6:source3:foo6:pieces20:z\xc3\x28zzzzzzzzzzzzzzzzz7:privatei1ee";
+        let mut data = b"d8:announce3:yes10:created by5:derek8:encoding5:UTF-813:creation datei170e4:info".to_vec();
+        data.extend_from_slice(raw_info);
+        data.push(b'e');
 
-        let expected = Torrent {
-            announce: "yes".to_string(),
-            created_by: "derek".to_string(),
-            encoding: "UTF-8".to_string(),
-            creation_date: 170,
-            info: expected_info,
-        };
+        let result = Torrent::from_bytes(&data).unwrap();
 
-        assert_eq!("yes?info_hash=%3AJ%9A%B3%D7%3E%D0t%BDD%DDz%A5%EE%9D%DE%8C%AD%28%AE", expected.announce_url().unwrap())
+        assert_eq!(InfoHash::digest(raw_info), result.info_hash);
+        assert_ne!(result.info_hash, Torrent::from(decode(data).unwrap()).unwrap().info_hash);
+    }
+
+    #[test]
+    fn test_from_bytes_err_when_info_is_missing() {
+        let result = Torrent::from_bytes(b"d8:announce3:yese");
+        assert_result_matches_error("\"info\" key is not present in torrent file.".to_string(), result);
+    }
+
+    #[test]
+    fn test_unknown_keys_are_preserved() {
+        let data = b"d8:announce3:yes7:comment5:hello4:infod6:lengthi4e4:name5:derek12:piece lengthi100e6:pieces20:z\xc3\x28zzzzzzzzzzzzzzzzz6:source3:fooe8:url-listl4:httpee";
+        let result = Torrent::from_bytes(data).unwrap();
+
+        let mut extra = DictMap::new();
+        extra.insert(ByteString::from_str("comment"), Bencode::ByteString(b"hello".to_vec()));
+        extra.insert(ByteString::from_str("url-list"), Bencode::List(vec![Bencode::ByteString(b"http".to_vec())]));
+        let mut info_extra = DictMap::new();
+        info_extra.insert(ByteString::from_str("source"), Bencode::ByteString(b"foo".to_vec()));
+
+        assert_eq!(extra, result.extra);
+        assert_eq!(info_extra, result.info.extra);
+    }
+
+    #[test]
+    fn test_encode_round_trips_byte_for_byte() {
+        let data = b"d8:announce3:yes7:comment5:hello10:created by5:derek13:creation datei170e8:encoding5:UTF-84:infod5:filesld6:lengthi3e6:md5sum2:ab4:pathl1:aeee4:name3:dir12:piece lengthi100e6:pieces20:z\xc3\x28zzzzzzzzzzzzzzzzz7:privatei0e6:source3:fooe8:url-listl4:httpee";
+        let result = Torrent::from_bytes(data).unwrap();
+
+        assert_eq!(data.to_vec(), result.encode());
+        assert_eq!(Ok(result.clone()), Torrent::from_bytes(&result.encode()));
+        assert_eq!(Some(0), result.info.private);
+    }
+
+    #[test]
+    fn test_announce_list() {
+        let data = b"d8:announce3:yes13:announce-listll1:a1:bel1:cee4:infod6:lengthi4e4:name5:derek12:piece lengthi100e6:pieces20:z\xc3\x28zzzzzzzzzzzzzzzzzee";
+        let result = Torrent::from_bytes(data).unwrap();
+        let tiers = vec![
+            vec!["a".to_string(), "b".to_string()],
+            vec!["c".to_string()],
+        ];
+
+        assert_eq!(Some(tiers.clone()), result.announce_list);
+        assert_eq!(tiers, result.trackers());
+        assert_eq!(data.to_vec(), result.encode());
+    }
+
+    #[test]
+    fn test_announce_is_optional_with_an_announce_list() {
+        let data = b"d13:announce-listll1:aee4:infod6:lengthi4e4:name5:derek12:piece lengthi100e6:pieces20:z\xc3\x28zzzzzzzzzzzzzzzzzee";
+        let result = Torrent::from_bytes(data).unwrap();
+
+        assert_eq!(None, result.announce);
+        assert_eq!(vec![vec!["a".to_string()]], result.trackers());
+        assert_eq!(data.to_vec(), result.encode());
+
+        let result = torrent(b"d13:announce-listle4:infod6:lengthi4e4:name5:derek12:piece lengthi100e6:pieces20:zzzzzzzzzzzzzzzzzzzzee");
+        assert_result_matches_error("\"announce\" key is not present in torrent file.".to_string(), result);
+    }
+
+    #[test]
+    fn test_trackers_falls_back_to_announce() {
+        let result = torrent(b"d8:announce3:yes4:infod6:lengthi4e4:name5:derek12:piece lengthi100e6:pieces20:z\xc3\x28zzzzzzzzzzzzzzzzzee").unwrap();
+
+        assert_eq!(vec![vec!["yes".to_string()]], result.trackers());
+    }
+
+    #[test]
+    fn test_err_when_announce_list_tier_is_not_a_list() {
+        let result = torrent(b"d8:announce3:yes13:announce-listl1:aee");
+        assert_result_matches_error("\"announce-list\" value is not a list of Lists.".to_string(), result);
     }
 }
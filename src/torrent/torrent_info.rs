@@ -1,58 +1,86 @@
-use std::fmt;
+use std::{cmp, fmt};
 use std::result::Result;
-use sha1::Digest;
-use percent_encoding;
 
-use crate::bencoding;
 use crate::bencoding::byte_string::ByteString;
-use crate::bencoding::bencode::Bencode;
+use crate::bencoding::bencode::{Bencode, DictMap};
 
 use crate::torrent::error::Error;
+use crate::torrent::file_layout::{self, Layout, TorrentFile};
 
 #[derive(Eq, PartialEq, Clone, Debug)]
 pub struct TorrentInfo {
-    pub length: i64,
+    pub layout: Layout,
     pub name: String,
     pub piece_length: i64,
-    pub private: bool,
+    /// Kept as stored, only 1 means private but any other value has to
+    /// survive re-encoding for the info hash to stay the same.
+    pub private: Option<i64>,
     pub pieces: Vec<u8>,
+    /// `info` keys we don't model (`source`, `md5sum`, ...), these are part of
+    /// the info hash so they must survive re-encoding.
+    pub extra: DictMap,
 }
 
+const KNOWN_KEYS: [&str; 6] = ["files", "length", "name", "piece length", "pieces", "private"];
+
 impl TorrentInfo {
     pub fn from(input: Bencode) -> Result<Self, Error> {
-        let length = input.get_number("length")?;
+        let layout = Layout::from(&input)?;
         let name = input.get_string("name")?;
+        file_layout::check_path_component(&name)?;
         let piece_length = input.get_number("piece length")?;
-        let private_num = input.get_number("private")?;
-        let private = private_num == 1;
+        let private = input.get_optional_number("private")?;
+        let extra = input.unknown_entries(&KNOWN_KEYS);
         let pieces = input.remove_bytestring("pieces")?;
+        check_pieces(layout.length(), piece_length, &pieces)?;
 
         Ok(
             Self {
-                length,
+                layout,
                 name,
                 piece_length,
                 private,
                 pieces,
+                extra,
             }
         )
     }
 
-    pub fn sha1(&self) -> String {
-        let mut hasher = sha1::Sha1::new();
-        let encoded_info = bencoding::encoder::encode(self.torrent_info());
-        hasher.input(encoded_info);
-        let sha1 = hasher.result();
+    /// The total length of the torrent data, summed over all files.
+    pub fn length(&self) -> i64 {
+        self.layout.length()
+    }
 
-        percent_encoding::percent_encode(sha1.as_slice(), percent_encoding::NON_ALPHANUMERIC).to_string()
+    pub fn files(&self) -> Vec<TorrentFile> {
+        self.layout.files(&self.name)
     }
 
-    fn torrent_info(&self) -> Bencode {
-        let mut dict = bencoding::bencode::DictMap::new();
-        dict.insert(
-            ByteString::from_str("length"),
-            Bencode::Number(self.length),
-        );
+    /// The number of pieces, one per 20 byte SHA-1 in `pieces`.
+    pub fn piece_count(&self) -> u32 {
+        (self.pieces.len() / 20) as u32
+    }
+
+    /// The length of piece `index`, every piece is `piece_length` long except
+    /// the last which holds whatever is left. Parsing checks that
+    /// `piece_length` fits in a u32.
+    pub fn piece_len(&self, index: u32) -> u32 {
+        let start = i64::from(index) * self.piece_length;
+        cmp::max(cmp::min(self.piece_length, self.length() - start), 0) as u32
+    }
+
+    pub fn piece_hash(&self, index: u32) -> &[u8] {
+        let start = index as usize * 20;
+        &self.pieces[start..start + 20]
+    }
+
+    /// The SHA-1 of every piece, in piece order.
+    pub fn piece_hashes(&self) -> impl Iterator<Item = &[u8]> {
+        self.pieces.chunks(20)
+    }
+
+    pub fn to_bencode(&self) -> Bencode {
+        let mut dict = self.extra.clone();
+        self.layout.insert_into(&mut dict);
         dict.insert(
             ByteString::from_str("name"),
             Bencode::ByteString(self.name.as_bytes().to_vec()),
@@ -62,11 +90,12 @@ impl TorrentInfo {
             Bencode::Number(self.piece_length),
         );
 
-        let private = if self.private { 1 } else { 0 };
-        dict.insert(
-            ByteString::from_str("private"),
-            Bencode::Number(private),
-        );
+        if let Some(private) = self.private {
+            dict.insert(
+                ByteString::from_str("private"),
+                Bencode::Number(private),
+            );
+        }
 
         let mut v = vec![0; self.pieces.len()];
         v.copy_from_slice(&self.pieces);
@@ -76,10 +105,37 @@ impl TorrentInfo {
             Bencode::ByteString(v),
         );
 
-        return Bencode::Dict(dict)
+        Bencode::Dict(dict)
     }
 }
 
+/// `pieces` has to hold one 20 byte SHA-1 for every `piece_length` bytes of
+/// data, with the last piece possibly short.
+fn check_pieces(length: i64, piece_length: i64, pieces: &[u8]) -> Result<(), Error> {
+    if piece_length <= 0 {
+        return Err(Error::new(format!("\"piece length\" {} is not positive.", piece_length)));
+    }
+    // Pieces and the blocks within them are addressed with 32 bit offsets.
+    if piece_length > i64::from(u32::MAX) {
+        return Err(Error::new(format!("\"piece length\" {} does not fit in 32 bits.", piece_length)));
+    }
+    if length < 0 {
+        return Err(Error::new(format!("Length {} is negative.", length)));
+    }
+    if !pieces.len().is_multiple_of(20) {
+        return Err(Error::new(format!("\"pieces\" length {} is not a multiple of 20.", pieces.len())));
+    }
+
+    let expected = length / piece_length + i64::from(length % piece_length != 0);
+    if pieces.len() as i64 / 20 != expected {
+        return Err(Error::new(format!(
+            "\"pieces\" holds {} hashes, expected {} for {} bytes in pieces of {}.",
+            pieces.len() / 20, expected, length, piece_length,
+        )));
+    }
+    Ok(())
+}
+
 impl fmt::Display for TorrentInfo {
     fn fmt(&self, fmt: &mut fmt::Formatter) -> fmt::Result {
         format(fmt, self)
@@ -89,9 +145,10 @@ impl fmt::Display for TorrentInfo {
 fn format(fmt: &mut fmt::Formatter, v: &TorrentInfo) -> fmt::Result {
     write!(fmt, "TorrentInfo: {{ ")?;
     write!(fmt, "name: \"{}\", ", v.name)?;
-    write!(fmt, "length: {}, ", v.length)?;
+    write!(fmt, "length: {}, ", v.length())?;
+    write!(fmt, "files: {}, ", v.files().len())?;
     write!(fmt, "piece_length: {}, ", v.piece_length)?;
-    write!(fmt, "private: {}, ", v.private)?;
+    write!(fmt, "private: {:?}, ", v.private)?;
     write!(fmt, "pieces: {:?} ", v.pieces)?;
     write!(fmt, "}}")
 }
@@ -99,14 +156,22 @@ fn format(fmt: &mut fmt::Formatter, v: &TorrentInfo) -> fmt::Result {
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::bencoding;
     use crate::bencoding::decoder::decode;
 
     fn torrent_info(data: &[u8]) -> Result<TorrentInfo, Error> {
         TorrentInfo::from(
-            decode(data.to_vec())
+            decode(data.to_vec()).unwrap()
         )
     }
 
+    /// A single piece hash, the first bytes are not valid UTF-8.
+    fn pieces() -> Vec<u8> {
+        let mut pieces = vec![b'z', 195, 40];
+        pieces.extend_from_slice(&[b'z'; 17]);
+        pieces
+    }
+
     fn assert_result_matches_error(msg: String, result: Result<TorrentInfo, Error>) {
         let actual = match result {
             Ok(_) => panic!("Unexpected Ok value."),
@@ -142,7 +207,7 @@ mod tests {
     #[test]
     fn test_err_when_piece_length_is_present() {
         let result = torrent_info(b"d6:lengthi4e4:name5:derek12:piece lengthi100ee");
-        assert_result_matches_error("\"private\" key is not present in torrent file.".to_string(), result);
+        assert_result_matches_error("\"pieces\" key is not present in torrent file.".to_string(), result);
     }
 
     #[test]
@@ -153,37 +218,114 @@ mod tests {
 
     #[test]
     fn test_ok_when_all_values_are_present() {
-        let data = b"d6:lengthi4e4:name5:derek12:piece lengthi100e7:privatei1e6:pieces3:z\xc3\x28e";
+        let data = b"d6:lengthi4e4:name5:derek12:piece lengthi100e7:privatei1e6:pieces20:z\xc3\x28zzzzzzzzzzzzzzzzze";
         let result = torrent_info(data);
 
         let expected = TorrentInfo {
-            length: 4,
+            layout: Layout::SingleFile { length: 4 },
             name: "derek".to_string(),
             piece_length: 100,
-            private: true,
-            pieces: vec![b'z', 195, 40],
+            private: Some(1),
+            pieces: pieces(),
+            extra: DictMap::new(),
         };
 
         assert_eq!(Ok(expected), result);
     }
 
     #[test]
-    fn test_sha_when_all_values_are_present() {
-        let data = b"d6:lengthi4e4:name5:derek12:piece lengthi100e6:pieces3:z\xc3\x287:privatei1ee";
-        let mut hasher = sha1::Sha1::new();
-        hasher.input(data.to_vec());
-        let sha1 = hasher.result();
-        let expected_str = percent_encoding::percent_encode(sha1.as_slice(), percent_encoding::NON_ALPHANUMERIC).to_string();
-
+    fn test_to_bencode_when_all_values_are_present() {
         let torrent = TorrentInfo {
-            length: 4,
+            layout: Layout::SingleFile { length: 4 },
             name: "derek".to_string(),
             piece_length: 100,
-            private: true,
-            pieces: vec![b'z', 195, 40],
+            private: Some(1),
+            pieces: pieces(),
+            extra: DictMap::new(),
+        };
+
+        assert_eq!(
+            b"d6:lengthi4e4:name5:derek12:piece lengthi100e6:pieces20:z\xc3\x28zzzzzzzzzzzzzzzzz7:privatei1ee".to_vec(),
+            bencoding::encoder::encode(torrent.to_bencode()),
+        );
+    }
+
+    #[test]
+    fn test_private_values_other_than_0_and_1_round_trip() {
+        let data = b"d6:lengthi4e4:name5:derek12:piece lengthi100e6:pieces20:zzzzzzzzzzzzzzzzzzzz7:privatei2ee";
+        let result = torrent_info(data).unwrap();
+
+        assert_eq!(Some(2), result.private);
+        assert_eq!(data.to_vec(), bencoding::encoder::encode(result.to_bencode()));
+    }
+
+    #[test]
+    fn test_ok_for_a_multi_file_torrent() {
+        let data = b"d5:filesld6:lengthi3e4:pathl1:aeed6:lengthi4e4:pathl1:b1:ceee4:name3:dir12:piece lengthi4e6:pieces40:z\xc3\x28zzzzzzzzzzzzzzzzzzzzzzzzzzzzzzzzzzzzz7:privatei0ee";
+        let result = torrent_info(data).unwrap();
+
+        assert_eq!(7, result.length());
+        assert_eq!(
+            vec![
+                TorrentFile { path: "dir/a".into(), length: 3, offset: 0 },
+                TorrentFile { path: "dir/b/c".into(), length: 4, offset: 3 },
+            ],
+            result.files(),
+        );
+        assert_eq!(data.to_vec(), bencoding::encoder::encode(result.to_bencode()));
+    }
+
+    #[test]
+    fn test_err_when_name_escapes_the_directory() {
+        let result = torrent_info(b"d6:lengthi4e4:name2:..12:piece lengthi100e7:privatei1e6:pieces0:e");
+        assert_result_matches_error("Invalid path component \"..\".".to_string(), result);
+    }
+
+    #[test]
+    fn test_err_when_pieces_is_not_whole_hashes() {
+        let result = torrent_info(b"d6:lengthi4e4:name5:derek12:piece lengthi100e6:pieces3:z\xc3\x28e");
+        assert_result_matches_error("\"pieces\" length 3 is not a multiple of 20.".to_string(), result);
+    }
+
+    #[test]
+    fn test_err_when_pieces_does_not_match_the_length() {
+        let result = torrent_info(b"d6:lengthi201e4:name5:derek12:piece lengthi100e6:pieces20:zzzzzzzzzzzzzzzzzzzze");
+        assert_result_matches_error("\"pieces\" holds 1 hashes, expected 3 for 201 bytes in pieces of 100.".to_string(), result);
+
+        let result = torrent_info(b"d6:lengthi4e4:name5:derek12:piece lengthi0e6:pieces0:e");
+        assert_result_matches_error("\"piece length\" 0 is not positive.".to_string(), result);
+    }
+
+    #[test]
+    fn test_err_on_huge_lengths() {
+        let result = torrent_info(b"d6:lengthi9223372036854775807e4:name5:derek12:piece lengthi100e6:pieces20:zzzzzzzzzzzzzzzzzzzze");
+        assert_result_matches_error(
+            "\"pieces\" holds 1 hashes, expected 92233720368547759 for 9223372036854775807 bytes in pieces of 100.".to_string(),
+            result,
+        );
+
+        let result = torrent_info(b"d6:lengthi4e4:name5:derek12:piece lengthi4294967296e6:pieces20:zzzzzzzzzzzzzzzzzzzze");
+        assert_result_matches_error("\"piece length\" 4294967296 does not fit in 32 bits.".to_string(), result);
+    }
+
+    #[test]
+    fn test_piece_geometry() {
+        let mut pieces = vec![1; 20];
+        pieces.extend_from_slice(&[2; 20]);
+        pieces.extend_from_slice(&[3; 20]);
+        let info = TorrentInfo {
+            layout: Layout::SingleFile { length: 40000 },
+            name: "derek".to_string(),
+            piece_length: 16384,
+            private: None,
+            pieces,
+            extra: DictMap::new(),
         };
 
-        assert_eq!("%3AJ%9A%B3%D7%3E%D0t%BDD%DDz%A5%EE%9D%DE%8C%AD%28%AE", expected_str);
-        assert_eq!(expected_str, torrent.sha1());
+        assert_eq!(3, info.piece_count());
+        assert_eq!(16384, info.piece_len(0));
+        assert_eq!(40000 - 2 * 16384, info.piece_len(2));
+        assert_eq!(&[3; 20], info.piece_hash(2));
+        assert_eq!(vec![&[1; 20][..], &[2; 20], &[3; 20]], info.piece_hashes().collect::<Vec<_>>());
     }
 }
@@ -1,32 +1,46 @@
 use std::fmt;
 use std::result::Result;
-use std::net::Ipv4Addr;
-use byteorder::{ByteOrder, BigEndian};
 
 use crate::bencoding::bencode::Bencode;
 use crate::torrent::error::Error;
 use crate::torrent::peer::Peer;
 
+/// A successful announce response. Only `interval` and `peers` are required,
+/// trackers commonly leave out the counters. `peers` may be sent compact or as
+/// a list of dicts, both are parsed into `Peer`s. IPv6 peers from `peers6`
+/// (BEP 7) are appended after them.
 #[derive(Eq, PartialEq, Clone, Debug)]
 pub struct TrackerInfo {
-    pub complete: i64,
-    pub downloaded: i64,
-    pub incomplete: i64,
+    pub complete: Option<i64>,
+    pub downloaded: Option<i64>,
+    pub incomplete: Option<i64>,
     pub interval: i64,
-    pub min_interval: i64,
-    pub peers: Vec<u8>,
+    pub min_interval: Option<i64>,
+    pub warning_message: Option<String>,
+    pub tracker_id: Option<String>,
+    pub peers: Vec<Peer>,
 }
 
-
-
 impl TrackerInfo {
     pub fn from(input: Bencode) -> Result<Self, Error> {
-        let complete = input.get_number("complete")?;
-        let downloaded = input.get_number("downloaded")?;
-        let incomplete = input.get_number("incomplete")?;
+        let complete = input.get_optional_number("complete")?;
+        let downloaded = input.get_optional_number("downloaded")?;
+        let incomplete = input.get_optional_number("incomplete")?;
         let interval = input.get_number("interval")?;
-        let min_interval = input.get_number("min interval")?;
-        let peers = input.remove_bytestring("peers")?;
+        let min_interval = input.get_optional_number("min interval")?;
+        let warning_message = input.get_optional_string("warning message")?;
+        let tracker_id = input.get_optional_string("tracker id")?;
+        let peers6 = if input.contains_key("peers6") {
+            parse_compact(input.get_bytes("peers6")?, 18, "peers6", Peer::from_compact_v6)?
+        } else {
+            Vec::new()
+        };
+        let mut peers = if input.contains_key("peers") || peers6.is_empty() {
+            parse_peers(input.remove("peers")?)?
+        } else {
+            Vec::new()
+        };
+        peers.extend(peers6);
 
         Ok(
             Self {
@@ -35,22 +49,36 @@ impl TrackerInfo {
                 incomplete,
                 interval,
                 min_interval,
+                warning_message,
+                tracker_id,
                 peers,
             }
         )
     }
 
-    pub fn peer_addrs(&self) -> Vec<Peer> {
-        let mut ip_addrs = Vec::<Peer>::new();
+}
 
-        for peer in self.peers.chunks(6) {
-            let ip = Ipv4Addr::from(BigEndian::read_u32(&peer[..4]));
-            let port = BigEndian::read_u16(&peer[4..]);
-            ip_addrs.push(Peer { ip, port })
-        }
+fn parse_peers(input: Bencode) -> Result<Vec<Peer>, Error> {
+    match input {
+        Bencode::ByteString(bytes) => parse_compact(&bytes, 6, "peers", Peer::from_compact_v4),
+        Bencode::List(entries) => {
+            let mut peers = Vec::new();
+            for entry in entries {
+                if let Some(peer) = Peer::from_dict(entry)? {
+                    peers.push(peer);
+                }
+            }
+            Ok(peers)
+        },
+        _ => Err(Error::new("\"peers\" value is not a ByteString or List.".to_string())),
+    }
+}
 
-        return ip_addrs;
+fn parse_compact(bytes: &[u8], size: usize, key: &str, parse: fn(&[u8]) -> Peer) -> Result<Vec<Peer>, Error> {
+    if !bytes.len().is_multiple_of(size) {
+        return Err(Error::new(format!("\"{}\" length {} is not a multiple of {}.", key, bytes.len(), size)));
     }
+    Ok(bytes.chunks(size).map(parse).collect())
 }
 
 impl fmt::Display for TrackerInfo {
@@ -61,15 +89,16 @@ impl fmt::Display for TrackerInfo {
 
 fn format(fmt: &mut fmt::Formatter, v: &TrackerInfo) -> fmt::Result {
     write!(fmt, "TrackerInfo: {{ ")?;
-    write!(fmt, "complete: {}, ", v.complete)?;
-    write!(fmt, "downloaded: {}, ", v.downloaded)?;
-    write!(fmt, "incomplete: {}, ", v.incomplete)?;
+    write!(fmt, "complete: {:?}, ", v.complete)?;
+    write!(fmt, "downloaded: {:?}, ", v.downloaded)?;
+    write!(fmt, "incomplete: {:?}, ", v.incomplete)?;
     write!(fmt, "interval: {}, ", v.interval)?;
-    write!(fmt, "min_interval: {}, ", v.min_interval)?;
+    write!(fmt, "min_interval: {:?}, ", v.min_interval)?;
+    write!(fmt, "warning_message: {:?}, ", v.warning_message)?;
+    write!(fmt, "tracker_id: {:?}, ", v.tracker_id)?;
     write!(fmt, "peers: [")?;
-    let addrs = v.peer_addrs();
-    let len = addrs.len();
-    for (i, peer) in addrs.iter().enumerate() {
+    let len = v.peers.len();
+    for (i, peer) in v.peers.iter().enumerate() {
         write!(fmt, "{}", peer)?;
         if len != i + 1 {
             write!(fmt, ", ")?;
@@ -85,7 +114,7 @@ mod tests {
 
     fn tracker_info(data: &[u8]) -> Result<TrackerInfo, Error> {
         TrackerInfo::from(
-            decode(data.to_vec())
+            decode(data.to_vec()).unwrap()
         )
     }
 
@@ -106,68 +135,96 @@ mod tests {
     #[test]
     fn test_err_when_input_is_an_empty_dictionary() {
         let result = tracker_info(b"de");
-        assert_result_matches_error("\"complete\" key is not present in torrent file.".to_string(), result);
+        assert_result_matches_error("\"interval\" key is not present in torrent file.".to_string(), result);
     }
 
     #[test]
-    fn test_err_when_complete_is_present() {
-        let result = tracker_info(b"d8:completei4ee");
-        assert_result_matches_error("\"downloaded\" key is not present in torrent file.".to_string(), result);
+    fn test_err_when_peers_is_missing() {
+        let result = tracker_info(b"d8:intervali1906ee");
+        assert_result_matches_error("\"peers\" key is not present in torrent file.".to_string(), result);
     }
 
     #[test]
-    fn test_err_when_downloaded_is_present() {
-        let result = tracker_info(b"d8:completei4e10:downloadedi6ee");
-        assert_result_matches_error("\"incomplete\" key is not present in torrent file.".to_string(), result);
+    fn test_ok_when_only_required_values_are_present() {
+        let result = tracker_info(b"d8:intervali1906e5:peers0:e");
+        let expected = TrackerInfo {
+            complete: None,
+            downloaded: None,
+            incomplete: None,
+            interval: 1906,
+            min_interval: None,
+            warning_message: None,
+            tracker_id: None,
+            peers: Vec::new(),
+        };
+
+        assert_eq!(Ok(expected), result);
     }
 
     #[test]
-    fn test_err_when_incomplete_is_present() {
-        let result = tracker_info(b"d8:completei4e10:downloadedi6e10:incompletei1ee");
-        assert_result_matches_error("\"interval\" key is not present in torrent file.".to_string(), result);
+    fn test_ok_when_all_values_are_present() {
+        let data = b"d8:completei4e10:downloadedi6e10:incompletei1e8:intervali1906e12:min intervali953e5:peers6:Oz\x00x\x1AA10:tracker id3:abc15:warning message4:slowe";
+        let result = tracker_info(data);
+        let expected = TrackerInfo {
+            complete: Some(4),
+            downloaded: Some(6),
+            incomplete: Some(1),
+            interval: 1906,
+            min_interval: Some(953),
+            warning_message: Some("slow".to_string()),
+            tracker_id: Some("abc".to_string()),
+            peers: vec![Peer::new("79.122.0.120:6721".parse().unwrap())],
+        };
+
+        assert_eq!(Ok(expected), result);
     }
 
     #[test]
-    fn test_err_when_interval_is_present() {
-        let result = tracker_info(b"d8:completei4e10:downloadedi6e10:incompletei1e8:intervali1906ee");
-        assert_result_matches_error("\"min interval\" key is not present in torrent file.".to_string(), result);
+    fn test_ok_with_dictionary_peers() {
+        let data = b"d8:intervali1906e5:peersld2:ip8:10.0.0.17:peer id20:-RS0001-abcdefghijkl4:porti6881eed2:ip9:127.0.0.14:porti51413eeee";
+        let expected = vec![
+            Peer { addr: "10.0.0.1:6881".parse().unwrap(), peer_id: Some(*b"-RS0001-abcdefghijkl") },
+            Peer::new("127.0.0.1:51413".parse().unwrap()),
+        ];
+
+        assert_eq!(expected, tracker_info(data).unwrap().peers);
     }
 
     #[test]
-    fn test_err_when_min_interval_is_present() {
-        let result = tracker_info(b"d8:completei4e10:downloadedi6e10:incompletei1e8:intervali1906e12:min intervali953ee");
-        assert_result_matches_error("\"peers\" key is not present in torrent file.".to_string(), result);
+    fn test_ok_with_peers6() {
+        let data = b"d8:intervali1906e5:peers6:Oz\x00x\x1AA6:peers618:\x20\x01\x0d\xb8\x00\x00\x00\x00\x00\x00\x00\x00\x00\x00\x00\x01\x1A\xE1e";
+        let expected = vec![
+            Peer::new("79.122.0.120:6721".parse().unwrap()),
+            Peer::new("[2001:db8::1]:6881".parse().unwrap()),
+        ];
+
+        assert_eq!(expected, tracker_info(data).unwrap().peers);
     }
 
     #[test]
-    fn test_ok_when_all_values_are_present() {
-        let data = b"d8:completei4e10:downloadedi6e10:incompletei1e8:intervali1906e12:min intervali953e5:peers6:Oz\x00x\x1AAe";
-        let result = tracker_info(data);
-        let expected = TrackerInfo {
-            complete: 4,
-            downloaded: 6,
-            incomplete: 1,
-            interval: 1906,
-            min_interval: 953,
-            peers: vec![79, 122, 0, 120, 26, 65],
-        };
-
-        assert_eq!(Ok(expected), result);
+    fn test_ok_with_only_peers6() {
+        let data = b"d8:intervali1906e6:peers618:\x20\x01\x0d\xb8\x00\x00\x00\x00\x00\x00\x00\x00\x00\x00\x00\x01\x1A\xE1e";
+        assert_eq!(vec![Peer::new("[2001:db8::1]:6881".parse().unwrap())], tracker_info(data).unwrap().peers);
     }
 
     #[test]
-    fn test_peer_addrs() {
-        let tracker_info = TrackerInfo {
-            complete: 4,
-            downloaded: 6,
-            incomplete: 1,
-            interval: 1906,
-            min_interval: 953,
-            peers: vec![79, 122, 0, 120, 26, 65],
-        };
+    fn test_err_when_peers6_is_truncated() {
+        let result = tracker_info(b"d8:intervali1906e5:peers0:6:peers64:\x20\x01\x0d\xb8e");
+        assert_result_matches_error("\"peers6\" length 4 is not a multiple of 18.".to_string(), result);
+    }
 
-        let expected = vec![Peer { ip: Ipv4Addr::new(79, 122, 0, 120), port: 6721 }];
+    #[test]
+    fn test_err_when_compact_peers_are_truncated() {
+        let result = tracker_info(b"d8:intervali1906e5:peers4:Oz\x00xe");
+        assert_result_matches_error("\"peers\" length 4 is not a multiple of 6.".to_string(), result);
+    }
 
-        assert_eq!(expected, tracker_info.peer_addrs());
+    #[test]
+    fn test_display() {
+        let result = tracker_info(b"d8:intervali1906e5:peers12:Oz\x00x\x1AA\x0A\x00\x00\x01\x1A\xE1e").unwrap();
+        assert_eq!(
+            "TrackerInfo: { complete: None, downloaded: None, incomplete: None, interval: 1906, min_interval: None, warning_message: None, tracker_id: None, peers: [79.122.0.120:6721, 10.0.0.1:6881] }",
+            format!("{}", result),
+        );
     }
 }
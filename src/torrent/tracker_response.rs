@@ -0,0 +1,71 @@
+use std::fmt;
+use std::result::Result;
+
+use crate::bencoding::bencode::Bencode;
+use crate::torrent::error::Error;
+use crate::torrent::tracker_info::TrackerInfo;
+
+/// The body of an announce response, a tracker that rejects the request only
+/// sends a `failure reason`.
+#[derive(Eq, PartialEq, Clone, Debug)]
+pub enum TrackerResponse {
+    Success(TrackerInfo),
+    Failure { reason: String },
+}
+
+impl TrackerResponse {
+    pub fn from(input: Bencode) -> Result<Self, Error> {
+        if let Some(reason) = input.get_optional_string("failure reason")? {
+            return Ok(TrackerResponse::Failure { reason });
+        }
+
+        Ok(TrackerResponse::Success(TrackerInfo::from(input)?))
+    }
+}
+
+impl fmt::Display for TrackerResponse {
+    fn fmt(&self, fmt: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            TrackerResponse::Success(info) => write!(fmt, "{}", info),
+            TrackerResponse::Failure { reason } => write!(fmt, "TrackerResponse: {{ failure_reason: \"{}\" }}", reason),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::bencoding::decoder::decode;
+
+    fn tracker_response(data: &[u8]) -> Result<TrackerResponse, Error> {
+        TrackerResponse::from(decode(data.to_vec()).unwrap())
+    }
+
+    #[test]
+    fn test_failure() {
+        let result = tracker_response(b"d14:failure reason17:torrent not founde");
+
+        assert_eq!(Ok(TrackerResponse::Failure { reason: "torrent not found".to_string() }), result);
+    }
+
+    #[test]
+    fn test_success() {
+        let result = tracker_response(b"d8:intervali1800e5:peers0:15:warning message4:slowe").unwrap();
+
+        match result {
+            TrackerResponse::Success(info) => {
+                assert_eq!(1800, info.interval);
+                assert_eq!(Some("slow".to_string()), info.warning_message);
+            },
+            other => panic!("Unexpected response {}", other),
+        }
+    }
+
+    #[test]
+    fn test_err_when_success_is_missing_interval() {
+        assert_eq!(
+            Err(Error::new("\"interval\" key is not present in torrent file.".to_string())),
+            tracker_response(b"d5:peers0:e"),
+        );
+    }
+}